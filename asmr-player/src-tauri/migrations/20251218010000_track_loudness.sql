@@ -0,0 +1,3 @@
+-- Loudness measured by the scanner (or taken from ReplayGain tags) for normalization
+ALTER TABLE tracks ADD COLUMN loudness_lufs REAL;
+ALTER TABLE tracks ADD COLUMN true_peak REAL; -- linear, 1.0 = full scale
@@ -0,0 +1,12 @@
+-- Named positions and loop regions within a track
+CREATE TABLE IF NOT EXISTS bookmarks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    track_id INTEGER NOT NULL,
+    start_sec REAL NOT NULL,
+    end_sec REAL, -- NULL for a single position; looping it runs to the end of the track
+    label TEXT NOT NULL DEFAULT '',
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_bookmarks_track ON bookmarks(track_id, start_sec);
@@ -0,0 +1,3 @@
+-- Silence at either end of a track, measured by the scanner, in seconds
+ALTER TABLE tracks ADD COLUMN leading_silence_sec REAL;
+ALTER TABLE tracks ADD COLUMN trailing_silence_sec REAL;
@@ -0,0 +1,6 @@
+-- Peak/RMS overviews for the seek bar, keyed by file so they survive rescans
+CREATE TABLE IF NOT EXISTS track_waveforms (
+    path TEXT PRIMARY KEY,
+    mtime INTEGER NOT NULL, -- of the file the overview was made from
+    data BLOB NOT NULL -- u16 LE peak/RMS pairs
+);
@@ -0,0 +1,6 @@
+-- What each track file looked like when it was last probed, so rescans can skip it
+ALTER TABLE tracks ADD COLUMN file_size INTEGER;
+ALTER TABLE tracks ADD COLUMN file_mtime INTEGER;
+ALTER TABLE tracks ADD COLUMN content_hash TEXT; -- SHA-256, only when the scan was asked to hash
+
+CREATE INDEX IF NOT EXISTS idx_tracks_work_path ON tracks(work_id, path);
@@ -0,0 +1,2 @@
+-- Set by the library watcher when a work's folder disappears, cleared when it comes back
+ALTER TABLE works ADD COLUMN missing BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,31 @@
+-- Folders that make up the library
+CREATE TABLE IF NOT EXISTS library_roots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE,
+    enabled BOOLEAN NOT NULL DEFAULT 1, -- Disabled roots are neither scanned nor watched
+    last_scanned_at DATETIME,
+    work_count INTEGER NOT NULL DEFAULT 0,
+    error_count INTEGER NOT NULL DEFAULT 0, -- Problems during the last scan
+    last_error TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE works ADD COLUMN root_id INTEGER REFERENCES library_roots(id);
+CREATE INDEX IF NOT EXISTS idx_works_root ON works(root_id);
+
+-- Folders remembered by earlier scans
+INSERT OR IGNORE INTO library_roots (path)
+SELECT value FROM json_each((SELECT value FROM app_settings WHERE key = 'library.roots'));
+DELETE FROM app_settings WHERE key = 'library.roots';
+
+-- Each work goes under the innermost root holding its folder. A root holds its own
+-- path and what is below it after a separator, so /lib doesn't take /library.
+UPDATE works SET root_id = (
+    SELECT r.id FROM library_roots r
+    WHERE works.dir_path = r.path
+       OR substr(works.dir_path, 1, length(rtrim(r.path, '/\')) + 1)
+          IN (rtrim(r.path, '/\') || '/', rtrim(r.path, '/\') || '\')
+    ORDER BY length(r.path) DESC
+    LIMIT 1
+);
+UPDATE library_roots SET work_count = (SELECT COUNT(*) FROM works WHERE root_id = library_roots.id);
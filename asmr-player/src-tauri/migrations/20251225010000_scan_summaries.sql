@@ -0,0 +1,13 @@
+-- How each library scan went, for viewing after it's done
+CREATE TABLE IF NOT EXISTS scan_summaries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    status TEXT NOT NULL, -- finished, cancelled or failed
+    roots TEXT NOT NULL DEFAULT '[]', -- JSON array of the folders scanned
+    started_at DATETIME NOT NULL,
+    duration_secs REAL NOT NULL DEFAULT 0,
+    works_found INTEGER NOT NULL DEFAULT 0,
+    tracks_found INTEGER NOT NULL DEFAULT 0,
+    files_failed INTEGER NOT NULL DEFAULT 0,
+    error_count INTEGER NOT NULL DEFAULT 0,
+    errors TEXT NOT NULL DEFAULT '[]' -- JSON array of the first errors
+);
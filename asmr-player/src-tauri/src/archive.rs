@@ -0,0 +1,184 @@
+mod zip;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Separates an archive from the entry inside it in a track path, as in
+/// `/library/RJ01234567.zip!/mp3/01.mp3`.
+pub const ENTRY_SEPARATOR: &str = "!/";
+
+/// Extensions of the archives works are shipped in, whether or not they can be read yet.
+const ARCHIVE_EXTENSIONS: [&str; 3] = ["zip", "rar", "7z"];
+
+/// Archive formats that can be read. Another format plugs in by implementing
+/// `Extractor` and being added here.
+static EXTRACTORS: &[&dyn Extractor] = &[&zip::Zip];
+
+/// A file inside an archive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, `/`-separated and decoded to UTF-8.
+    pub name: String,
+    /// Uncompressed size.
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, as the archive records it.
+    pub mtime: i64,
+    pub crc32: Option<u32>,
+}
+
+/// Reads one archive format.
+pub trait Extractor: Sync {
+    /// Lower-case extensions of the archives this reads.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// The files in `archive`, leaving out folders and entries that can't be read.
+    fn list(&self, archive: &Path) -> io::Result<Vec<ArchiveEntry>>;
+
+    /// Stream the entry named `name`, decompressing as it is read.
+    fn open(&self, archive: &Path, name: &str) -> io::Result<EntryReader>;
+}
+
+/// Anything an entry can be read through.
+pub trait ReadSeek: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
+
+/// A seekable stream over one entry, holding only a read buffer in memory.
+pub struct EntryReader {
+    inner: Box<dyn ReadSeek>,
+    len: u64,
+}
+
+impl EntryReader {
+    pub fn new(inner: Box<dyn ReadSeek>, len: u64) -> Self {
+        Self { inner, len }
+    }
+
+    /// Uncompressed length of the entry.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Lower-case archive extension of `path`. The first volume of a split 7z archive
+/// (`.7z.001`) counts as a 7z archive.
+fn archive_extension(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    let name = name.strip_suffix(".001").unwrap_or(&name);
+    let (_, ext) = name.rsplit_once('.')?;
+    ARCHIVE_EXTENSIONS.contains(&ext).then(|| ext.to_string())
+}
+
+/// Whether `path` names an archive a work could be packed in. Later volumes of a split
+/// archive are not archives of their own; they are read through the first.
+pub fn is_archive(path: &Path) -> bool {
+    archive_extension(path).is_some() && volume_number(path).is_none_or(|n| n == 1)
+}
+
+/// Volume number of a `.partN.rar` archive.
+fn volume_number(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    let (rest, _) = name.rsplit_once(".rar")?;
+    let (_, part) = rest.rsplit_once(".part")?;
+    part.parse().ok()
+}
+
+/// The name of an archive without its extension and volume suffix, for a work title.
+pub fn stem(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let Some(ext) = archive_extension(path) else {
+        return name;
+    };
+    let lower = name.to_ascii_lowercase();
+    let mut end = lower.rfind(&format!(".{}", ext)).unwrap_or(name.len());
+    if volume_number(path).is_some() {
+        end = lower[..end].rfind(".part").unwrap_or(end);
+    }
+    name[..end].to_string()
+}
+
+/// The extractor for `path`, if its format can be read.
+pub fn extractor_for(path: &Path) -> Option<&'static dyn Extractor> {
+    let ext = archive_extension(path)?;
+    EXTRACTORS.iter().copied().find(|x| x.extensions().contains(&ext.as_str()))
+}
+
+fn unsupported(archive: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Can't read {} archives yet: {}", archive_extension(archive).unwrap_or_default(), archive.display()),
+    )
+}
+
+/// The files in `archive`.
+pub fn list(archive: &Path) -> io::Result<Vec<ArchiveEntry>> {
+    extractor_for(archive).ok_or_else(|| unsupported(archive))?.list(archive)
+}
+
+/// Path of an entry, as stored for its track.
+pub fn entry_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), ENTRY_SEPARATOR, name))
+}
+
+/// Split a track path into the archive and the entry inside it. None for plain files.
+pub fn split_entry_path(path: &str) -> Option<(&Path, &str)> {
+    path.match_indices(ENTRY_SEPARATOR).find_map(|(i, _)| {
+        let archive = Path::new(&path[..i]);
+        is_archive(archive).then(|| (archive, &path[i + ENTRY_SEPARATOR.len()..]))
+    })
+}
+
+/// Stream the entry named `name` out of `archive`.
+pub fn open(archive: &Path, name: &str) -> io::Result<EntryReader> {
+    extractor_for(archive).ok_or_else(|| unsupported(archive))?.open(archive, name)
+}
+
+/// Stream the entry a track path points at.
+pub fn open_entry(path: &str) -> io::Result<EntryReader> {
+    let (archive, name) = split_entry_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Not inside an archive: {}", path)))?;
+    open(archive, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_and_their_volumes_are_recognised() {
+        assert!(is_archive(Path::new("/lib/RJ01234567.ZIP")));
+        assert!(is_archive(Path::new("/lib/RJ01234567.part1.rar")));
+        assert!(is_archive(Path::new("/lib/RJ01234567.part01.rar")));
+        assert!(!is_archive(Path::new("/lib/RJ01234567.part2.rar")));
+        assert!(is_archive(Path::new("/lib/RJ01234567.7z.001")));
+        assert!(!is_archive(Path::new("/lib/RJ01234567.7z.002")));
+        assert!(!is_archive(Path::new("/lib/RJ01234567.mp3")));
+
+        assert_eq!(stem(Path::new("/lib/RJ01234567 作品.zip")), "RJ01234567 作品");
+        assert_eq!(stem(Path::new("/lib/RJ01234567.part01.rar")), "RJ01234567");
+        assert_eq!(stem(Path::new("/lib/RJ01234567.7z.001")), "RJ01234567");
+    }
+
+    #[test]
+    fn entry_paths_round_trip() {
+        let path = entry_path(Path::new("/lib/a!/b/RJ01234567.zip"), "mp3/01 トラック.mp3");
+        let path = path.to_string_lossy();
+        assert_eq!(
+            split_entry_path(&path),
+            Some((Path::new("/lib/a!/b/RJ01234567.zip"), "mp3/01 トラック.mp3"))
+        );
+        assert_eq!(split_entry_path("/lib/RJ01234567/01.mp3"), None);
+    }
+}
@@ -0,0 +1,555 @@
+use super::{ArchiveEntry, EntryReader, Extractor};
+use encoding_rs::SHIFT_JIS;
+use flate2::bufread::DeflateDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::path::Path;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIR: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+
+// Fixed-size parts of the records above.
+const LOCAL_HEADER_LEN: usize = 30;
+const END_OF_CENTRAL_DIR_LEN: usize = 22;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_END_OF_CENTRAL_DIR_LEN: usize = 56;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+
+const EXTRA_ZIP64: u16 = 0x0001;
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+const EXTRA_UNICODE_PATH: u16 = 0x7075;
+
+/// ZIP archives, stored or deflated, including ZIP64. Split and encrypted archives
+/// aren't read.
+pub struct Zip;
+
+impl Extractor for Zip {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["zip"]
+    }
+
+    fn list(&self, archive: &Path) -> io::Result<Vec<ArchiveEntry>> {
+        let entries = central_directory(&mut File::open(archive)?)?;
+        Ok(entries
+            .into_iter()
+            .filter(|e| !e.entry.name.ends_with('/'))
+            .filter(|e| match e.check_readable() {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("Skipping {}: {}", e.entry.name, err);
+                    false
+                }
+            })
+            .map(|e| e.entry)
+            .collect())
+    }
+
+    fn open(&self, archive: &Path, name: &str) -> io::Result<EntryReader> {
+        let mut file = File::open(archive)?;
+        let entry = central_directory(&mut file)?
+            .into_iter()
+            .find(|e| e.entry.name == name)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} is not in {}", name, archive.display()))
+            })?;
+        entry.check_readable()?;
+
+        let mut header = [0; LOCAL_HEADER_LEN];
+        file.seek(SeekFrom::Start(entry.header_offset))?;
+        file.read_exact(&mut header)?;
+        let mut fields = Fields(&header);
+        if fields.u32()? != LOCAL_HEADER {
+            return Err(invalid("Bad local file header"));
+        }
+        fields.skip(22)?;
+        let name_len = fields.u16()? as u64;
+        let extra_len = fields.u16()? as u64;
+        let start = entry.header_offset + LOCAL_HEADER_LEN as u64 + name_len + extra_len;
+
+        let len = entry.entry.size;
+        if entry.method == STORED {
+            Ok(EntryReader::new(Box::new(Stored::new(file, start, len)?), len))
+        } else {
+            Ok(EntryReader::new(Box::new(Inflated::new(file, start, entry.compressed_size, len)?), len))
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Little-endian fields read off the front of a record.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("Truncated ZIP record"));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// An entry as the central directory describes it.
+struct ZipEntry {
+    entry: ArchiveEntry,
+    flags: u16,
+    method: u16,
+    compressed_size: u64,
+    header_offset: u64,
+}
+
+impl ZipEntry {
+    fn check_readable(&self) -> io::Result<()> {
+        if self.flags & FLAG_ENCRYPTED != 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Encrypted entries can't be read"));
+        }
+        if self.method != STORED && self.method != DEFLATED {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Compression method {} isn't supported", self.method),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Every entry of the archive, folders included.
+fn central_directory(file: &mut File) -> io::Result<Vec<ZipEntry>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    // The end record sits at the very end, followed only by a comment of up to 64 KiB.
+    let tail_len = file_len.min((END_OF_CENTRAL_DIR_LEN + u16::MAX as usize) as u64);
+    let tail_start = file_len - tail_len;
+    let tail = read_at(file, tail_start, tail_len as usize)?;
+    let end = (0..=tail.len().saturating_sub(END_OF_CENTRAL_DIR_LEN))
+        .rev()
+        .find(|&i| tail[i..].starts_with(&END_OF_CENTRAL_DIR.to_le_bytes()))
+        .ok_or_else(|| invalid("Not a ZIP archive"))?;
+
+    let mut fields = Fields(&tail[end + 4..]);
+    let mut disk = fields.u16()? as u32;
+    let mut directory_disk = fields.u16()? as u32;
+    fields.skip(2)?;
+    let mut count = fields.u16()? as u64;
+    let mut directory_len = fields.u32()? as u64;
+    let mut directory_start = fields.u32()? as u64;
+
+    // Fields that don't fit are maxed out, and the real values are in the ZIP64 record.
+    let end_offset = tail_start + end as u64;
+    if end_offset >= ZIP64_LOCATOR_LEN as u64 {
+        let locator = read_at(file, end_offset - ZIP64_LOCATOR_LEN as u64, ZIP64_LOCATOR_LEN)?;
+        let mut fields = Fields(&locator);
+        if fields.u32()? == ZIP64_LOCATOR {
+            fields.skip(4)?;
+            let record_offset = fields.u64()?;
+            let record = read_at(file, record_offset, ZIP64_END_OF_CENTRAL_DIR_LEN)?;
+            let mut fields = Fields(&record);
+            if fields.u32()? != ZIP64_END_OF_CENTRAL_DIR {
+                return Err(invalid("Bad ZIP64 end of central directory"));
+            }
+            fields.skip(12)?;
+            disk = fields.u32()?;
+            directory_disk = fields.u32()?;
+            fields.skip(8)?;
+            count = fields.u64()?;
+            directory_len = fields.u64()?;
+            directory_start = fields.u64()?;
+        }
+    }
+    if disk != 0 || directory_disk != 0 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Split ZIP archives can't be read"));
+    }
+    if directory_start.saturating_add(directory_len) > file_len {
+        return Err(invalid("Central directory lies outside the archive"));
+    }
+
+    let directory = read_at(file, directory_start, directory_len as usize)?;
+    let mut fields = Fields(&directory);
+    // The count only sizes the list; a bogus one must not allocate gigabytes.
+    let mut entries = Vec::with_capacity(count.min(directory_len / 46) as usize);
+    for _ in 0..count {
+        entries.push(central_entry(&mut fields)?);
+    }
+    Ok(entries)
+}
+
+fn central_entry(fields: &mut Fields) -> io::Result<ZipEntry> {
+    if fields.u32()? != CENTRAL_HEADER {
+        return Err(invalid("Bad central directory entry"));
+    }
+    fields.skip(4)?;
+    let flags = fields.u16()?;
+    let method = fields.u16()?;
+    let time = fields.u16()?;
+    let date = fields.u16()?;
+    let crc32 = fields.u32()?;
+    let mut compressed_size = fields.u32()? as u64;
+    let mut size = fields.u32()? as u64;
+    let name_len = fields.u16()? as usize;
+    let extra_len = fields.u16()? as usize;
+    let comment_len = fields.u16()? as usize;
+    fields.skip(8)?;
+    let mut header_offset = fields.u32()? as u64;
+    let raw_name = fields.bytes(name_len)?;
+    let mut extra = Fields(fields.bytes(extra_len)?);
+    fields.skip(comment_len)?;
+
+    let mut name = None;
+    let mut mtime = dos_time(date, time);
+    while let (Ok(id), Ok(len)) = (extra.u16(), extra.u16()) {
+        let Ok(data) = extra.bytes(len as usize) else {
+            break;
+        };
+        let mut data = Fields(data);
+        match id {
+            // Only the fields maxed out in the entry itself are here, in this order.
+            EXTRA_ZIP64 => {
+                if size == u32::MAX as u64 {
+                    size = data.u64()?;
+                }
+                if compressed_size == u32::MAX as u64 {
+                    compressed_size = data.u64()?;
+                }
+                if header_offset == u32::MAX as u64 {
+                    header_offset = data.u64()?;
+                }
+            }
+            // The first flag says the modification time is there.
+            EXTRA_TIMESTAMP if data.u8()? & 1 != 0 => {
+                if let Ok(seconds) = data.u32() {
+                    mtime = seconds as i32 as i64;
+                }
+            }
+            // Kept by tools that write names in a code page; stale if the name was
+            // changed by one that doesn't know about it.
+            EXTRA_UNICODE_PATH if data.u8()? == 1 && data.u32()? == crc32fast::hash(raw_name) => {
+                name = std::str::from_utf8(data.0).ok().map(str::to_string);
+            }
+            _ => {}
+        }
+    }
+    let name = name.unwrap_or_else(|| decode_name(raw_name, flags));
+
+    Ok(ZipEntry {
+        entry: ArchiveEntry {
+            name: name.replace('\\', "/").trim_start_matches('/').to_string(),
+            size,
+            mtime,
+            crc32: Some(crc32),
+        },
+        flags,
+        method,
+        compressed_size,
+        header_offset,
+    })
+}
+
+/// Entry names are UTF-8 when the archive says so. Otherwise the format says CP437, but
+/// Japanese archives are made on Windows in CP932 (which encoding_rs calls Shift_JIS).
+/// Names that are valid UTF-8 anyway come from tools that don't set the flag.
+fn decode_name(raw: &[u8], flags: u16) -> String {
+    if flags & FLAG_UTF8 != 0 {
+        return String::from_utf8_lossy(raw).into_owned();
+    }
+    if let Ok(name) = std::str::from_utf8(raw) {
+        return name.to_string();
+    }
+    SHIFT_JIS.decode_without_bom_handling(raw).0.into_owned()
+}
+
+/// Seconds since the epoch of an MS-DOS date and time, read as UTC. They are only
+/// compared with each other, so the time zone they were written in doesn't matter.
+fn dos_time(date: u16, time: u16) -> i64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).max(1) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    // Days since the epoch of a proleptic Gregorian date.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3f) as i64 * 60 + (time & 0x1f) as i64 * 2;
+    days * 86_400 + seconds
+}
+
+fn seek_target(pos: u64, len: u64, to: SeekFrom) -> io::Result<u64> {
+    match to {
+        SeekFrom::Start(n) => Some(n),
+        SeekFrom::End(n) => len.checked_add_signed(n),
+        SeekFrom::Current(n) => pos.checked_add_signed(n),
+    }
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start of the entry"))
+}
+
+/// A stored entry, read straight out of the archive.
+struct Stored {
+    file: BufReader<File>,
+    len: u64,
+    pos: u64,
+}
+
+impl Stored {
+    fn new(mut file: File, start: u64, len: u64) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self { file: BufReader::with_capacity(64 * 1024, file), len, pos: 0 })
+    }
+}
+
+impl Read for Stored {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let n = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Stored {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let target = seek_target(self.pos, self.len, to)?;
+        // Relative, so a short hop stays inside the read buffer.
+        self.file.seek_relative(target as i64 - self.pos as i64)?;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+/// A deflated entry, inflated as it is read. Deflate streams only run forwards, so
+/// seeking back starts over from the beginning of the entry.
+///
+/// No checkpoints are kept, so each backward seek costs as much as inflating the entry
+/// up to the target: seeking back near the end of a long deflated WAV inflates most of
+/// it again. Audio is nearly always stored rather than deflated in the archives works
+/// come in, and stored entries seek in place, so this is left simple.
+struct Inflated {
+    file: File,
+    start: u64,
+    compressed_len: u64,
+    decoder: DeflateDecoder<BufReader<Take<File>>>,
+    len: u64,
+    pos: u64,
+}
+
+impl Inflated {
+    fn new(file: File, start: u64, compressed_len: u64, len: u64) -> io::Result<Self> {
+        let decoder = Self::decoder(&file, start, compressed_len)?;
+        Ok(Self { file, start, compressed_len, decoder, len, pos: 0 })
+    }
+
+    fn decoder(file: &File, start: u64, compressed_len: u64) -> io::Result<DeflateDecoder<BufReader<Take<File>>>> {
+        let mut file = file.try_clone()?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(DeflateDecoder::new(BufReader::with_capacity(64 * 1024, file.take(compressed_len))))
+    }
+}
+
+impl Read for Inflated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let n = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        if n == 0 {
+            return Ok(0);
+        }
+        let n = self.decoder.read(&mut buf[..n])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Deflated entry ends early"));
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Inflated {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let target = seek_target(self.pos, self.len, to)?;
+        // Re-inflates everything up to `target`; see the note on `Inflated`.
+        if target < self.pos {
+            self.decoder = Self::decoder(&self.file, self.start, self.compressed_len)?;
+            self.pos = 0;
+        }
+        // Nothing to inflate when already at or past the end.
+        let mut skip = target.min(self.len).saturating_sub(self.pos);
+        let mut buf = [0; 16 * 1024];
+        while skip > 0 {
+            let chunk = skip.min(buf.len() as u64) as usize;
+            let n = self.read(&mut buf[..chunk])?;
+            skip -= n as u64;
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    struct TestEntry<'a> {
+        name: &'a [u8],
+        flags: u16,
+        data: &'a [u8],
+        deflate: bool,
+    }
+
+    /// Write a single-disk archive the way common tools do, without data descriptors.
+    fn write_zip(name: &str, entries: &[TestEntry]) -> PathBuf {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let data = if entry.deflate {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(entry.data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                entry.data.to_vec()
+            };
+            let method = if entry.deflate { DEFLATED } else { STORED };
+            // 2024-01-02 12:34:56
+            let (time, date) = ((12 << 11) | (34 << 5) | 28, (44 << 9) | (1 << 5) | 2);
+            let mut common = Vec::new();
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&entry.flags.to_le_bytes());
+            common.extend_from_slice(&method.to_le_bytes());
+            common.extend_from_slice(&(time as u16).to_le_bytes());
+            common.extend_from_slice(&(date as u16).to_le_bytes());
+            common.extend_from_slice(&crc32fast::hash(entry.data).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+
+            let offset = out.len() as u32;
+            out.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            out.extend_from_slice(&common);
+            out.extend_from_slice(entry.name);
+            out.extend_from_slice(&data);
+
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&20u16.to_le_bytes());
+            directory.extend_from_slice(&common);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(entry.name);
+        }
+        let directory_start = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(&END_OF_CENTRAL_DIR.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_start.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+
+        let path = std::env::temp_dir().join(format!("asmr-zip-test-{}-{}.zip", name, std::process::id()));
+        std::fs::write(&path, out).unwrap();
+        path
+    }
+
+    #[test]
+    fn names_are_decoded_from_utf8_or_cp932() {
+        let (cp932, _, _) = SHIFT_JIS.encode("ボイス\\01 おやすみ.wav");
+        let path = write_zip(
+            "names",
+            &[
+                TestEntry { name: "音声/".as_bytes(), flags: FLAG_UTF8, data: b"", deflate: false },
+                TestEntry { name: "音声/01.mp3".as_bytes(), flags: FLAG_UTF8, data: b"mp3", deflate: false },
+                TestEntry { name: &cp932, flags: 0, data: b"wav", deflate: true },
+                TestEntry { name: b"secret.mp3", flags: FLAG_ENCRYPTED, data: b"", deflate: false },
+            ],
+        );
+        let entries = Zip.list(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["音声/01.mp3", "ボイス/01 おやすみ.wav"]);
+        assert_eq!(entries[1].size, 3);
+        assert_eq!(entries[1].crc32, Some(crc32fast::hash(b"wav")));
+        assert_eq!(entries[1].mtime, 1_704_198_896);
+    }
+
+    #[test]
+    fn entries_stream_and_seek() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let path = write_zip(
+            "seek",
+            &[
+                TestEntry { name: b"stored.wav", flags: 0, data: &data, deflate: false },
+                TestEntry { name: b"deflated.wav", flags: 0, data: &data, deflate: true },
+            ],
+        );
+        for name in ["stored.wav", "deflated.wav"] {
+            let mut reader = Zip.open(&path, name).unwrap();
+            assert_eq!(reader.len(), data.len() as u64);
+            let mut buf = [0; 100];
+
+            reader.seek(SeekFrom::Start(250_000)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &data[250_000..250_100], "{}", name);
+
+            reader.seek(SeekFrom::Start(10)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &data[10..110], "{}", name);
+
+            reader.seek(SeekFrom::End(-50)).unwrap();
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(&tail[..], &data[data.len() - 50..], "{}", name);
+
+            // Past the end reads nothing, however often it is sought, and coming back works.
+            assert_eq!(reader.seek(SeekFrom::End(10)).unwrap(), data.len() as u64 + 10);
+            assert_eq!(reader.seek(SeekFrom::End(20)).unwrap(), data.len() as u64 + 20);
+            assert_eq!(reader.read(&mut buf).unwrap(), 0, "{}", name);
+            reader.seek(SeekFrom::Start(10)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &data[10..110], "{}", name);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,51 +1,1093 @@
-use rodio::{Decoder, OutputStream, Sink, Source, OutputStreamHandle};
-use spectrum_analyzer::scaling::divide_by_N;
-use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
-use std::fs::File;
-use std::io::Cursor;
-use std::sync::Mutex;
+mod bookmarks;
+mod clock;
+mod decoder;
+mod dsp;
+mod events;
+mod flac;
+mod loudness;
+mod looping;
+mod output;
+mod progress;
+mod queue;
+mod render;
+mod settings;
+mod shuffle;
+mod silence;
+mod sleep_timer;
+mod spectrum;
+mod speed;
+mod stereo;
+mod transition;
+mod waveform;
+
+pub use events::PlaybackState;
+pub use loudness::{analyze_loudness, analyze_loudness_and_silence, Loudness};
+pub use progress::save_progress;
+use progress::save_progress_at_path;
+pub use silence::{detect_silence, Silence};
+
+use bookmarks::Bookmark;
+use clock::{ClockSource, OutputLatency, PlaybackClock};
+use decoder::{open_source, open_tracked, BoxedSource, DecodeFailure, OpenedSource};
+use dsp::{presets, DspChain, DspControl, DspPreset, DspSettings, FrameSource, LiveSettings};
+use events::PlayerEvent;
+use loudness::{load_loudness, normalization_gain, GainSource, LoudnessLookup, NormalizationSettings, SharedGain};
+use looping::{LoopControl, LoopRegion, LoopSource};
+use output::{OpenedOutput, Output, OutputDevice};
+use queue::{same_work, PlaybackMode, PlayQueue, QueueEntry, QueueSnapshot, QueueTrack, RemoveOutcome, RepeatMode, ShuffleMode};
+use settings::{
+    delete_setting, load_setting, restore_setting, save_setting, work_dsp_key, work_speed_key, DSP_KEY, NORMALIZATION_KEY,
+    OUTPUT_DEVICE_KEY, PLAYBACK_MODE_KEY, SKIP_SILENCE_KEY, SPECTRUM_KEY, STEREO_KEY, TRANSITION_KEY,
+};
+use render::{OutputSink, RenderOutput, RenderSettings, RenderStatus, RenderSummary};
+use silence::{load_silence, TrimControl, TrimSource};
+use sleep_timer::{SleepTimer, SleepTimerMode, SleepTimerStatus, Tick};
+use spectrum::{SpectrumAnalyzer, SpectrumSettings};
+use speed::{SpeedControl, SpeedSettings, SpeedSource, MAX_SPEED, MIN_SPEED};
+use stereo::{StereoSettings, StereoStage};
+use transition::{FadeControl, FadeSource, Fades, Handover, TransitionSettings};
+use waveform::{Waveform, STORED_BUCKETS};
+use sqlx::SqlitePool;
+use rodio::source::SeekError;
+use rodio::{Sink, Source, OutputStreamHandle};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
-use tauri::{AppHandle, Emitter, State};
-use tokio::sync::broadcast;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::{broadcast, mpsc};
+
+/// Reported by `QueuedSource` from the audio thread.
+enum TrackEvent {
+    Started { generation: u64, entry_id: u64, source_id: u64, clock: TrackClock },
+    // `error` is set when decoding failed before the end of the file.
+    Finished { generation: u64, entry_id: u64, error: Option<String> },
+    // The track reached its crossfade and wants the next one started.
+    FadingOut { generation: u64, entry_id: u64 },
+}
+
+/// Payload of the `get_playback_state` command, enough to rebuild the player after a reload.
+#[derive(Clone, serde::Serialize)]
+pub struct PlaybackStatus {
+    state: PlaybackState,
+    current_index: Option<usize>,
+    entry: Option<QueueEntry>,
+    position: PlaybackPosition,
+    volume: f32,
+}
+
+/// Payload of the `output-device-changed` event and the output device commands.
+#[derive(Clone, serde::Serialize)]
+pub struct OutputStatus {
+    // None while no device is available.
+    active: Option<String>,
+    // None follows the system default.
+    preferred: Option<String>,
+}
+
+/// Audible position of a track as reported by its `ClockSource`.
+#[derive(Clone)]
+struct TrackClock {
+    entry_id: u64,
+    playback: PlaybackClock,
+    duration: Option<f64>,
+}
+
+impl TrackClock {
+    fn seconds(&self) -> f64 {
+        self.playback.position().as_secs_f64()
+    }
+}
+
+/// Payload of the `playback-position` event and the `get_playback_position` command.
+/// `position` and `duration` are track time; divide by `speed` for wall-clock time.
+#[derive(Clone, serde::Serialize)]
+pub struct PlaybackPosition {
+    entry_id: Option<u64>,
+    position: f64,
+    duration: Option<f64>,
+    speed: f64,
+    paused: bool,
+}
+
+/// Payload of the `ab-loop-changed` event and the `get_ab_loop` command.
+#[derive(Clone, serde::Serialize)]
+pub struct AbLoop {
+    entry_id: u64,
+    track_id: i64,
+    start: f64,
+    // None loops to the end of the track.
+    end: Option<f64>,
+    bookmark_id: Option<i64>,
+}
+
+impl AbLoop {
+    fn region(&self) -> LoopRegion {
+        LoopRegion {
+            start: Duration::from_secs_f64(self.start.max(0.0)),
+            end: self.end.map(|e| Duration::from_secs_f64(e.max(0.0))),
+        }
+    }
+}
+
+/// Shortest region `set_ab_loop` accepts, in seconds.
+const MIN_LOOP_SECS: f64 = 0.1;
+
+/// Which track a resume position belongs to.
+#[derive(Debug, Clone, PartialEq)]
+enum ProgressTrack {
+    Library { work_id: i64, track_id: i64 },
+    /// An entry queued without its ids, matched against `tracks.path` when written.
+    Path(String),
+}
+
+impl ProgressTrack {
+    fn of(track: &QueueTrack) -> Self {
+        match track.work_id {
+            Some(work_id) if track.id > 0 => ProgressTrack::Library { work_id, track_id: track.id },
+            _ => ProgressTrack::Path(track.path.clone()),
+        }
+    }
+}
+
+/// (track, position_sec) to write to `track_progress`.
+type ProgressRecord = (ProgressTrack, f64);
+
+/// How often the playing position is written to `track_progress`.
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A work being exported on a render of its own, beside whatever the player is doing.
+struct Export {
+    output: RenderOutput,
+    // Holds the work's tracks; the export is done once it has played them all.
+    sink: OutputSink,
+}
+
+/// How often a running export is checked for having written its last track.
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What one track of an export is played with, taken from the player's settings so
+/// the file sounds the way the work does in the player.
+struct ExportTrack {
+    path: String,
+    gain: f32,
+    dsp: DspSettings,
+    stereo: StereoSettings,
+    speed: SpeedSettings,
+    silence: Option<Silence>,
+}
+
+impl ExportTrack {
+    /// Decode the track past its leading silence and run it through the playback chain,
+    /// without the parts that only make sense live: loops, fades and the visualizer.
+    fn open(self) -> Result<BoxedSource, String> {
+        let start = self.silence.map_or(0.0, |s| s.leading_secs);
+        let trim = self
+            .silence
+            .filter(|s| s.trailing_secs > 0.0)
+            .map(|s| Duration::from_secs_f64(s.trailing_secs));
+        let source = open_source(&self.path, start as f32)?;
+
+        let start = Duration::from_secs_f64(start);
+        let playback = PlaybackClock::new(start, OutputLatency::default());
+        let stretched = SpeedSource::new(source, SpeedControl::new(self.speed), playback.media_rate().clone());
+        let clocked = ClockSource::new(stretched, start, playback.clone());
+        let trimmed = TrimSource::new(clocked, TrimControl::new(trim), playback);
+        let normalized = GainSource::new(trimmed, SharedGain::new(self.gain));
+        let stereo = FrameSource::new(normalized, StereoStage::new(LiveSettings::new(self.stereo)));
+        Ok(Box::new(FrameSource::new(stereo, DspChain::new(DspControl::new(self.dsp)))))
+    }
+}
+
+/// The queue entry that has already been decoded and appended to the sink
+/// behind the current one.
+struct Preloaded {
+    entry_id: u64,
+    cancel: Arc<AtomicBool>,
+    // Nothing was appended; the current track fades out instead and the entry
+    // starts on a sink of its own once the fade begins.
+    crossfade: bool,
+}
+
+/// The next entry, picked under the lock and decoded outside it.
+struct PreloadJob {
+    generation: u64,
+    entry: QueueEntry,
+    lead_in: Duration,
+    offset: f32,
+}
+
+/// Live controls of one source in the sink.
+#[derive(Clone)]
+struct SourceControls {
+    entry_id: u64,
+    gain: SharedGain,
+    dsp: DspControl,
+    // Shared by every source; stereo settings don't vary per work.
+    stereo: LiveSettings<StereoSettings>,
+    spectrum: LiveSettings<SpectrumSettings>,
+    speed: SpeedControl,
+    looping: LoopControl,
+    fades: FadeControl,
+    trim: TrimControl,
+}
+
+/// Per-work data the engine needs before a work's tracks reach the sink.
+struct WorkLookup {
+    /// The works and tracks looked up, whose measurements replace anything cached for them.
+    works: Vec<i64>,
+    tracks: Vec<i64>,
+    loudness: Option<LoudnessLookup>,
+    dsp: HashMap<i64, Option<DspSettings>>,
+    speed: HashMap<i64, Option<SpeedSettings>>,
+    silence: Option<HashMap<i64, Silence>>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct TrackChanged {
+    index: usize,
+    entry: QueueEntry,
+}
 
 pub struct AudioState {
-    pub sink: Option<Sink>,
-    // stream is !Send, so we don't store it here. We leak it in new().
+    pub sink: Option<OutputSink>,
+    // The stream itself stays on the output thread; None while there is no device.
     pub stream_handle: Option<OutputStreamHandle>,
+    output: Output,
+    // Device the stream plays on, and the one the user picked (None follows the default).
+    output_device: Option<String>,
+    preferred_output: Option<String>,
+    // Plays into a file or into nothing instead of the device while set.
+    render: Option<RenderOutput>,
+    export: Option<Export>,
     pub app_handle: Option<AppHandle>,
     pub current_path: Option<String>,
+    pub queue: PlayQueue,
+    playback_state: PlaybackState,
+    // Volume set by the user; the sink runs at `volume * fade_gain`.
+    pub volume: f32,
+    fade_gain: f32,
+    clock: Option<TrackClock>,
+    latency: OutputLatency,
+    normalization: NormalizationSettings,
+    dsp: DspSettings,
+    stereo: LiveSettings<StereoSettings>,
+    spectrum: LiveSettings<SpectrumSettings>,
+    // Measured loudness of queued tracks and their works, filled in by the queue commands.
+    track_loudness: HashMap<i64, Loudness>,
+    work_loudness: HashMap<i64, Loudness>,
+    // Per-work DSP overrides; None once a work is known to have none.
+    work_dsp: HashMap<i64, Option<DspSettings>>,
+    // Remembered speed of each work, and the speed of tracks that have no work.
+    work_speed: HashMap<i64, Option<SpeedSettings>>,
+    speed: SpeedSettings,
+    // Gain and DSP of each source still in the sink. Keyed by source rather than queue
+    // entry, since repeat-one queues an entry behind itself.
+    sources: HashMap<u64, SourceControls>,
+    next_source_id: u64,
+    // A-B loop on the current entry; dropped when another entry becomes current.
+    ab_loop: Option<AbLoop>,
+    transition: TransitionSettings,
+    // The previous work's tail while the next one crossfades in.
+    fading_sink: Option<OutputSink>,
+    // Start tracks after their leading silence and end them before the trailing one.
+    skip_silence: bool,
+    track_silence: HashMap<i64, Silence>,
+    sleep_timer: Option<SleepTimer>,
+    next_timer_id: u64,
+    preloaded: Option<Preloaded>,
+    // Bumped every time the sink is rebuilt so events from dropped sources are ignored.
+    generation: u64,
+    events_tx: mpsc::UnboundedSender<TrackEvent>,
+    events_rx: Option<mpsc::UnboundedReceiver<TrackEvent>>,
+    // Progress writes go through one task so they reach the database in order.
+    progress_tx: mpsc::UnboundedSender<ProgressRecord>,
+    progress_rx: Option<mpsc::UnboundedReceiver<ProgressRecord>>,
 }
 
-// Sink and OutputStreamHandle are Send. The OutputStream itself is not.
-// By leaking the OutputStream and only storing its handle, AudioState becomes Send.
-unsafe impl Send for AudioState {}
-
 impl AudioState {
     pub fn new() -> Self {
-        // match on try_default.
-        // We MUST keep the stream alive, but we can't store it in tauri::State (requires Send).
-        // Since we want it to live for the app duration, we leak it.
-        let (stream, stream_handle) = match OutputStream::try_default() {
-            Ok((s, h)) => (Some(s), Some(h)),
-            Err(_) => (None, None),
+        // Without a device we start silent; the output thread keeps looking for one.
+        let output = Output::start();
+        let (stream_handle, output_device) = match output.open(None) {
+            Ok(opened) => (Some(opened.handle), Some(opened.device)),
+            Err(e) => {
+                eprintln!("[Audio] No output device: {}", e);
+                (None, None)
+            }
         };
-        
-        if let Some(s) = stream {
-            // Leak the stream to keep it alive without dropping it.
-            Box::leak(Box::new(s));
-        }
 
         let sink = if let Some(ref h) = stream_handle {
-             Sink::try_new(h).ok()
+             Sink::try_new(h).ok().map(OutputSink::device)
         } else {
             None
         };
 
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+
         Self {
             stream_handle,
+            output,
+            output_device,
+            preferred_output: None,
+            render: None,
+            export: None,
             sink,
             app_handle: None,
             current_path: None,
+            queue: PlayQueue::default(),
+            playback_state: PlaybackState::Stopped,
+            volume: 1.0,
+            fade_gain: 1.0,
+            clock: None,
+            latency: OutputLatency::default(),
+            normalization: NormalizationSettings::default(),
+            dsp: DspSettings::default(),
+            stereo: LiveSettings::new(StereoSettings::default()),
+            spectrum: LiveSettings::new(SpectrumSettings::default()),
+            track_loudness: HashMap::new(),
+            work_loudness: HashMap::new(),
+            work_dsp: HashMap::new(),
+            work_speed: HashMap::new(),
+            speed: SpeedSettings::default(),
+            sources: HashMap::new(),
+            next_source_id: 0,
+            ab_loop: None,
+            transition: TransitionSettings::default(),
+            fading_sink: None,
+            skip_silence: false,
+            track_silence: HashMap::new(),
+            sleep_timer: None,
+            next_timer_id: 0,
+            preloaded: None,
+            generation: 0,
+            events_tx,
+            events_rx: Some(events_rx),
+            progress_tx,
+            progress_rx: Some(progress_rx),
+        }
+    }
+
+    /// Throw away whatever the sink holds and start queue entry `index` at `offset` seconds.
+    /// The entry after it is preloaded once the audio thread reports that playback started.
+    fn start_entry(&mut self, app: &AppHandle, index: usize, offset: f32) -> Result<(), String> {
+        self.load_entry(app, index, offset, true)
+    }
+
+    fn load_entry(&mut self, app: &AppHandle, index: usize, offset: f32, play: bool) -> Result<(), String> {
+        self.load_entry_with(app, index, offset, play, None)
+    }
+
+    /// `load_entry`, optionally fading the entry in. A fade-in is a crossfade, so it
+    /// leaves `fading_sink` playing.
+    fn load_entry_with(
+        &mut self,
+        app: &AppHandle,
+        index: usize,
+        offset: f32,
+        play: bool,
+        fade_in: Option<Duration>,
+    ) -> Result<(), String> {
+        self.open_entry(Some(app), index, offset, play, fade_in)
+    }
+
+    /// `load_entry_with` without an app to report to, e.g. when nothing is listening.
+    fn open_entry(
+        &mut self,
+        app: Option<&AppHandle>,
+        index: usize,
+        offset: f32,
+        play: bool,
+        fade_in: Option<Duration>,
+    ) -> Result<(), String> {
+        let entry = self
+            .queue
+            .jump(index)
+            .cloned()
+            .ok_or_else(|| format!("Queue index {} out of range", index))?;
+        let offset = if offset > 0.0 { offset } else { self.start_offset(entry.entry_id) };
+
+        self.flush_progress();
+        self.generation += 1;
+        self.preloaded = None;
+        self.clock = None;
+        self.sources.clear();
+        self.current_path = Some(entry.track.path.clone());
+        self.drop_stale_loop();
+        if fade_in.is_none() {
+            self.fading_sink = None;
+        }
+
+        let new_sink = self.open_sink()?;
+        new_sink.set_volume(self.volume * self.fade_gain);
+        // Drop the old sink before decoding so the previous track stops right away.
+        self.sink = None;
+
+        let opened = match open_tracked(&entry.track.path, offset) {
+            Ok(opened) => opened,
+            Err(e) => {
+                if let Some(app) = app {
+                    emit_track_error(app, &entry, &e);
+                }
+                self.current_path = None;
+                self.set_playback_state(PlaybackState::Stopped);
+                return Err(e);
+            }
+        };
+        let (source_id, controls) = self.source_controls(entry.entry_id);
+        if fade_in.is_some() {
+            controls.fades.set(Fades { fade_in, fade_out: None });
+        }
+        append_to_sink(
+            &new_sink,
+            app.cloned(),
+            opened.source,
+            offset,
+            self.latency.clone(),
+            controls,
+            QueueTag {
+                generation: self.generation,
+                entry_id: entry.entry_id,
+                source_id,
+                cancel: Arc::new(AtomicBool::new(false)),
+                lead_in: Duration::ZERO,
+                failure: opened.failure,
+                events: self.events_tx.clone(),
+            },
+        );
+        if play {
+            new_sink.play();
+        } else {
+            new_sink.pause();
+        }
+        self.sink = Some(new_sink);
+        self.set_playback_state(if play { PlaybackState::Buffering } else { PlaybackState::Paused });
+        Ok(())
+    }
+
+    /// Stop playback entirely without touching the queue contents.
+    fn stop(&mut self) {
+        self.flush_progress();
+        self.generation += 1;
+        self.preloaded = None;
+        self.clock = None;
+        self.sources.clear();
+        self.current_path = None;
+        self.set_ab_loop(None);
+        self.fading_sink = None;
+        if let Some(ref sink) = self.sink {
+            sink.stop();
+        }
+        self.set_playback_state(PlaybackState::Stopped);
+    }
+
+    /// Move playback onto a new output stream, or hold it when no device is left.
+    /// Whatever was playing carries on from the same position.
+    fn switch_output(&mut self, app: &AppHandle, opened: Option<OpenedOutput>) {
+        self.output_device = opened.as_ref().map(|o| o.device.clone());
+        self.stream_handle = opened.map(|o| o.handle);
+        let _ = app.emit("output-device-changed", self.output_status());
+        // A render keeps playing where it is; the new device is picked up once it ends.
+        if self.render.is_none() {
+            self.reopen_output(app);
+        }
+    }
+
+    /// Rebuild the sink on the current output, carrying on from the same position.
+    fn reopen_output(&mut self, app: &AppHandle) {
+        let current = self.queue.current().map(|e| e.entry_id);
+        let resume = self.queue.current_index().filter(|_| self.current_path.is_some()).map(|index| {
+            let position = self.clock.as_ref().filter(|c| Some(c.entry_id) == current).map_or(0.0, |c| c.seconds());
+            (index, position)
+        });
+        let play = matches!(self.playback_state, PlaybackState::Playing | PlaybackState::Buffering);
+
+        match resume {
+            Some((index, position)) if self.render.is_some() || self.stream_handle.is_some() => {
+                if let Err(e) = self.load_entry(app, index, position as f32, play) {
+                    eprintln!("[Audio] Failed to resume on the new output: {}", e);
+                }
+            }
+            // The old sink is silent now; keep it and its clock so the position survives.
+            Some(_) => self.pause(),
+            None => self.sink = None,
+        }
+    }
+
+    /// A sink on the render if there is one, else on the device.
+    fn open_sink(&self) -> Result<OutputSink, String> {
+        if let Some(ref render) = self.render {
+            return Ok(render.sink());
+        }
+        let handle = self.stream_handle.as_ref().ok_or("No audio output device")?;
+        Sink::try_new(handle).map(OutputSink::device).map_err(|e| e.to_string())
+    }
+
+    /// Send playback to a file or into nothing instead of the device.
+    fn start_render(&mut self, app: &AppHandle, settings: RenderSettings) -> Result<RenderStatus, String> {
+        if self.render.is_some() {
+            return Err("A render is already running".to_string());
+        }
+        let output = RenderOutput::start(settings)?;
+        output.set_active(matches!(self.playback_state, PlaybackState::Playing | PlaybackState::Buffering));
+        let status = output.status();
+        self.render = Some(output);
+        self.fading_sink = None;
+        self.reopen_output(app);
+        Ok(status)
+    }
+
+    /// Close the render and move whatever is playing back to the device.
+    fn end_render(&mut self, app: &AppHandle) -> Option<RenderSummary> {
+        let output = self.render.take()?;
+        self.fading_sink = None;
+        self.reopen_output(app);
+        let summary = output.finish();
+        let _ = app.emit("render-finished", &summary);
+        Some(summary)
+    }
+
+    /// How each of `tracks` would be played right now.
+    fn export_tracks(&self, tracks: &[QueueTrack]) -> Vec<ExportTrack> {
+        tracks
+            .iter()
+            .map(|track| ExportTrack {
+                path: track.path.clone(),
+                gain: self.track_gain(track),
+                dsp: self.track_dsp(track),
+                stereo: self.stereo.get(),
+                speed: self.track_speed(track),
+                silence: self.silence_of(track),
+            })
+            .collect()
+    }
+
+    /// Close the export, done or not, and report what was written.
+    fn end_export(&mut self, app: &AppHandle) -> Option<RenderSummary> {
+        let export = self.export.take()?;
+        export.sink.stop();
+        let summary = export.output.finish();
+        let _ = app.emit("render-finished", &summary);
+        Some(summary)
+    }
+
+    fn output_status(&self) -> OutputStatus {
+        OutputStatus {
+            active: self.output_device.clone(),
+            preferred: self.preferred_output.clone(),
+        }
+    }
+
+    /// Record what the player is doing and tell the frontend if that changed.
+    fn set_playback_state(&mut self, state: PlaybackState) {
+        let Some(event) = events::state_changed(self.playback_state, state) else {
+            return;
+        };
+        self.playback_state = state;
+        if let Some(ref render) = self.render {
+            render.set_active(matches!(state, PlaybackState::Playing | PlaybackState::Buffering));
+        }
+        if let Some(ref app) = self.app_handle {
+            emit_player_event(app, event);
+        }
+    }
+
+    fn pause(&mut self) {
+        for sink in self.sink.iter().chain(&self.fading_sink) {
+            sink.pause();
+        }
+        if self.current_path.is_some() {
+            self.set_playback_state(PlaybackState::Paused);
+        }
+    }
+
+    fn resume(&mut self) {
+        for sink in self.sink.iter().chain(&self.fading_sink) {
+            sink.play();
+        }
+        if self.current_path.is_some() {
+            // No clock yet means the audio thread hasn't reached the track.
+            let state = if self.clock.is_some() { PlaybackState::Playing } else { PlaybackState::Buffering };
+            self.set_playback_state(state);
+        }
+    }
+
+    /// Seek the current entry to `seconds` of track time.
+    fn seek(&mut self, app: &AppHandle, seconds: f32) -> Result<(), String> {
+        let index = match (self.current_path.as_ref(), self.queue.current_index()) {
+            (Some(_), Some(i)) => i,
+            _ => return Ok(()), // Nothing playing
+        };
+
+        // Seek the playing source in place so the preloaded next track stays queued.
+        if let Some(ref sink) = self.sink {
+            if !sink.empty() {
+                match sink.try_seek(Duration::from_secs_f32(seconds.max(0.0))) {
+                    Ok(()) => {
+                        // try_seek waits for the audio thread, so the clock is already at the target.
+                        self.flush_progress();
+                        self.emit_position(app);
+                        return Ok(());
+                    }
+                    Err(e) => eprintln!("[Audio] In-place seek failed ({}), reopening track", e),
+                }
+            }
+        }
+
+        // Fall back to recreating the sink, which reopens the file at the target
+        let target = self.current_progress().map(|(track, _)| (track, seconds.max(0.0) as f64));
+        self.start_entry(app, index, seconds)?;
+        self.persist_progress(target);
+        Ok(())
+    }
+
+    /// Loop part of the current entry, or stop looping with None.
+    fn set_ab_loop(&mut self, ab_loop: Option<AbLoop>) {
+        if self.ab_loop.is_none() && ab_loop.is_none() {
+            return;
+        }
+        // The fade-out would cut into a loop that runs to the end of the track.
+        if ab_loop.is_some() && self.preloaded.as_ref().is_some_and(|p| p.crossfade) {
+            self.drop_preload();
+        }
+        if let Some(entry) = self.queue.current() {
+            for controls in self.sources.values().filter(|c| c.entry_id == entry.entry_id) {
+                controls.looping.set(ab_loop.as_ref().map(AbLoop::region));
+            }
+        }
+        self.ab_loop = ab_loop;
+        if let Some(ref app) = self.app_handle {
+            let _ = app.emit("ab-loop-changed", &self.ab_loop);
+        }
+    }
+
+    /// The loop belongs to one entry; forget it once something else is current.
+    fn drop_stale_loop(&mut self) {
+        let current = self.queue.current().map(|e| e.entry_id);
+        if self.ab_loop.as_ref().is_some_and(|l| Some(l.entry_id) != current) {
+            self.set_ab_loop(None);
+        }
+    }
+
+    /// After the queue has been edited, make sure the preloaded entry is still the one
+    /// that follows the current track. Returns true if a new preload should be scheduled.
+    fn resync_preload(&mut self) -> bool {
+        let wanted = self.queue.peek_next().map(|e| e.entry_id);
+        if self.preloaded.as_ref().is_some_and(|p| Some(p.entry_id) == wanted) {
+            return false;
+        }
+        self.drop_preload();
+        wanted.is_some() && self.sink.is_some() && self.current_path.is_some() && self.preload_allowed()
+    }
+
+    /// Take back whatever was lined up behind the current entry.
+    fn drop_preload(&mut self) {
+        let Some(p) = self.preloaded.take() else {
+            return;
+        };
+        if p.crossfade {
+            for controls in self.sources.values() {
+                controls.fades.set(Fades {
+                    fade_out: None,
+                    ..controls.fades.get()
+                });
+            }
+        } else {
+            // Not started yet, so the source ends immediately and the sink moves past it.
+            p.cancel.store(true, Ordering::Release);
+        }
+    }
+
+    /// How the current entry hands over to the one after it.
+    fn handover(&self) -> Handover {
+        let (Some(current), Some(next)) = (self.queue.current(), self.queue.peek_next()) else {
+            return Handover::Gapless;
+        };
+        match self.transition.handover(same_work(current, next)) {
+            // A loop holds playback in this track, so there is nothing to fade towards.
+            Handover::Crossfade(_) if self.ab_loop.is_some() => Handover::Gapless,
+            handover => handover,
+        }
+    }
+
+    /// Start entry `index` on a sink of its own, fading in, while the current sink plays
+    /// out its faded tail.
+    fn crossfade_to(&mut self, app: &AppHandle, index: usize, duration: Duration) -> Result<(), String> {
+        self.fading_sink = self.sink.take();
+        self.load_entry_with(app, index, 0.0, true, Some(duration))
+    }
+
+    /// A track/work sleep timer holds back the next track so the sink runs dry at the boundary.
+    fn preload_allowed(&self) -> bool {
+        match (&self.sleep_timer, self.queue.current_index()) {
+            (Some(timer), Some(current)) => !timer.stops_after(&self.queue, current),
+            _ => true,
+        }
+    }
+
+    /// The entry `preload_next` should decode, if any. A crossfade needs nothing decoded
+    /// yet: the next entry starts on its own sink when the fade begins.
+    fn preload_job(&mut self) -> Option<PreloadJob> {
+        if self.preloaded.is_some() || self.current_path.is_none() || !self.preload_allowed() {
+            return None;
+        }
+        let entry = self.queue.peek_next().cloned()?;
+        let lead_in = match self.handover() {
+            Handover::Gapless => Duration::ZERO,
+            Handover::Gap(gap) => gap,
+            Handover::Crossfade(duration) => {
+                for controls in self.sources.values() {
+                    controls.fades.set(Fades {
+                        fade_out: Some(duration),
+                        ..controls.fades.get()
+                    });
+                }
+                self.preloaded = Some(Preloaded {
+                    entry_id: entry.entry_id,
+                    cancel: Arc::new(AtomicBool::new(false)),
+                    crossfade: true,
+                });
+                return None;
+            }
+        };
+        Some(PreloadJob {
+            generation: self.generation,
+            offset: self.start_offset(entry.entry_id),
+            entry,
+            lead_in,
+        })
+    }
+
+    /// Append a decoded `job` behind the current track.
+    fn finish_preload(&mut self, app: Option<&AppHandle>, job: PreloadJob, opened: OpenedSource) {
+        // The sink or the queue may have changed while we were decoding.
+        if self.generation != job.generation
+            || self.preloaded.is_some()
+            || !self.preload_allowed()
+            || self.queue.peek_next().map(|e| e.entry_id) != Some(job.entry.entry_id)
+        {
+            return;
+        }
+        if self.sink.is_none() {
+            return;
+        }
+        let (source_id, controls) = self.source_controls(job.entry.entry_id);
+        let Some(ref sink) = self.sink else {
+            return;
+        };
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        append_to_sink(
+            sink,
+            app.cloned(),
+            opened.source,
+            job.offset,
+            self.latency.clone(),
+            controls,
+            QueueTag {
+                generation: job.generation,
+                entry_id: job.entry.entry_id,
+                source_id,
+                cancel: cancel.clone(),
+                lead_in: job.lead_in,
+                failure: opened.failure,
+                events: self.events_tx.clone(),
+            },
+        );
+        self.preloaded = Some(Preloaded {
+            entry_id: job.entry.entry_id,
+            cancel,
+            crossfade: false,
+        });
+    }
+
+    fn set_fade_gain(&mut self, gain: f32) {
+        self.fade_gain = gain;
+        for sink in self.sink.iter().chain(&self.fading_sink) {
+            sink.set_volume(self.volume * self.fade_gain);
+        }
+    }
+
+    /// Where the current entry is, once the audio thread has started it.
+    fn current_progress(&self) -> Option<ProgressRecord> {
+        let entry = self.queue.current()?;
+        // The queue may already point elsewhere while the old track is being torn down.
+        let clock = self.clock.as_ref().filter(|c| c.entry_id == entry.entry_id)?;
+        Some((ProgressTrack::of(&entry.track), clock.seconds()))
+    }
+
+    /// Hand a record to the progress writer.
+    fn persist_progress(&self, progress: Option<ProgressRecord>) {
+        if let Some(record) = progress {
+            let _ = self.progress_tx.send(record);
+        }
+    }
+
+    fn flush_progress(&self) {
+        self.persist_progress(self.current_progress());
+    }
+
+    fn is_playing(&self) -> bool {
+        self.sink.as_ref().is_some_and(|s| !s.is_paused() && !s.empty())
+    }
+
+    fn entry_by_id(&self, entry_id: u64) -> Option<&QueueEntry> {
+        self.queue.index_of(entry_id).and_then(|i| self.queue.entry(i))
+    }
+
+    fn normalization_gain(&self, entry_id: u64) -> f32 {
+        self.entry_by_id(entry_id).map_or(1.0, |e| self.track_gain(&e.track))
+    }
+
+    fn track_gain(&self, track: &QueueTrack) -> f32 {
+        let loudness = self.track_loudness.get(&track.id).copied();
+        let work = track.work_id.and_then(|w| self.work_loudness.get(&w)).copied();
+        normalization_gain(&self.normalization, loudness, work)
+    }
+
+    fn dsp_settings(&self, entry_id: u64) -> DspSettings {
+        match self.entry_by_id(entry_id) {
+            Some(entry) => self.track_dsp(&entry.track),
+            None => self.dsp.clone(),
+        }
+    }
+
+    /// The work's own chain if it has one, otherwise the user's.
+    fn track_dsp(&self, track: &QueueTrack) -> DspSettings {
+        track
+            .work_id
+            .and_then(|w| self.work_dsp.get(&w).cloned().flatten())
+            .unwrap_or_else(|| self.dsp.clone())
+    }
+
+    fn speed_settings(&self, entry_id: u64) -> SpeedSettings {
+        match self.entry_by_id(entry_id) {
+            Some(entry) => self.track_speed(&entry.track),
+            None => self.speed.clone(),
+        }
+    }
+
+    /// The work's remembered speed; works that have none play at normal speed.
+    fn track_speed(&self, track: &QueueTrack) -> SpeedSettings {
+        match track.work_id {
+            Some(work_id) => self.work_speed.get(&work_id).cloned().flatten().unwrap_or_default(),
+            None => self.speed.clone(),
+        }
+    }
+
+    /// Measured silence of the entry's track, if it is to be skipped.
+    fn silence(&self, entry_id: u64) -> Option<Silence> {
+        self.silence_of(&self.entry_by_id(entry_id)?.track)
+    }
+
+    fn silence_of(&self, track: &QueueTrack) -> Option<Silence> {
+        if !self.skip_silence {
+            return None;
+        }
+        self.track_silence.get(&track.id).copied()
+    }
+
+    /// Where an entry starts playing when it isn't resumed from a position.
+    fn start_offset(&self, entry_id: u64) -> f32 {
+        self.silence(entry_id).map_or(0.0, |s| s.leading_secs as f32)
+    }
+
+    fn trailing_trim(&self, entry_id: u64) -> Option<Duration> {
+        self.silence(entry_id)
+            .filter(|s| s.trailing_secs > 0.0)
+            .map(|s| Duration::from_secs_f64(s.trailing_secs))
+    }
+
+    /// Id and controls for a source about to be appended to the sink.
+    fn source_controls(&mut self, entry_id: u64) -> (u64, SourceControls) {
+        let controls = SourceControls {
+            entry_id,
+            gain: SharedGain::new(self.normalization_gain(entry_id)),
+            dsp: DspControl::new(self.dsp_settings(entry_id)),
+            stereo: self.stereo.clone(),
+            spectrum: self.spectrum.clone(),
+            speed: SpeedControl::new(self.speed_settings(entry_id)),
+            // Reopening the current entry (e.g. a seek fallback) keeps its loop.
+            looping: LoopControl::new(
+                self.ab_loop
+                    .as_ref()
+                    .filter(|l| l.entry_id == entry_id)
+                    .map(AbLoop::region),
+            ),
+            fades: FadeControl::new(Fades::default()),
+            trim: TrimControl::new(self.trailing_trim(entry_id)),
+        };
+        self.next_source_id += 1;
+        self.sources.insert(self.next_source_id, controls.clone());
+        (self.next_source_id, controls)
+    }
+
+    /// Re-apply normalization, DSP, speed and silence trimming to everything in the sink after settings changed.
+    fn refresh_sources(&self) {
+        for controls in self.sources.values() {
+            let entry_id = controls.entry_id;
+            controls.gain.set(self.normalization_gain(entry_id));
+            controls.dsp.set(self.dsp_settings(entry_id));
+            controls.speed.set(self.speed_settings(entry_id));
+            controls.trim.set(self.trailing_trim(entry_id));
+        }
+    }
+
+    fn merge_work_settings(&mut self, lookup: Option<WorkLookup>) {
+        let Some(lookup) = lookup else {
+            return;
+        };
+        // A rescan may have re-measured or cleared what an earlier lookup cached.
+        if let Some(loudness) = lookup.loudness {
+            for track_id in &lookup.tracks {
+                self.track_loudness.remove(track_id);
+            }
+            for work_id in &lookup.works {
+                self.work_loudness.remove(work_id);
+            }
+            self.track_loudness.extend(loudness.tracks);
+            self.work_loudness.extend(loudness.works);
+        }
+        if let Some(silence) = lookup.silence {
+            for track_id in &lookup.tracks {
+                self.track_silence.remove(track_id);
+            }
+            self.track_silence.extend(silence);
+        }
+        self.work_dsp.extend(lookup.dsp);
+        self.work_speed.extend(lookup.speed);
+        self.refresh_sources();
+    }
+
+    fn playback_position(&self) -> PlaybackPosition {
+        PlaybackPosition {
+            entry_id: self.queue.current().map(|e| e.entry_id),
+            position: self.clock.as_ref().map(|c| c.seconds()).unwrap_or(0.0),
+            duration: self.clock.as_ref().and_then(|c| c.duration),
+            speed: self.current_speed(),
+            paused: !self.is_playing(),
+        }
+    }
+
+    fn current_speed(&self) -> f64 {
+        self.queue
+            .current()
+            .map_or(1.0, |e| self.speed_settings(e.entry_id).speed as f64)
+    }
+
+    fn emit_position(&self, app: &AppHandle) {
+        let _ = app.emit("playback-position", self.playback_position());
+    }
+
+    /// Stop for the sleep timer. At a track boundary (`at_boundary`) the sink has already
+    /// run dry, so the next entry is loaded paused and resuming carries on from there.
+    fn fire_sleep_timer(&mut self, app: &AppHandle, at_boundary: bool) -> Option<ProgressRecord> {
+        let timer = self.sleep_timer.take()?;
+        let mut progress = self.current_progress();
+
+        if at_boundary {
+            let finished = self.queue.current().cloned();
+            if let Some(next) = self.queue.next_index() {
+                match self.load_entry(app, next, 0.0, false) {
+                    Ok(()) => {
+                        let next_entry = self.queue.current();
+                        // Within the same work, resume from the start of the next part.
+                        if let (Some(f), Some(n)) = (finished, next_entry) {
+                            if f.track.work_id.is_some() && f.track.work_id == n.track.work_id {
+                                progress = Some((ProgressTrack::of(&n.track), 0.0));
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("[Audio] Failed to load next track after sleep timer: {}", e),
+                }
+            } else {
+                self.current_path = None;
+                self.set_playback_state(PlaybackState::Stopped);
+            }
+        } else {
+            self.pause();
+        }
+
+        self.set_fade_gain(1.0);
+        let _ = app.emit("sleep-timer-fired", timer.mode);
+        progress
+    }
+}
+
+async fn write_progress(app: &AppHandle, (track, position): ProgressRecord) {
+    // The pool is managed once migrations finish; anything earlier is dropped.
+    let Some(pool) = app.try_state::<SqlitePool>() else {
+        return;
+    };
+    let saved = match track {
+        ProgressTrack::Library { work_id, track_id } => save_progress(pool.inner(), work_id, track_id, position).await,
+        // Files outside the library have no row to resume.
+        ProgressTrack::Path(path) => save_progress_at_path(pool.inner(), &path, position).await.map(|_| ()),
+    };
+    if let Err(e) = saved {
+        eprintln!("[Audio] Failed to save progress: {}", e);
+    }
+}
+
+/// Persist the resume position without relying on the frontend: every few seconds
+/// while playing, plus whatever pause/seek/stop flush through `progress_tx`.
+pub fn spawn_progress_writer(app: AppHandle) {
+    let rx = {
+        let state = app.state::<Mutex<AudioState>>();
+        let mut audio = lock_audio(&state);
+        audio.progress_rx.take()
+    };
+    let Some(mut rx) = rx else {
+        return;
+    };
+
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(PROGRESS_SAVE_INTERVAL);
+        let mut last_saved: Option<ProgressRecord> = None;
+        loop {
+            let record = tokio::select! {
+                received = rx.recv() => match received {
+                    Some(record) => record,
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    let state = app.state::<Mutex<AudioState>>();
+                    let audio = lock_audio(&state);
+                    if !audio.is_playing() {
+                        continue;
+                    }
+                    match audio.current_progress() {
+                        Some(record) if last_saved.as_ref() != Some(&record) => record,
+                        _ => continue,
+                    }
+                }
+            };
+            write_progress(&app, record.clone()).await;
+            last_saved = Some(record);
+        }
+    });
+}
+
+/// Write the current position synchronously; called on app exit, when the
+/// runtime won't get a chance to run the writer task again.
+pub fn flush_progress_blocking(app: &AppHandle) {
+    let progress = {
+        let state = app.state::<Mutex<AudioState>>();
+        let audio = lock_audio(&state);
+        audio.current_progress()
+    };
+    if let Some(record) = progress {
+        tauri::async_runtime::block_on(write_progress(app, record));
+    }
+}
+
+fn lock_audio(state: &Mutex<AudioState>) -> MutexGuard<'_, AudioState> {
+    match state.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            eprintln!("Audio mutex poisoned, recovering...");
+            poisoned.into_inner()
         }
     }
 }
@@ -66,11 +1108,13 @@ where
     I: Source<Item = f32> + Send,
 {
     pub fn new(input: I, sender: broadcast::Sender<Vec<f32>>) -> Self {
+        // Whole frames per chunk, so the analyzer can tell the channels apart.
+        let buffer_size = 512 * input.channels().max(1) as usize;
         Self {
             input,
             sender,
-            buffer: Vec::with_capacity(1024),
-            buffer_size: 1024,
+            buffer: Vec::with_capacity(buffer_size),
+            buffer_size,
         }
     }
 }
@@ -83,13 +1127,13 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         let sample = self.input.next()?;
-        
+
         self.buffer.push(sample);
         if self.buffer.len() >= self.buffer_size {
             let _ = self.sender.send(self.buffer.clone());
             self.buffer.clear();
         }
-        
+
         Some(sample)
     }
 }
@@ -113,110 +1157,395 @@ where
     fn total_duration(&self) -> Option<Duration> {
         self.input.total_duration()
     }
-}
 
-fn setup_sink_and_play(
-    sink: &Sink,
-    app_handle: AppHandle,
-    path: &str,
-    skip_seconds: f32,
-) -> Result<(), String> {
-    println!("[Audio] Attempting to play: {}", path);
-    
-    // Read entire file to memory to prevent seek errors on initialization with Symphonia
-    let file_bytes = std::fs::read(path).map_err(|e| {
-        eprintln!("[Audio] Failed to read file: {}", e);
-        e.to_string()
-    })?;
-    
-    println!("[Audio] File read successfully, size: {} bytes", file_bytes.len());
-    
-    let cursor = Cursor::new(file_bytes);
-    let source_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| Decoder::new(cursor)))
-        .map_err(|e| {
-            eprintln!("[Audio] Panic during audio decoding: {:?}", e);
-            "Panic during audio decoding".to_string()
-        })?;
-    let source = source_result.map_err(|e| {
-        eprintln!("[Audio] Decoder error: {}", e);
-        e.to_string()
-    })?;
-    
-    println!("[Audio] Decoder created successfully");
-    
-    // rodio 0.17 convert_samples
-    let source_f32 = source.convert_samples::<f32>();
-    
-    // Get metadata BEFORE consuming explicit source
-    let sample_rate = source_f32.sample_rate();
-    let channels = source_f32.channels();
-    // let total_duration = source_f32.total_duration(); // Avoid calling this to prevent symphonia seek panic on m4a
-
-    // Emit duration (Optional - The frontend relies on DB mostly now)
-    // if let Some(duration) = total_duration {
-    //    let _ = app_handle.emit("track-duration", duration.as_secs_f64());
-    // }
-
-    // Skip if seeking (conditional to avoid panic on 0.0s skip with symphonia)
-    let source_skipped: Box<dyn Source<Item = f32> + Send> = if skip_seconds > 0.1 {
-        Box::new(source_f32.skip_duration(Duration::from_secs_f32(skip_seconds)))
-    } else {
-        Box::new(source_f32)
-    };
-
-    // Set up channels for visualizer and progress
-    // We increase buffer size to avoid lag? No, 16 is fine if we consume fast.
-    let (tx, mut rx) = broadcast::channel(32);
-    
-    let viz_source = VisualizerSource::new(source_skipped, tx);
-    
-    sink.append(viz_source);
-    sink.play();
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
 
-    tauri::async_runtime::spawn(async move {
-        // Calculate initial offset in samples
-        let mut processed_samples: u64 = 0;
-        let start_offset_seconds = skip_seconds as f64;
-        
-        let mut last_emit = Instant::now();
+/// Identifies a source in the sink so the queue worker can follow gapless transitions.
+struct QueueTag {
+    generation: u64,
+    entry_id: u64,
+    source_id: u64,
+    cancel: Arc<AtomicBool>,
+    // Silence to play before the track, e.g. the gap between two works.
+    lead_in: Duration,
+    failure: DecodeFailure,
+    events: mpsc::UnboundedSender<TrackEvent>,
+}
+
+// Reports when the wrapped track starts and runs dry. A preloaded track that gets
+// cancelled before it starts yields nothing, so the sink moves straight past it.
+// Any lead-in silence plays before the track counts as started.
+struct QueuedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    tag: QueueTag,
+    clock: TrackClock,
+    // Samples of lead-in silence still to play.
+    lead_in: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<I> Iterator for QueuedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            if self.tag.cancel.load(Ordering::Acquire) {
+                return None;
+            }
+            if self.lead_in > 0 {
+                self.lead_in -= 1;
+                return Some(0.0);
+            }
+            self.started = true;
+            let _ = self.tag.events.send(TrackEvent::Started {
+                generation: self.tag.generation,
+                entry_id: self.tag.entry_id,
+                source_id: self.tag.source_id,
+                clock: self.clock.clone(),
+            });
+        }
+
+        match self.input.next() {
+            Some(sample) => Some(sample),
+            None => {
+                if !self.finished {
+                    self.finished = true;
+                    let _ = self.tag.events.send(TrackEvent::Finished {
+                        generation: self.tag.generation,
+                        entry_id: self.tag.entry_id,
+                        error: self.tag.failure.take(),
+                    });
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<I> Source for QueuedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.lead_in > 0 {
+            return Some(self.lead_in);
+        }
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+/// Wrap a decoded track with the visualizer tap and queue tracking, append it to the
+/// sink and spawn the task that turns its samples into spectrum/progress events.
+/// Without an app there is nobody to send them to, so no task is spawned.
+fn append_to_sink(
+    sink: &OutputSink,
+    app_handle: Option<AppHandle>,
+    source: BoxedSource,
+    skip_seconds: f32,
+    latency: OutputLatency,
+    controls: SourceControls,
+    tag: QueueTag,
+) {
+    // Get metadata BEFORE consuming explicit source
+    let sample_rate = source.sample_rate();
+    let channels = source.channels() as usize;
+
+    // Set up channels for visualizer and progress
+    // We increase buffer size to avoid lag? No, 16 is fine if we consume fast.
+    let (tx, mut rx) = broadcast::channel(32);
+
+    let start = Duration::from_secs_f32(skip_seconds.max(0.0));
+    let playback = PlaybackClock::new(start, latency);
+    let clock = TrackClock {
+        entry_id: tag.entry_id,
+        playback: playback.clone(),
+        duration: source.total_duration().map(|d| d.as_secs_f64()),
+    };
+    let entry_id = tag.entry_id;
+    let duration = clock.duration;
+    let stretched = SpeedSource::new(source, controls.speed, playback.media_rate().clone());
+    let clocked = ClockSource::new(stretched, start, playback.clone());
+    let trimmed = TrimSource::new(clocked, controls.trim, playback.clone());
+    let looped = LoopSource::new(trimmed, controls.looping, playback.clone());
+    let (events, generation) = (tag.events.clone(), tag.generation);
+    let faded = FadeSource::new(
+        looped,
+        controls.fades,
+        playback.clone(),
+        Box::new(move || {
+            let _ = events.send(TrackEvent::FadingOut { generation, entry_id });
+        }),
+    );
+    // Normalization first so the limiter at the end of the DSP chain catches its boosts.
+    let normalized = GainSource::new(faded, controls.gain);
+    let stereo = FrameSource::new(normalized, StereoStage::new(controls.stereo));
+    let processed = FrameSource::new(stereo, DspChain::new(controls.dsp));
+    let mut analyzer = SpectrumAnalyzer::new(controls.spectrum, processed.sample_rate(), processed.channels());
+    let viz_source = VisualizerSource::new(processed, tx);
+
+    let lead_in = (tag.lead_in.as_secs_f64() * sample_rate as f64) as usize * channels;
+    sink.append(QueuedSource {
+        input: viz_source,
+        tag,
+        clock,
+        lead_in,
+        started: false,
+        finished: false,
+    });
+
+    let Some(app_handle) = app_handle else {
+        return;
+    };
+    tauri::async_runtime::spawn(async move {
+        let mut last_emit = Instant::now();
 
         while let Ok(samples) = rx.recv().await {
-             let chunk_len = samples.len() as u64;
-             processed_samples += chunk_len;
-
-             // 1. FFT
-             let spectrum = samples_fft_to_spectrum(
-                 &samples,
-                 sample_rate, 
-                 FrequencyLimit::Range(20., 20000.),
-                 Some(&divide_by_N),
-             );
-
-             if let Ok(spec) = spectrum {
-                 let mut data: Vec<f32> = spec.data().iter().map(|(_, val)| val.val()).collect();
-                 if data.len() > 100 {
-                     data.truncate(100); 
-                 }
-                 let _ = app_handle.emit("spectrum-update", data);
+             // 1. Spectrum, at most `max_fps` times a second
+             if let Some(frame) = analyzer.push(&samples, Instant::now()) {
+                 let _ = app_handle.emit("spectrum-update", frame.combined());
+                 let _ = app_handle.emit("spectrum-frame", frame);
              }
 
              // 2. Progress
              // Only emit every ~250ms or so to save bandwidth
              if last_emit.elapsed().as_millis() > 250 {
-                 let samples_per_sec = (sample_rate as u64) * (channels as u64);
-                 if samples_per_sec > 0 {
-                     let elapsed_seconds = processed_samples as f64 / samples_per_sec as f64;
-                     let total_current_time = start_offset_seconds + elapsed_seconds;
-                     let _ = app_handle.emit("playback-progress", total_current_time);
-                 }
+                 // What the device has played, not what the decoder has handed out.
+                 let position = playback.position().as_secs_f64();
+                 let _ = app_handle.emit("playback-progress", position);
+                 let _ = app_handle.emit(
+                     "playback-position",
+                     PlaybackPosition {
+                         entry_id: Some(entry_id),
+                         position,
+                         duration,
+                         speed: playback.speed() as f64,
+                         paused: false,
+                     },
+                 );
                  last_emit = Instant::now();
              }
         }
     });
-    
-    Ok(())
 }
 
+fn emit_queue_changed(app: &AppHandle, audio: &AudioState) {
+    let _ = app.emit("queue-changed", audio.queue.snapshot());
+}
+
+fn emit_player_event(app: &AppHandle, event: PlayerEvent) {
+    let name = event.name();
+    let _ = match event {
+        PlayerEvent::StateChanged(state) => app.emit(name, state),
+        PlayerEvent::TrackEnded(ended) => app.emit(name, ended),
+        PlayerEvent::TrackError(error) => app.emit(name, error),
+    };
+}
+
+fn emit_track_error(app: &AppHandle, entry: &QueueEntry, error: &str) {
+    emit_player_event(app, PlayerEvent::error(entry, error));
+}
+
+fn emit_track_changed(app: &AppHandle, audio: &AudioState) {
+    if let (Some(index), Some(entry)) = (audio.queue.current_index(), audio.queue.current()) {
+        let _ = app.emit(
+            "track-changed",
+            TrackChanged {
+                index,
+                entry: entry.clone(),
+            },
+        );
+    }
+}
+
+/// Decoding reads the file, so keep it off the async runtime and outside the state lock.
+fn schedule_preload(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || preload_next(&app));
+}
+
+/// Decode the entry after the current one and append it behind the current track.
+fn preload_next(app: &AppHandle) {
+    let state = app.state::<Mutex<AudioState>>();
+    let Some(job) = lock_audio(&state).preload_job() else {
+        return;
+    };
+    let opened = match open_tracked(&job.entry.track.path, job.offset) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("[Audio] Failed to preload {}: {}", job.entry.track.path, e);
+            emit_track_error(app, &job.entry, &e);
+            return;
+        }
+    };
+    lock_audio(&state).finish_preload(Some(app), job, opened);
+}
+
+fn handle_track_event(app: &AppHandle, event: TrackEvent) {
+    let state = app.state::<Mutex<AudioState>>();
+    let mut audio = lock_audio(&state);
+
+    match event {
+        TrackEvent::Started { generation, entry_id, source_id, clock } => {
+            if generation != audio.generation {
+                return;
+            }
+            // The audio thread is the source of truth for what is audible.
+            if !audio.queue.set_current_entry(entry_id) {
+                return;
+            }
+            if audio.preloaded.as_ref().is_some_and(|p| p.entry_id == entry_id) {
+                audio.preloaded = None;
+            }
+            // The previous track has left the sink.
+            audio.sources.retain(|&id, _| id == source_id);
+            audio.current_path = audio.queue.current().map(|e| e.track.path.clone());
+            audio.clock = Some(clock);
+            audio.drop_stale_loop();
+            if audio.sink.as_ref().is_some_and(|s| !s.is_paused()) {
+                audio.set_playback_state(PlaybackState::Playing);
+            }
+            emit_track_changed(app, &audio);
+            emit_queue_changed(app, &audio);
+            drop(audio);
+            schedule_preload(app);
+        }
+        TrackEvent::Finished { generation, entry_id, error } => {
+            if generation != audio.generation {
+                return;
+            }
+            if let Some(entry) = audio.entry_by_id(entry_id) {
+                for event in events::finished(entry, error.as_deref()) {
+                    emit_player_event(app, event);
+                }
+            }
+            // A crossfade that never started (e.g. the duration was unknown) leaves
+            // the next entry to be started here.
+            if audio.preloaded.as_ref().is_some_and(|p| !p.crossfade)
+                || audio.queue.current().map(|e| e.entry_id) != Some(entry_id)
+            {
+                return;
+            }
+            let stop_here = match (&audio.sleep_timer, audio.queue.current_index()) {
+                (Some(timer), Some(current)) => timer.stops_after(&audio.queue, current),
+                _ => false,
+            };
+            if stop_here {
+                let progress = audio.fire_sleep_timer(app, true);
+                audio.persist_progress(progress);
+                emit_queue_changed(app, &audio);
+                return;
+            }
+            // Nothing was preloaded (decode failed or the track was shorter than the
+            // preload took), so fall back to starting the next entry directly.
+            match audio.queue.next_index() {
+                Some(next) => {
+                    if let Err(e) = audio.start_entry(app, next, 0.0) {
+                        eprintln!("[Audio] Failed to start next track: {}", e);
+                    }
+                    emit_queue_changed(app, &audio);
+                }
+                None => {
+                    audio.current_path = None;
+                    audio.set_playback_state(PlaybackState::Stopped);
+                }
+            }
+        }
+        TrackEvent::FadingOut { generation, entry_id } => {
+            if generation != audio.generation
+                || audio.queue.current().map(|e| e.entry_id) != Some(entry_id)
+                || !audio.preloaded.as_ref().is_some_and(|p| p.crossfade)
+            {
+                return;
+            }
+            let (Handover::Crossfade(duration), Some(next)) = (audio.handover(), audio.queue.next_index()) else {
+                return;
+            };
+            // The outgoing sink's own end is stale by then, so the track ends here.
+            if let Some(entry) = audio.queue.current() {
+                emit_player_event(app, PlayerEvent::ended(entry));
+            }
+            if let Err(e) = audio.crossfade_to(app, next, duration) {
+                eprintln!("[Audio] Failed to crossfade into the next track: {}", e);
+            }
+            emit_queue_changed(app, &audio);
+        }
+    }
+}
+
+/// Follow output devices coming and going. Runs on the output thread, which is why
+/// nothing may wait on `Output::open` while holding the audio lock.
+pub fn spawn_output_watcher(app: AppHandle) {
+    let output = {
+        let state = app.state::<Mutex<AudioState>>();
+        let audio = lock_audio(&state);
+        audio.output.clone()
+    };
+    output.watch(Box::new(move |opened| {
+        let state = app.state::<Mutex<AudioState>>();
+        let mut audio = lock_audio(&state);
+        audio.switch_output(&app, opened);
+    }));
+}
+
+/// Start following track transitions reported by the audio thread.
+pub fn spawn_queue_worker(app: AppHandle) {
+    let rx = {
+        let state = app.state::<Mutex<AudioState>>();
+        let mut audio = lock_audio(&state);
+        audio.app_handle = Some(app.clone());
+        audio.events_rx.take()
+    };
+    let Some(mut rx) = rx else {
+        return;
+    };
+
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            handle_track_event(&app, event);
+        }
+    });
+}
+
+/// The library track stored at `path`. None if it isn't in the library or the database
+/// isn't ready.
+async fn lookup_track(app: &AppHandle, path: &str) -> Option<QueueTrack> {
+    let pool = app.try_state::<SqlitePool>()?;
+    match bookmarks::load_track_by_path(pool.inner(), path).await {
+        Ok(track) => track,
+        Err(e) => {
+            eprintln!("[Audio] Failed to look up track {}: {}", path, e);
+            None
+        }
+    }
+}
 
 #[tauri::command]
 pub async fn play_track(
@@ -224,89 +1553,1285 @@ pub async fn play_track(
     state: State<'_, Mutex<AudioState>>,
     path: String,
 ) -> Result<(), String> {
-    let mut audio = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => {
-            eprintln!("Audio mutex poisoned, recovering...");
-            poisoned.into_inner()
+    // A bare path replaces the queue with a single entry: the library track stored there,
+    // so progress and per-work settings apply, or an anonymous one for an outside file.
+    let track = match lookup_track(&app, &path).await {
+        Some(track) => track,
+        None => {
+            let title = std::path::Path::new(&path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            QueueTrack {
+                id: 0,
+                work_id: None,
+                title,
+                path,
+                duration: 0.0,
+                work_title: None,
+                cover_path: None,
+            }
         }
     };
+    let tracks = vec![track];
+    let lookup = fetch_work_settings(&app, &tracks).await;
+
+    let mut audio = lock_audio(&state);
     audio.app_handle = Some(app.clone());
-    audio.current_path = Some(path.clone());
-    
-    // Reset sink
-    // Reset sink
-    if let Some(ref handle) = audio.stream_handle {
-         let new_sink = Sink::try_new(handle).map_err(|e| e.to_string())?;
-         audio.sink = Some(new_sink);
+    audio.merge_work_settings(lookup);
+    audio.queue.replace(tracks, 0);
+    emit_queue_changed(&app, &audio);
+    audio.start_entry(&app, 0, 0.0)
+}
+
+#[tauri::command]
+pub fn pause_track(state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.pause();
+    audio.flush_progress();
+    if let Some(ref app) = audio.app_handle {
+        audio.emit_position(app);
     }
+    Ok(())
+}
 
-    if let Some(ref sink) = audio.sink {
-        setup_sink_and_play(sink, app.clone(), &path, 0.0)?;
+#[tauri::command]
+pub fn resume_track(state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.resume();
+    if let Some(ref app) = audio.app_handle {
+        audio.emit_position(app);
     }
-    
     Ok(())
 }
 
+/// `seconds` is track time, whatever the playback speed.
 #[tauri::command]
-pub fn pause_track(state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
-    let audio = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => poisoned.into_inner(),
+pub fn seek_track(app: AppHandle, state: State<'_, Mutex<AudioState>>, seconds: f32) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.seek(&app, seconds)
+}
+
+/// Position the listener is hearing right now, corrected for output latency.
+#[tauri::command]
+pub fn get_playback_position(state: State<'_, Mutex<AudioState>>) -> Result<PlaybackPosition, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.playback_position())
+}
+
+/// Whether the player is playing, paused, stopped or buffering, with the current entry.
+#[tauri::command]
+pub fn get_playback_state(state: State<'_, Mutex<AudioState>>) -> Result<PlaybackStatus, String> {
+    let audio = lock_audio(&state);
+    Ok(PlaybackStatus {
+        state: audio.playback_state,
+        current_index: audio.queue.current_index(),
+        entry: audio.queue.current().cloned(),
+        position: audio.playback_position(),
+        volume: audio.volume,
+    })
+}
+
+#[tauri::command]
+pub fn set_volume(state: State<'_, Mutex<AudioState>>, volume: f32) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.volume = volume;
+    let gain = audio.fade_gain;
+    audio.set_fade_gain(gain);
+    Ok(())
+}
+
+// ============ Desktop media controls ============
+
+/// The current entry as the desktop's media controls describe it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaTrack {
+    pub entry_id: u64,
+    pub work_id: Option<i64>,
+    pub title: String,
+    pub path: String,
+    pub work_title: Option<String>,
+    pub cover_path: Option<String>,
+    pub duration: Option<f64>,
+}
+
+/// Player state for the MPRIS server; `position` is track time.
+#[derive(Debug, Clone)]
+pub struct MediaStatus {
+    pub state: PlaybackState,
+    pub track: Option<MediaTrack>,
+    pub position: f64,
+    pub speed: f64,
+    pub volume: f32,
+    pub can_go_next: bool,
+    pub can_go_previous: bool,
+}
+
+pub fn media_status(app: &AppHandle) -> MediaStatus {
+    let state = app.state::<Mutex<AudioState>>();
+    let audio = lock_audio(&state);
+    let position = audio.playback_position();
+    let track = audio.queue.current().map(|entry| MediaTrack {
+        entry_id: entry.entry_id,
+        work_id: entry.track.work_id,
+        title: entry.track.title.clone(),
+        path: entry.track.path.clone(),
+        work_title: entry.track.work_title.clone(),
+        cover_path: entry.track.cover_path.clone(),
+        // The clock knows the decoded length; the scanned one is rounded to seconds.
+        duration: position.duration.or((entry.track.duration > 0.0).then_some(entry.track.duration)),
+    });
+    MediaStatus {
+        state: audio.playback_state,
+        track,
+        position: position.position,
+        speed: position.speed,
+        volume: audio.volume,
+        can_go_next: audio.queue.skip_index().is_some(),
+        can_go_previous: audio.queue.previous_index().is_some(),
+    }
+}
+
+/// Resume, or start the current entry again if playback was stopped.
+pub fn media_play(app: &AppHandle) -> Result<(), String> {
+    let state = app.state::<Mutex<AudioState>>();
+    let mut audio = lock_audio(&state);
+    if audio.current_path.is_some() {
+        audio.resume();
+        audio.emit_position(app);
+        return Ok(());
+    }
+    match audio.queue.current_index() {
+        Some(index) => audio.start_entry(app, index, 0.0),
+        None => Ok(()),
+    }
+}
+
+pub fn media_stop(app: &AppHandle) {
+    let state = app.state::<Mutex<AudioState>>();
+    lock_audio(&state).stop();
+}
+
+// ============ Output device ============
+
+#[tauri::command]
+pub async fn list_output_devices() -> Result<Vec<OutputDevice>, String> {
+    tauri::async_runtime::spawn_blocking(output::list_devices)
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn get_output_device(state: State<'_, Mutex<AudioState>>) -> Result<OutputStatus, String> {
+    Ok(lock_audio(&state).output_status())
+}
+
+/// Play through `device`, or follow the system default with None. The choice is
+/// remembered, and playback moves back to the device whenever it is plugged in again.
+#[tauri::command]
+pub async fn set_output_device(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    device: Option<String>,
+) -> Result<OutputStatus, String> {
+    if let Some(ref name) = device {
+        let devices = list_output_devices().await?;
+        if !devices.iter().any(|d| &d.name == name) {
+            return Err(format!("Output device not found: {}", name));
+        }
+    }
+    let opened = open_output(&state, device.clone()).await?;
+    match device {
+        Some(ref name) => save_setting(pool.inner(), OUTPUT_DEVICE_KEY, name).await,
+        None => delete_setting(pool.inner(), OUTPUT_DEVICE_KEY).await,
+    }
+    .map_err(|e| e.to_string())?;
+
+    let mut audio = lock_audio(&state);
+    audio.preferred_output = device;
+    audio.switch_output(&app, Some(opened));
+    Ok(audio.output_status())
+}
+
+/// Opening blocks on the output thread, so it happens off the runtime and outside the lock.
+async fn open_output(state: &Mutex<AudioState>, device: Option<String>) -> Result<OpenedOutput, String> {
+    let output = lock_audio(state).output.clone();
+    tauri::async_runtime::spawn_blocking(move || output.open(device))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// ============ Render ============
+
+/// Play into a WAV file, or into nothing with no path, instead of the device.
+/// Whatever is playing moves over at its current position.
+#[tauri::command]
+pub fn start_render(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    settings: RenderSettings,
+) -> Result<RenderStatus, String> {
+    lock_audio(&state).start_render(&app, settings)
+}
+
+/// Cancel a running export, or else close the render and go back to the device.
+/// None if nothing was rendering.
+#[tauri::command]
+pub fn stop_render(app: AppHandle, state: State<'_, Mutex<AudioState>>) -> Result<Option<RenderSummary>, String> {
+    let mut audio = lock_audio(&state);
+    Ok(audio.end_export(&app).or_else(|| audio.end_render(&app)))
+}
+
+#[tauri::command]
+pub fn get_render_status(state: State<'_, Mutex<AudioState>>) -> Result<Option<RenderStatus>, String> {
+    let audio = lock_audio(&state);
+    let export = audio.export.as_ref().map(|e| e.output.status());
+    Ok(export.or_else(|| audio.render.as_ref().map(RenderOutput::status)))
+}
+
+/// Render a whole work, with its normalization, DSP, speed and silence trimming, into
+/// one file as fast as it decodes. Runs beside playback without touching the queue; the
+/// work is written once in order whatever repeat and shuffle are set to.
+/// `render-finished` is emitted when it is done.
+#[tauri::command]
+pub async fn export_work(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    work_id: i64,
+    settings: RenderSettings,
+) -> Result<RenderStatus, String> {
+    if settings.path.is_none() {
+        return Err("Export needs a file to write to".to_string());
+    }
+    if lock_audio(&state).export.is_some() {
+        return Err("An export is already running".to_string());
+    }
+    let tracks = bookmarks::load_work_queue(pool.inner(), work_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    if tracks.is_empty() {
+        return Err(format!("Work {} has no tracks", work_id));
+    }
+    let lookup = fetch_work_settings(&app, &tracks).await;
+    let plan = {
+        let mut audio = lock_audio(&state);
+        audio.merge_work_settings(lookup);
+        audio.export_tracks(&tracks)
     };
-    if let Some(ref sink) = audio.sink {
-        sink.pause();
+
+    // Open every track before writing anything, so a missing file fails the export up front.
+    let sources = tauri::async_runtime::spawn_blocking(move || {
+        plan.into_iter().map(ExportTrack::open).collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let mut audio = lock_audio(&state);
+    if audio.export.is_some() {
+        return Err("An export is already running".to_string());
+    }
+    let output = RenderOutput::start(RenderSettings {
+        realtime: false,
+        ..settings
+    })?;
+    let sink = output.sink();
+    for source in sources {
+        sink.append(source);
+    }
+    output.set_active(true);
+    let status = output.status();
+    audio.export = Some(Export { output, sink });
+    drop(audio);
+
+    spawn_export_watch(app);
+    Ok(status)
+}
+
+/// Close the export once its sink has played out the last track.
+fn spawn_export_watch(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(EXPORT_POLL_INTERVAL).await;
+            let state = app.state::<Mutex<AudioState>>();
+            let mut audio = lock_audio(&state);
+            match audio.export {
+                Some(ref export) if !export.sink.empty() => continue,
+                Some(_) => {
+                    audio.end_export(&app);
+                }
+                None => {}
+            }
+            break;
+        }
+    });
+}
+
+// ============ Queue commands ============
+
+#[tauri::command]
+pub fn get_queue(state: State<'_, Mutex<AudioState>>) -> Result<QueueSnapshot, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.queue.snapshot())
+}
+
+/// Look up loudness, silence and DSP overrides of the works behind `tracks` so they apply from
+/// the first sample. Always read afresh, since a rescan can change them at any time. None if
+/// the database isn't ready or no track belongs to a work.
+async fn fetch_work_settings(app: &AppHandle, tracks: &[QueueTrack]) -> Option<WorkLookup> {
+    let mut work_ids: Vec<i64> = tracks.iter().filter_map(|t| t.work_id).collect();
+    work_ids.sort_unstable();
+    work_ids.dedup();
+    if work_ids.is_empty() {
+        return None;
+    }
+
+    let pool = app.try_state::<SqlitePool>()?;
+    let pool = pool.inner();
+    let loudness = match load_loudness(pool, &work_ids).await {
+        Ok(lookup) => Some(lookup),
+        Err(e) => {
+            eprintln!("[Audio] Failed to load loudness: {}", e);
+            None
+        }
+    };
+    let silence = match load_silence(pool, &work_ids).await {
+        Ok(silence) => Some(silence),
+        Err(e) => {
+            eprintln!("[Audio] Failed to load track silence: {}", e);
+            None
+        }
+    };
+    let mut dsp = HashMap::new();
+    let mut speed = HashMap::new();
+    for &work_id in &work_ids {
+        match load_setting::<DspSettings>(pool, &work_dsp_key(work_id)).await {
+            Ok(settings) => {
+                dsp.insert(work_id, settings);
+            }
+            Err(e) => eprintln!("[Audio] Failed to load DSP settings for work {}: {}", work_id, e),
+        }
+        match load_setting::<SpeedSettings>(pool, &work_speed_key(work_id)).await {
+            Ok(settings) => {
+                speed.insert(work_id, settings);
+            }
+            Err(e) => eprintln!("[Audio] Failed to load playback speed for work {}: {}", work_id, e),
+        }
+    }
+    let tracks = tracks.iter().filter(|t| t.work_id.is_some()).map(|t| t.id).collect();
+    Some(WorkLookup { works: work_ids, tracks, loudness, dsp, speed, silence })
+}
+
+/// Replace the queue and start playing `start_index`.
+#[tauri::command]
+pub async fn queue_replace(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    tracks: Vec<QueueTrack>,
+    start_index: usize,
+) -> Result<(), String> {
+    let lookup = fetch_work_settings(&app, &tracks).await;
+    let mut audio = lock_audio(&state);
+    audio.merge_work_settings(lookup);
+    audio.queue.replace(tracks, start_index);
+    emit_queue_changed(&app, &audio);
+
+    match audio.queue.current_index() {
+        Some(index) => audio.start_entry(&app, index, 0.0),
+        None => {
+            audio.stop();
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn queue_enqueue(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    tracks: Vec<QueueTrack>,
+) -> Result<(), String> {
+    let lookup = fetch_work_settings(&app, &tracks).await;
+    let mut audio = lock_audio(&state);
+    audio.merge_work_settings(lookup);
+    audio.queue.enqueue(tracks);
+    let needs_preload = audio.resync_preload();
+    emit_queue_changed(&app, &audio);
+    drop(audio);
+    if needs_preload {
+        schedule_preload(&app);
     }
     Ok(())
 }
 
 #[tauri::command]
-pub fn resume_track(state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
-    let audio = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => poisoned.into_inner(),
+pub async fn queue_insert_next(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    tracks: Vec<QueueTrack>,
+) -> Result<(), String> {
+    let lookup = fetch_work_settings(&app, &tracks).await;
+    let mut audio = lock_audio(&state);
+    audio.merge_work_settings(lookup);
+    audio.queue.insert_next(tracks);
+    let needs_preload = audio.resync_preload();
+    emit_queue_changed(&app, &audio);
+    drop(audio);
+    if needs_preload {
+        schedule_preload(&app);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_remove(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    index: usize,
+) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    let was_playing = audio.current_path.is_some();
+    let outcome = audio.queue.remove(index)?;
+
+    let result = match (outcome, audio.queue.current_index()) {
+        (RemoveOutcome::CurrentReplaced, Some(next)) if was_playing => audio.start_entry(&app, next, 0.0),
+        (RemoveOutcome::CurrentRemovedAtEnd, _) => {
+            audio.stop();
+            Ok(())
+        }
+        _ => Ok(()),
     };
-    if let Some(ref sink) = audio.sink {
-        sink.play();
+    let needs_preload = audio.resync_preload();
+    emit_queue_changed(&app, &audio);
+    drop(audio);
+    if needs_preload {
+        schedule_preload(&app);
+    }
+    result
+}
+
+#[tauri::command]
+pub fn queue_move(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    from: usize,
+    to: usize,
+) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.queue.move_entry(from, to)?;
+    let needs_preload = audio.resync_preload();
+    emit_queue_changed(&app, &audio);
+    drop(audio);
+    if needs_preload {
+        schedule_preload(&app);
     }
     Ok(())
 }
 
 #[tauri::command]
-pub fn seek_track(app: AppHandle, state: State<'_, Mutex<AudioState>>, seconds: f32) -> Result<(), String> {
-    let mut audio = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => poisoned.into_inner(),
+pub fn queue_jump(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    index: usize,
+) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.start_entry(&app, index, 0.0)?;
+    emit_queue_changed(&app, &audio);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_next(app: AppHandle, state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    let Some(next) = audio.queue.skip_index() else {
+        return Ok(()); // End of queue
     };
-    
-    let path = if let Some(ref p) = audio.current_path {
-        p.clone()
-    } else {
-        return Ok(()); // Nothing playing
+    audio.start_entry(&app, next, 0.0)?;
+    emit_queue_changed(&app, &audio);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_previous(app: AppHandle, state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    // At the head of the queue "previous" restarts the current track.
+    let Some(previous) = audio.queue.previous_index() else {
+        return Ok(());
     };
+    audio.start_entry(&app, previous, 0.0)?;
+    emit_queue_changed(&app, &audio);
+    Ok(())
+}
 
-    // Recreate sink to clear current buffer and seek
-    // Recreate sink to clear current buffer and seek
-    if let Some(ref handle) = audio.stream_handle {
-         let new_sink = Sink::try_new(handle).map_err(|e| e.to_string())?;
-         audio.sink = Some(new_sink);
+#[tauri::command]
+pub fn queue_clear(app: AppHandle, state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.stop();
+    audio.queue.clear();
+    emit_queue_changed(&app, &audio);
+    Ok(())
+}
+
+// ============ Repeat / Shuffle ============
+
+/// Tell the frontend about a new repeat/shuffle mode, persist it and re-pick the
+/// preloaded entry, since what plays next has probably changed.
+async fn apply_playback_mode(
+    app: &AppHandle,
+    state: &Mutex<AudioState>,
+    pool: &SqlitePool,
+    mode: PlaybackMode,
+) -> Result<PlaybackMode, String> {
+    let needs_preload = {
+        let mut audio = lock_audio(state);
+        let needs_preload = audio.resync_preload();
+        let _ = app.emit("playback-mode-changed", mode);
+        emit_queue_changed(app, &audio);
+        needs_preload
+    };
+    if needs_preload {
+        schedule_preload(app);
     }
-    
-    if let Some(ref sink) = audio.sink {
-        setup_sink_and_play(sink, app.clone(), &path, seconds)?;
+    save_setting(pool, PLAYBACK_MODE_KEY, &mode)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(mode)
+}
+
+#[tauri::command]
+pub fn get_playback_mode(state: State<'_, Mutex<AudioState>>) -> Result<PlaybackMode, String> {
+    Ok(lock_audio(&state).queue.mode())
+}
+
+#[tauri::command]
+pub async fn set_repeat_mode(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    mode: RepeatMode,
+) -> Result<PlaybackMode, String> {
+    let playback_mode = {
+        let mut audio = lock_audio(&state);
+        audio.queue.set_repeat(mode);
+        audio.queue.mode()
+    };
+    apply_playback_mode(&app, &state, pool.inner(), playback_mode).await
+}
+
+/// Turn shuffle on or off. Without a `seed` a fresh one is picked; pass a previous
+/// seed back to get the same order again.
+#[tauri::command]
+pub async fn set_shuffle_mode(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    mode: ShuffleMode,
+    seed: Option<u64>,
+) -> Result<PlaybackMode, String> {
+    let seed = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let playback_mode = {
+        let mut audio = lock_audio(&state);
+        audio.queue.set_shuffle(mode, seed);
+        audio.queue.mode()
+    };
+    apply_playback_mode(&app, &state, pool.inner(), playback_mode).await
+}
+
+// ============ Normalization ============
+
+/// Restore persisted audio settings once the database is up.
+pub async fn load_settings(app: &AppHandle, pool: &SqlitePool) {
+    let normalization = restore_setting::<NormalizationSettings>(pool, NORMALIZATION_KEY).await;
+    let dsp = restore_setting::<DspSettings>(pool, DSP_KEY).await;
+    let stereo = restore_setting::<StereoSettings>(pool, STEREO_KEY).await;
+    let playback_mode = restore_setting::<PlaybackMode>(pool, PLAYBACK_MODE_KEY).await;
+    let transition = restore_setting::<TransitionSettings>(pool, TRANSITION_KEY).await;
+    let spectrum = restore_setting::<SpectrumSettings>(pool, SPECTRUM_KEY).await;
+    let skip_silence = restore_setting::<bool>(pool, SKIP_SILENCE_KEY).await;
+    let output_device = restore_setting::<String>(pool, OUTPUT_DEVICE_KEY).await;
+
+    let state = app.state::<Mutex<AudioState>>();
+    if let Some(device) = output_device {
+        // Falls back to the current device if this one isn't plugged in.
+        match open_output(&state, Some(device.clone())).await {
+            Ok(opened) => {
+                let mut audio = lock_audio(&state);
+                audio.preferred_output = Some(device);
+                audio.switch_output(app, Some(opened));
+            }
+            Err(e) => eprintln!("[Audio] Failed to open output device {}: {}", device, e),
+        }
+    }
+
+    let mut audio = lock_audio(&state);
+    if let Some(transition) = transition {
+        audio.transition = transition.clamped();
+    }
+    if let Some(skip_silence) = skip_silence {
+        audio.skip_silence = skip_silence;
+    }
+    if let Some(mode) = playback_mode {
+        audio.queue.set_repeat(mode.repeat);
+        audio.queue.set_shuffle(mode.shuffle, mode.seed);
     }
-    
+    if let Some(normalization) = normalization {
+        audio.normalization = normalization;
+    }
+    if let Some(dsp) = dsp {
+        audio.dsp = dsp;
+    }
+    if let Some(stereo) = stereo {
+        audio.stereo.set(stereo);
+    }
+    if let Some(spectrum) = spectrum {
+        audio.spectrum.set(spectrum.clamped());
+    }
+    audio.refresh_sources();
+}
+
+#[tauri::command]
+pub fn get_normalization(state: State<'_, Mutex<AudioState>>) -> Result<NormalizationSettings, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.normalization.clone())
+}
+
+/// Switch between off / track / work gain and set the target loudness. Applies to the
+/// track that is playing right away.
+#[tauri::command]
+pub async fn set_normalization(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    mut settings: NormalizationSettings,
+) -> Result<(), String> {
+    settings.target_lufs = settings.target_lufs.clamp(-40.0, 0.0);
+    save_setting(pool.inner(), NORMALIZATION_KEY, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Tracks queued before the database was ready have no loudness yet.
+    let queued: Vec<QueueTrack> = {
+        let audio = lock_audio(&state);
+        audio.queue.snapshot().entries.into_iter().map(|e| e.track).collect()
+    };
+    let lookup = fetch_work_settings(&app, &queued).await;
+
+    let mut audio = lock_audio(&state);
+    audio.merge_work_settings(lookup);
+    audio.normalization = settings;
+    audio.refresh_sources();
     Ok(())
 }
 
+// ============ Equalizer / DSP ============
+
 #[tauri::command]
-pub fn set_volume(state: State<'_, Mutex<AudioState>>, volume: f32) -> Result<(), String> {
-    let audio = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => poisoned.into_inner(),
+pub fn get_dsp_presets() -> Vec<DspPreset> {
+    presets()
+}
+
+/// The user's chain, or with `work_id` that work's override (None if it has none).
+#[tauri::command]
+pub async fn get_dsp_settings(
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    work_id: Option<i64>,
+) -> Result<Option<DspSettings>, String> {
+    let Some(work_id) = work_id else {
+        let audio = lock_audio(&state);
+        return Ok(Some(audio.dsp.clone()));
+    };
+    load_setting(pool.inner(), &work_dsp_key(work_id))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Save the user's chain, or with `work_id` an override for that work. Takes effect
+/// on the playing track without restarting it.
+#[tauri::command]
+pub async fn set_dsp_settings(
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    settings: DspSettings,
+    work_id: Option<i64>,
+) -> Result<(), String> {
+    let key = work_id.map_or_else(|| DSP_KEY.to_string(), work_dsp_key);
+    save_setting(pool.inner(), &key, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut audio = lock_audio(&state);
+    match work_id {
+        Some(work_id) => {
+            audio.work_dsp.insert(work_id, Some(settings));
+        }
+        None => audio.dsp = settings,
+    }
+    audio.refresh_sources();
+    Ok(())
+}
+
+/// Drop a work's override so it follows the user's chain again.
+#[tauri::command]
+pub async fn clear_work_dsp_settings(
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    work_id: i64,
+) -> Result<(), String> {
+    delete_setting(pool.inner(), &work_dsp_key(work_id))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut audio = lock_audio(&state);
+    audio.work_dsp.insert(work_id, None);
+    audio.refresh_sources();
+    Ok(())
+}
+
+// ============ Stereo ============
+
+#[tauri::command]
+pub fn get_stereo_settings(state: State<'_, Mutex<AudioState>>) -> Result<StereoSettings, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.stereo.get())
+}
+
+/// Balance, channel swap, mono downmix and crossfeed. Applied to the playing track
+/// on the next frame; nothing is re-decoded.
+#[tauri::command]
+pub async fn set_stereo_settings(
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    mut settings: StereoSettings,
+) -> Result<(), String> {
+    settings.balance = settings.balance.clamp(-1.0, 1.0);
+    save_setting(pool.inner(), STEREO_KEY, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let audio = lock_audio(&state);
+    audio.stereo.set(settings);
+    Ok(())
+}
+
+// ============ Transitions between works ============
+
+#[tauri::command]
+pub fn get_transition_settings(state: State<'_, Mutex<AudioState>>) -> Result<TransitionSettings, String> {
+    Ok(lock_audio(&state).transition.clone())
+}
+
+/// Crossfade or silence where the queue moves from one work to another. Tracks of
+/// the same work stay gapless.
+#[tauri::command]
+pub async fn set_transition_settings(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    settings: TransitionSettings,
+) -> Result<TransitionSettings, String> {
+    let settings = settings.clamped();
+    save_setting(pool.inner(), TRANSITION_KEY, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let needs_preload = {
+        let mut audio = lock_audio(&state);
+        audio.transition = settings.clone();
+        // Line the next entry up again under the new settings.
+        audio.drop_preload();
+        audio.resync_preload()
+    };
+    if needs_preload {
+        schedule_preload(&app);
+    }
+    Ok(settings)
+}
+
+// ============ Spectrum ============
+
+#[tauri::command]
+pub fn get_spectrum_settings(state: State<'_, Mutex<AudioState>>) -> Result<SpectrumSettings, String> {
+    Ok(lock_audio(&state).spectrum.get())
+}
+
+/// Band count, channel layout, smoothing and frame rate of the `spectrum-update` and
+/// `spectrum-frame` events. Applies to the track that is playing right away.
+#[tauri::command]
+pub async fn set_spectrum_settings(
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    settings: SpectrumSettings,
+) -> Result<SpectrumSettings, String> {
+    let settings = settings.clamped();
+    save_setting(pool.inner(), SPECTRUM_KEY, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+    lock_audio(&state).spectrum.set(settings.clone());
+    Ok(settings)
+}
+
+// ============ Waveform ============
+
+/// Peak/RMS overview of a track in `buckets` slices, at most `STORED_BUCKETS`. The
+/// file is decoded the first time and again whenever it changes on disk.
+#[tauri::command]
+pub async fn get_track_waveform(pool: State<'_, SqlitePool>, track_id: i64, buckets: usize) -> Result<Waveform, String> {
+    let pool = pool.inner();
+    let path: String = sqlx::query_scalar("SELECT path FROM tracks WHERE id = ?")
+        .bind(track_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Track {} not found", track_id))?;
+    let mtime = waveform::file_mtime(&path)?;
+
+    let cached = match waveform::load_waveform(pool, &path, mtime).await {
+        Ok(cached) => cached,
+        Err(e) => {
+            eprintln!("[Audio] Failed to read cached waveform of {}: {}", path, e);
+            None
+        }
+    };
+    let full = match cached {
+        Some(full) => full,
+        None => {
+            let decode_path = path.clone();
+            let full = tauri::async_runtime::spawn_blocking(move || waveform::analyze_waveform(&decode_path))
+                .await
+                .map_err(|e| e.to_string())??;
+            if let Err(e) = waveform::store_waveform(pool, &path, mtime, &full).await {
+                eprintln!("[Audio] Failed to cache waveform of {}: {}", path, e);
+            }
+            full
+        }
+    };
+    Ok(full.resample(buckets.min(STORED_BUCKETS)))
+}
+
+// ============ Silence skipping ============
+
+#[tauri::command]
+pub fn get_skip_silence(state: State<'_, Mutex<AudioState>>) -> Result<bool, String> {
+    Ok(lock_audio(&state).skip_silence)
+}
+
+/// Start tracks after their leading silence and move on before their trailing
+/// silence, as measured by the scanner. The playing track picks up the new end
+/// right away; its start only changes the next time it is loaded.
+#[tauri::command]
+pub async fn set_skip_silence(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    enabled: bool,
+) -> Result<bool, String> {
+    save_setting(pool.inner(), SKIP_SILENCE_KEY, &enabled)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let needs_preload = {
+        let mut audio = lock_audio(&state);
+        audio.skip_silence = enabled;
+        audio.refresh_sources();
+        // A preloaded track was opened at the old start.
+        audio.drop_preload();
+        audio.resync_preload()
     };
-    if let Some(ref sink) = audio.sink {
-        sink.set_volume(volume);
+    if needs_preload {
+        schedule_preload(&app);
     }
+    Ok(enabled)
+}
+
+// ============ Playback speed ============
+
+#[tauri::command]
+pub fn get_playback_speed(state: State<'_, Mutex<AudioState>>) -> Result<SpeedSettings, String> {
+    let audio = lock_audio(&state);
+    Ok(match audio.queue.current() {
+        Some(entry) => audio.speed_settings(entry.entry_id),
+        None => audio.speed.clone(),
+    })
+}
+
+/// Set the speed (0.5–2.0) of `work_id`, by default the work that is playing, and
+/// remember it for that work. Tracks without a work keep it until the app closes.
+/// `preserve_pitch` time-stretches instead of resampling; omitted, it stays as it was.
+#[tauri::command]
+pub async fn set_playback_speed(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    pool: State<'_, SqlitePool>,
+    speed: f32,
+    preserve_pitch: Option<bool>,
+    work_id: Option<i64>,
+) -> Result<SpeedSettings, String> {
+    if !speed.is_finite() {
+        return Err(format!("Invalid playback speed: {}", speed));
+    }
+    let (work_id, current) = {
+        let audio = lock_audio(&state);
+        let work_id = work_id.or_else(|| audio.queue.current().and_then(|e| e.track.work_id));
+        let current = match work_id {
+            Some(id) => audio.work_speed.get(&id).cloned().flatten(),
+            None => Some(audio.speed.clone()),
+        };
+        (work_id, current)
+    };
+    // A work that hasn't been queued yet may still have a remembered setting.
+    let current = match (current, work_id) {
+        (Some(current), _) => current,
+        (None, Some(id)) => load_setting(pool.inner(), &work_speed_key(id))
+            .await
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default(),
+        (None, None) => SpeedSettings::default(),
+    };
+
+    let settings = SpeedSettings {
+        speed: speed.clamp(MIN_SPEED, MAX_SPEED),
+        preserve_pitch: preserve_pitch.unwrap_or(current.preserve_pitch),
+    };
+    if let Some(id) = work_id {
+        save_setting(pool.inner(), &work_speed_key(id), &settings)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut audio = lock_audio(&state);
+    match work_id {
+        Some(id) => {
+            audio.work_speed.insert(id, Some(settings.clone()));
+        }
+        None => audio.speed = settings.clone(),
+    }
+    audio.refresh_sources();
+    audio.emit_position(&app);
+    Ok(settings)
+}
+
+// ============ A-B loop / bookmarks ============
+
+fn validate_region(start: f64, end: Option<f64>) -> Result<(), String> {
+    if !start.is_finite() || start < 0.0 {
+        return Err(format!("Invalid loop start: {}", start));
+    }
+    match end {
+        Some(end) if !end.is_finite() || end < start + MIN_LOOP_SECS => {
+            Err(format!("Loop end must be at least {}s after its start", MIN_LOOP_SECS))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Repeat `start..end` (track time) of the current track until cleared. The jump back
+/// happens in the audio thread, so the loop is seamless.
+#[tauri::command]
+pub fn set_ab_loop(state: State<'_, Mutex<AudioState>>, start: f64, end: f64) -> Result<AbLoop, String> {
+    validate_region(start, Some(end))?;
+    let mut audio = lock_audio(&state);
+    let entry = audio.queue.current().ok_or("Nothing is playing")?;
+    let ab_loop = AbLoop {
+        entry_id: entry.entry_id,
+        track_id: entry.track.id,
+        start,
+        end: Some(end),
+        bookmark_id: None,
+    };
+    audio.set_ab_loop(Some(ab_loop.clone()));
+    Ok(ab_loop)
+}
+
+#[tauri::command]
+pub fn clear_ab_loop(state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.set_ab_loop(None);
     Ok(())
 }
+
+#[tauri::command]
+pub fn get_ab_loop(state: State<'_, Mutex<AudioState>>) -> Result<Option<AbLoop>, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.ab_loop.clone())
+}
+
+/// Bookmarks of a track or a work; all bookmarks when neither is given.
+#[tauri::command]
+pub async fn list_bookmarks(
+    pool: State<'_, SqlitePool>,
+    track_id: Option<i64>,
+    work_id: Option<i64>,
+) -> Result<Vec<Bookmark>, String> {
+    bookmarks::list_bookmarks(pool.inner(), track_id, work_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Save a position (`end_sec` omitted) or a region of a track under `label`.
+#[tauri::command]
+pub async fn create_bookmark(
+    pool: State<'_, SqlitePool>,
+    track_id: i64,
+    start_sec: f64,
+    end_sec: Option<f64>,
+    label: Option<String>,
+) -> Result<Bookmark, String> {
+    validate_region(start_sec, end_sec)?;
+    let pool = pool.inner();
+    let id = bookmarks::create_bookmark(pool, track_id, start_sec, end_sec, label.as_deref().unwrap_or(""))
+        .await
+        .map_err(|e| e.to_string())?;
+    bookmarks::get_bookmark(pool, id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Bookmark vanished after saving".to_string())
+}
+
+#[tauri::command]
+pub async fn delete_bookmark(pool: State<'_, SqlitePool>, id: i64) -> Result<(), String> {
+    bookmarks::delete_bookmark(pool.inner(), id).await.map_err(|e| e.to_string())
+}
+
+/// Play a bookmark from its start, looping its region or leaving any loop. A track
+/// that isn't queued brings its whole work into the queue.
+async fn play_bookmark(app: &AppHandle, id: i64, looping: bool) -> Result<(), String> {
+    let pool = app.try_state::<SqlitePool>().ok_or("Database is not ready")?;
+    let bookmark = bookmarks::get_bookmark(pool.inner(), id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Bookmark {} not found", id))?;
+
+    let state = app.state::<Mutex<AudioState>>();
+    let queued = lock_audio(&state).queue.index_of_track(bookmark.track_id);
+    let index = match queued {
+        Some(index) => index,
+        None => {
+            let tracks = bookmarks::load_work_queue(pool.inner(), bookmark.work_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            let index = tracks
+                .iter()
+                .position(|t| t.id == bookmark.track_id)
+                .ok_or("Bookmarked track is no longer in the library")?;
+            let lookup = fetch_work_settings(app, &tracks).await;
+            let mut audio = lock_audio(&state);
+            audio.merge_work_settings(lookup);
+            audio.stop();
+            audio.queue.replace(tracks, index);
+            index
+        }
+    };
+
+    let mut audio = lock_audio(&state);
+    let start = bookmark.start_sec as f32;
+    let same_entry = audio.queue.current_index() == Some(index) && audio.current_path.is_some();
+    if !same_entry {
+        audio.start_entry(app, index, start)?;
+    }
+    if looping {
+        let entry = audio.queue.entry(index).ok_or("Nothing is playing")?;
+        let ab_loop = AbLoop {
+            entry_id: entry.entry_id,
+            track_id: entry.track.id,
+            start: bookmark.start_sec,
+            end: bookmark.end_sec,
+            bookmark_id: Some(bookmark.id),
+        };
+        // Before seeking, so an old loop can't pull playback back out of the new one.
+        audio.set_ab_loop(Some(ab_loop));
+    } else {
+        audio.set_ab_loop(None);
+    }
+    if same_entry {
+        audio.seek(app, start)?;
+        audio.resume();
+    }
+    emit_queue_changed(app, &audio);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn jump_to_bookmark(app: AppHandle, id: i64) -> Result<(), String> {
+    play_bookmark(&app, id, false).await
+}
+
+/// Play a bookmark and loop it; a bookmark without an end loops to the end of its track.
+#[tauri::command]
+pub async fn loop_bookmark(app: AppHandle, id: i64) -> Result<(), String> {
+    play_bookmark(&app, id, true).await
+}
+
+// ============ Sleep timer ============
+
+fn sleep_timer_status(audio: &AudioState, timer: &SleepTimer) -> SleepTimerStatus {
+    let position = audio.clock.as_ref().map(|c| c.seconds()).unwrap_or(0.0);
+    let duration = audio.clock.as_ref().and_then(|c| c.duration);
+    timer.status(timer.remaining(&audio.queue, position, duration, audio.current_speed()))
+}
+
+/// Drives the fade and the wall-clock deadline. Track/work timers fire from the
+/// queue worker when the sink runs dry; this task only fades them out.
+fn spawn_sleep_timer_task(app: AppHandle, timer_id: u64) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_tick: Option<Instant> = None;
+        loop {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            let state = app.state::<Mutex<AudioState>>();
+            let mut audio = lock_audio(&state);
+            let Some(timer) = SleepTimer::still_running(audio.sleep_timer.as_ref(), timer_id).cloned() else {
+                break;
+            };
+
+            let status = sleep_timer_status(&audio, &timer);
+            match timer.tick(status.remaining_secs) {
+                Tick::Fade(gain) => audio.set_fade_gain(gain),
+                Tick::Expired => {
+                    let progress = audio.fire_sleep_timer(&app, false);
+                    audio.persist_progress(progress);
+                    break;
+                }
+            }
+
+            if last_tick.is_none_or(|t| t.elapsed() >= Duration::from_secs(1)) {
+                let _ = app.emit("sleep-timer-tick", status);
+                last_tick = Some(Instant::now());
+            }
+        }
+    });
+}
+
+/// Start (or replace) the sleep timer. `fade_seconds` is the length of the volume
+/// ramp before playback pauses; it defaults to 30 seconds.
+#[tauri::command]
+pub fn set_sleep_timer(
+    app: AppHandle,
+    state: State<'_, Mutex<AudioState>>,
+    mode: SleepTimerMode,
+    fade_seconds: Option<f64>,
+) -> Result<SleepTimerStatus, String> {
+    let mut audio = lock_audio(&state);
+    audio.next_timer_id += 1;
+    let timer = SleepTimer::new(audio.next_timer_id, mode, fade_seconds.unwrap_or(30.0));
+
+    let timer_id = timer.id;
+    let status = sleep_timer_status(&audio, &timer);
+    audio.sleep_timer = Some(timer);
+    audio.set_fade_gain(1.0);
+    // Drops the preloaded next track if the timer ends playback before it.
+    let needs_preload = audio.resync_preload();
+    drop(audio);
+
+    if needs_preload {
+        schedule_preload(&app);
+    }
+    spawn_sleep_timer_task(app, timer_id);
+    Ok(status)
+}
+
+#[tauri::command]
+pub fn cancel_sleep_timer(app: AppHandle, state: State<'_, Mutex<AudioState>>) -> Result<(), String> {
+    let mut audio = lock_audio(&state);
+    audio.sleep_timer = None;
+    audio.set_fade_gain(1.0);
+    let needs_preload = audio.resync_preload();
+    drop(audio);
+
+    if needs_preload {
+        schedule_preload(&app);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_sleep_timer(state: State<'_, Mutex<AudioState>>) -> Result<Option<SleepTimerStatus>, String> {
+    let audio = lock_audio(&state);
+    Ok(audio.sleep_timer.as_ref().map(|t| sleep_timer_status(&audio, t)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::queue::tests::track;
+    use super::render::RenderFormat;
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("audio-test-{}-{}", std::process::id(), name))
+    }
+
+    /// A second of 8 kHz stereo held at `level`.
+    fn write_tone(path: &Path, level: f32) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..8_000 * 2 {
+            writer.write_sample((level * 32_768.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn queued_tracks_render_back_to_back() {
+        let (first, second, out) = (temp_path("a.wav"), temp_path("b.wav"), temp_path("mix.wav"));
+        write_tone(&first, 0.25);
+        write_tone(&second, 0.5);
+        let tracks = [(1, &first), (2, &second)]
+            .into_iter()
+            .map(|(id, path)| QueueTrack {
+                path: path.to_string_lossy().to_string(),
+                ..track(id, Some(1))
+            })
+            .collect();
+
+        let mut audio = AudioState::new();
+        audio.render = Some(
+            RenderOutput::start(RenderSettings {
+                path: Some(out.to_string_lossy().to_string()),
+                realtime: false,
+                sample_rate: 8_000,
+                channels: 2,
+                format: RenderFormat::Float32,
+            })
+            .unwrap(),
+        );
+        audio.queue.replace(tracks, 0);
+        // Paused until the second track is behind the first, as the audio thread would
+        // have it long before the first one ends.
+        audio.open_entry(None, 0, 0.0, false, None).unwrap();
+        let job = audio.preload_job().unwrap();
+        let opened = open_tracked(&job.entry.track.path, job.offset).unwrap();
+        audio.finish_preload(None, job, opened);
+        assert!(audio.preloaded.is_some());
+        audio.resume();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !audio.sink.as_ref().unwrap().empty() {
+            assert!(Instant::now() < deadline, "render stalled");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let summary = audio.render.take().unwrap().finish();
+        assert_eq!(summary.error, None);
+        let samples: Vec<f32> = hound::WavReader::open(&out).unwrap().samples().map(Result::unwrap).collect();
+        for path in [&first, &second, &out] {
+            std::fs::remove_file(path).ok();
+        }
+
+        // Two seconds, the first at the first track's level and the rest at the second's.
+        assert!((samples.len() as f64 / 16_000.0 - 2.0).abs() < 0.01, "{}", samples.len());
+        let join = samples.iter().position(|&s| s > 0.375).unwrap();
+        assert!((join as i64 - 16_000).abs() < 160, "{}", join);
+        assert!(samples[..join].iter().all(|&s| (s - 0.25).abs() < 0.01));
+        assert!(samples[join..].iter().all(|&s| (s - 0.5).abs() < 0.01));
+    }
+}
@@ -0,0 +1,128 @@
+use super::queue::QueueTrack;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// A named position, or region when `end_sec` is set, within a track.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Bookmark {
+    pub id: i64,
+    pub track_id: i64,
+    pub work_id: i64,
+    pub start_sec: f64,
+    pub end_sec: Option<f64>,
+    pub label: String,
+}
+
+const SELECT_BOOKMARKS: &str = r#"
+    SELECT b.id, b.track_id, t.work_id, b.start_sec, b.end_sec, b.label
+    FROM bookmarks b
+    JOIN tracks t ON t.id = b.track_id
+"#;
+
+/// Bookmarks of one track, of one work, or all of them, in playback order.
+pub async fn list_bookmarks(
+    pool: &SqlitePool,
+    track_id: Option<i64>,
+    work_id: Option<i64>,
+) -> Result<Vec<Bookmark>, sqlx::Error> {
+    let sql = format!(
+        "{} WHERE (?1 IS NULL OR b.track_id = ?1) AND (?2 IS NULL OR t.work_id = ?2)
+         ORDER BY t.work_id, t.title, b.start_sec",
+        SELECT_BOOKMARKS
+    );
+    sqlx::query_as::<_, Bookmark>(&sql)
+        .bind(track_id)
+        .bind(work_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_bookmark(pool: &SqlitePool, id: i64) -> Result<Option<Bookmark>, sqlx::Error> {
+    let sql = format!("{} WHERE b.id = ?", SELECT_BOOKMARKS);
+    sqlx::query_as::<_, Bookmark>(&sql).bind(id).fetch_optional(pool).await
+}
+
+pub async fn create_bookmark(
+    pool: &SqlitePool,
+    track_id: i64,
+    start_sec: f64,
+    end_sec: Option<f64>,
+    label: &str,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO bookmarks (track_id, start_sec, end_sec, label) VALUES (?, ?, ?, ?)")
+        .bind(track_id)
+        .bind(start_sec)
+        .bind(end_sec)
+        .bind(label)
+        .execute(pool)
+        .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn delete_bookmark(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM bookmarks WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct WorkTrackRow {
+    id: i64,
+    work_id: i64,
+    title: String,
+    path: String,
+    duration_sec: i64,
+    work_title: String,
+    cover_path: Option<String>,
+}
+
+/// The tracks of `work_id` in the order `get_work_tracks` lists them, ready to queue.
+pub async fn load_work_queue(pool: &SqlitePool, work_id: i64) -> Result<Vec<QueueTrack>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, WorkTrackRow>(
+        r#"
+        SELECT t.id, t.work_id, t.title, t.path, t.duration_sec, w.title AS work_title, w.cover_path
+        FROM tracks t
+        JOIN works w ON w.id = t.work_id
+        WHERE t.work_id = ?
+        ORDER BY t.title ASC
+        "#,
+    )
+    .bind(work_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(QueueTrack::from).collect())
+}
+
+/// The library track stored at `path`, ready to queue. None for files outside the library.
+pub async fn load_track_by_path(pool: &SqlitePool, path: &str) -> Result<Option<QueueTrack>, sqlx::Error> {
+    let row = sqlx::query_as::<_, WorkTrackRow>(
+        r#"
+        SELECT t.id, t.work_id, t.title, t.path, t.duration_sec, w.title AS work_title, w.cover_path
+        FROM tracks t
+        JOIN works w ON w.id = t.work_id
+        WHERE t.path = ?
+        "#,
+    )
+    .bind(path)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(QueueTrack::from))
+}
+
+impl From<WorkTrackRow> for QueueTrack {
+    fn from(r: WorkTrackRow) -> Self {
+        QueueTrack {
+            id: r.id,
+            work_id: Some(r.work_id),
+            title: r.title,
+            path: r.path,
+            duration: r.duration_sec as f64,
+            work_title: Some(r.work_title),
+            cover_path: r.cover_path,
+        }
+    }
+}
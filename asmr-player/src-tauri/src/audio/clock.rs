@@ -0,0 +1,477 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frames pulled between two looks at the wall clock.
+const CHECK_INTERVAL_FRAMES: u64 = 32;
+/// Pulls further apart than this belong to different output callbacks.
+const BURST_GAP: Duration = Duration::from_millis(2);
+/// Anything longer is a stall (or a resume after pause), not a device buffer.
+const MAX_LATENCY: Duration = Duration::from_millis(500);
+
+/// Estimated length of the output device buffer, i.e. how far the samples handed to
+/// the output run ahead of what is audible. Shared by every track on a stream.
+#[derive(Clone, Default)]
+pub struct OutputLatency(Arc<AtomicU64>);
+
+impl OutputLatency {
+    pub fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Fold one measured output callback into the running estimate.
+    pub fn observe(&self, burst: Duration) {
+        if burst.is_zero() || burst > MAX_LATENCY {
+            return;
+        }
+        let old = self.0.load(Ordering::Relaxed);
+        let new = burst.as_nanos() as u64;
+        let smoothed = if old == 0 { new } else { (old * 3 + new) / 4 };
+        self.0.store(smoothed, Ordering::Relaxed);
+    }
+}
+
+/// Media time per output frame of the samples a `ClockSource` is counting: 2.0 when a
+/// speed stage plays its input twice as fast. Written by that stage on the audio thread.
+#[derive(Clone)]
+pub struct MediaRate(Arc<AtomicU32>);
+
+impl Default for MediaRate {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU32::new(1.0f32.to_bits())))
+    }
+}
+
+impl MediaRate {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, rate: f32) {
+        self.0.store(rate.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Detects output callbacks from the timing of pulls. The device pulls a whole buffer
+/// in one go, so the media time between the starts of two bursts is one buffer.
+#[derive(Default)]
+struct BurstMeter {
+    last_check: Option<Instant>,
+    burst_start: Option<Duration>,
+}
+
+impl BurstMeter {
+    /// Returns the length of the previous burst when `now` starts a new one.
+    fn observe(&mut self, now: Instant, position: Duration) -> Option<Duration> {
+        let new_burst = self
+            .last_check
+            .is_none_or(|prev| now.saturating_duration_since(prev) > BURST_GAP);
+        self.last_check = Some(now);
+        if !new_burst {
+            return None;
+        }
+        let measured = self.burst_start.map(|start| position.saturating_sub(start));
+        self.burst_start = Some(position);
+        measured
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+struct ClockShared {
+    epoch: Instant,
+    // Media position of the newest sample handed to the output, in nanoseconds.
+    pulled: AtomicU64,
+    // When that happened, in nanoseconds since `epoch`.
+    pulled_at: AtomicU64,
+    // The last seek target; audio from before it is never reported again.
+    floor: AtomicU64,
+}
+
+/// Audible position of one track, written by its `ClockSource` on the audio thread.
+#[derive(Clone)]
+pub struct PlaybackClock {
+    shared: Arc<ClockShared>,
+    latency: OutputLatency,
+    rate: MediaRate,
+}
+
+impl PlaybackClock {
+    pub fn new(start: Duration, latency: OutputLatency) -> Self {
+        let clock = Self {
+            shared: Arc::new(ClockShared {
+                epoch: Instant::now(),
+                pulled: AtomicU64::new(0),
+                pulled_at: AtomicU64::new(0),
+                floor: AtomicU64::new(0),
+            }),
+            latency,
+            rate: MediaRate::default(),
+        };
+        clock.reset(start, clock.shared.epoch);
+        clock
+    }
+
+    /// Handle for the stage that decides how fast media time passes.
+    pub fn media_rate(&self) -> &MediaRate {
+        &self.rate
+    }
+
+    /// Current playback speed.
+    pub fn speed(&self) -> f32 {
+        self.rate.get()
+    }
+
+    /// Position of the newest sample handed to the output.
+    pub fn pulled(&self) -> Duration {
+        Duration::from_nanos(self.shared.pulled.load(Ordering::Relaxed))
+    }
+
+    /// Position that is audible right now.
+    pub fn position(&self) -> Duration {
+        self.position_at(Instant::now())
+    }
+
+    /// The device buffer holds `latency` worth of samples when a pull finishes and
+    /// drains in real time until the next one. Once pulls stop (paused or run dry)
+    /// the position settles on the last sample pulled. Both are wall-clock spans, so
+    /// at other speeds they cover proportionally more or less of the track.
+    pub fn position_at(&self, now: Instant) -> Duration {
+        let shared = &self.shared;
+        let pulled = self.pulled();
+        let pulled_at = shared.epoch + Duration::from_nanos(shared.pulled_at.load(Ordering::Relaxed));
+        let floor = Duration::from_nanos(shared.floor.load(Ordering::Relaxed));
+        let latency = self.latency.get();
+        let rate = self.rate.get().max(0.0) as f64;
+
+        let drained = scale(now.saturating_duration_since(pulled_at).min(latency), rate);
+        let latency = scale(latency, rate);
+        let audible = (pulled.saturating_sub(latency) + drained).min(pulled);
+        audible.max(floor)
+    }
+
+    fn publish(&self, pulled: Duration, at: Instant) {
+        let shared = &self.shared;
+        let at = at.saturating_duration_since(shared.epoch);
+        shared.pulled.store(pulled.as_nanos() as u64, Ordering::Relaxed);
+        shared.pulled_at.store(at.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn reset(&self, position: Duration, at: Instant) {
+        self.shared.floor.store(position.as_nanos() as u64, Ordering::Relaxed);
+        self.publish(position, at);
+    }
+}
+
+fn scale(duration: Duration, rate: f64) -> Duration {
+    Duration::from_nanos((duration.as_nanos() as f64 * rate).round() as u64)
+}
+
+fn frames_to_duration(frames: u64, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos((frames as u128 * 1_000_000_000 / sample_rate as u128) as u64)
+}
+
+/// Counts the frames the output actually pulls and publishes them to a `PlaybackClock`.
+/// Sits right after the speed stage so it sees the native sample rate, which may change
+/// between spans, and converts played frames to media time at the stage's rate.
+pub struct ClockSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    clock: PlaybackClock,
+    // Media time at the start of the current span, and media frames counted since.
+    base: Duration,
+    span_media: f64,
+    // Same for frames handed to the output, which is what the device buffer holds.
+    output_base: Duration,
+    span_frames: u64,
+    sample_rate: u32,
+    channels: u16,
+    sample_in_frame: u16,
+    samples_left_in_span: Option<usize>,
+    bursts: BurstMeter,
+}
+
+impl<I> ClockSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, start: Duration, clock: PlaybackClock) -> Self {
+        let mut source = Self {
+            input,
+            clock,
+            base: start,
+            span_media: 0.0,
+            output_base: Duration::ZERO,
+            span_frames: 0,
+            sample_rate: 0,
+            channels: 0,
+            sample_in_frame: 0,
+            samples_left_in_span: None,
+            bursts: BurstMeter::default(),
+        };
+        source.read_format();
+        source
+    }
+
+    fn read_format(&mut self) {
+        self.sample_rate = self.input.sample_rate();
+        self.channels = self.input.channels().max(1);
+        self.samples_left_in_span = self.input.current_frame_len();
+    }
+
+    fn media_position(&self) -> Duration {
+        if self.sample_rate == 0 {
+            return self.base;
+        }
+        let nanos = self.span_media * 1e9 / self.sample_rate as f64;
+        self.base + Duration::from_nanos(nanos.round() as u64)
+    }
+
+    fn output_position(&self) -> Duration {
+        self.output_base + frames_to_duration(self.span_frames, self.sample_rate)
+    }
+
+    /// The format may change here, so bank the time counted at the old rate.
+    fn start_span(&mut self) {
+        self.base = self.media_position();
+        self.output_base = self.output_position();
+        self.span_media = 0.0;
+        self.span_frames = 0;
+        self.sample_in_frame = 0;
+        self.read_format();
+    }
+
+    fn publish(&mut self) {
+        let now = Instant::now();
+        let position = self.media_position();
+        if let Some(burst) = self.bursts.observe(now, self.output_position()) {
+            self.clock.latency.observe(burst);
+        }
+        self.clock.publish(position, now);
+    }
+}
+
+impl<I> Iterator for ClockSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.samples_left_in_span == Some(0) {
+            self.start_span();
+        }
+        let Some(sample) = self.input.next() else {
+            self.publish();
+            return None;
+        };
+
+        if let Some(ref mut left) = self.samples_left_in_span {
+            *left = left.saturating_sub(1);
+        }
+        self.sample_in_frame += 1;
+        if self.sample_in_frame >= self.channels {
+            self.sample_in_frame = 0;
+            self.span_frames += 1;
+            self.span_media += self.clock.rate.get() as f64;
+            if self.span_frames.is_multiple_of(CHECK_INTERVAL_FRAMES) {
+                self.publish();
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<I> Source for ClockSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.base = pos;
+        self.span_media = 0.0;
+        self.span_frames = 0;
+        self.sample_in_frame = 0;
+        self.read_format();
+        self.bursts.reset();
+        self.clock.reset(pos, Instant::now());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays `spans` of (sample_rate, channels, frames) back to back.
+    struct MockSource {
+        spans: Vec<(u32, u16, usize)>,
+        span: usize,
+        samples_left: usize,
+    }
+
+    impl MockSource {
+        fn new(spans: Vec<(u32, u16, usize)>) -> Self {
+            let samples_left = spans.first().map(|&(_, ch, frames)| ch as usize * frames).unwrap_or(0);
+            Self {
+                spans,
+                span: 0,
+                samples_left,
+            }
+        }
+    }
+
+    impl Iterator for MockSource {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            if self.samples_left == 0 {
+                return None;
+            }
+            self.samples_left -= 1;
+            // Like the decoder, move on to the next span as soon as this one is used up.
+            if self.samples_left == 0 && self.span + 1 < self.spans.len() {
+                self.span += 1;
+                let (_, ch, frames) = self.spans[self.span];
+                self.samples_left = ch as usize * frames;
+            }
+            Some(0.0)
+        }
+    }
+
+    impl Source for MockSource {
+        fn current_frame_len(&self) -> Option<usize> {
+            Some(self.samples_left)
+        }
+
+        fn channels(&self) -> u16 {
+            self.spans.get(self.span).map_or(1, |s| s.1)
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.spans.get(self.span).map_or(44100, |s| s.0)
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+
+        fn try_seek(&mut self, _pos: Duration) -> Result<(), SeekError> {
+            Ok(())
+        }
+    }
+
+    fn clock_source(spans: Vec<(u32, u16, usize)>) -> (ClockSource<MockSource>, PlaybackClock) {
+        let clock = PlaybackClock::new(Duration::ZERO, OutputLatency::default());
+        (ClockSource::new(MockSource::new(spans), Duration::ZERO, clock.clone()), clock)
+    }
+
+    fn pull(source: &mut ClockSource<MockSource>, samples: usize) {
+        for _ in 0..samples {
+            source.next();
+        }
+    }
+
+    #[test]
+    fn counts_frames_at_the_source_rate() {
+        let (mut source, clock) = clock_source(vec![(48000, 2, 96000)]);
+        pull(&mut source, 48000 * 2);
+        assert_eq!(clock.pulled(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn sample_rate_changes_between_spans_are_banked() {
+        let (mut source, clock) = clock_source(vec![(44100, 1, 44100), (22050, 2, 22050)]);
+        pull(&mut source, 44100 + 22050 * 2);
+        assert_eq!(source.next(), None);
+        assert_eq!(clock.pulled(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn seek_restarts_the_count_at_the_target() {
+        let (mut source, clock) = clock_source(vec![(8000, 1, 80000)]);
+        pull(&mut source, 8000);
+        assert_eq!(clock.pulled(), Duration::from_secs(1));
+
+        source.try_seek(Duration::from_secs(5)).unwrap();
+        assert_eq!(clock.pulled(), Duration::from_secs(5));
+        pull(&mut source, 4000);
+        assert_eq!(clock.pulled(), Duration::from_millis(5500));
+    }
+
+    #[test]
+    fn position_trails_pulled_samples_by_the_output_latency() {
+        let latency = OutputLatency::default();
+        latency.observe(Duration::from_millis(100));
+        let clock = PlaybackClock::new(Duration::ZERO, latency);
+        let t0 = Instant::now();
+        clock.publish(Duration::from_secs(2), t0);
+
+        assert_eq!(clock.position_at(t0), Duration::from_millis(1900));
+        assert_eq!(clock.position_at(t0 + Duration::from_millis(50)), Duration::from_millis(1950));
+        // No further pulls (paused): the buffer plays out and the clock stops.
+        assert_eq!(clock.position_at(t0 + Duration::from_secs(3)), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn media_time_follows_the_rate_and_latency_scales_with_it() {
+        let (mut source, clock) = clock_source(vec![(8000, 1, 80000)]);
+        clock.media_rate().set(2.0);
+        pull(&mut source, 8000);
+        assert_eq!(clock.pulled(), Duration::from_secs(2));
+
+        clock.latency.observe(Duration::from_millis(100));
+        let t0 = Instant::now();
+        clock.publish(Duration::from_secs(2), t0);
+        // 100 ms of output buffer holds 200 ms of the track at double speed.
+        assert_eq!(clock.position_at(t0), Duration::from_millis(1800));
+        assert_eq!(clock.position_at(t0 + Duration::from_millis(50)), Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn position_never_drops_below_a_seek_target() {
+        let latency = OutputLatency::default();
+        latency.observe(Duration::from_millis(100));
+        let clock = PlaybackClock::new(Duration::ZERO, latency);
+        let t0 = Instant::now();
+        clock.reset(Duration::from_secs(30), t0);
+        assert_eq!(clock.position_at(t0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn burst_meter_measures_one_callback() {
+        let mut meter = BurstMeter::default();
+        let t0 = Instant::now();
+        assert_eq!(meter.observe(t0, Duration::ZERO), None);
+        assert_eq!(meter.observe(t0 + Duration::from_micros(100), Duration::from_millis(5)), None);
+        assert_eq!(
+            meter.observe(t0 + Duration::from_millis(20), Duration::from_millis(20)),
+            Some(Duration::from_millis(20))
+        );
+    }
+}
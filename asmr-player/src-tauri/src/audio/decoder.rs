@@ -0,0 +1,492 @@
+use crate::archive::{self, EntryReader};
+use rodio::source::SeekError;
+use rodio::Source;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+pub type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+// A decode error on a single packet is recoverable; this many in a row is not.
+const MAX_DECODE_RETRIES: usize = 3;
+
+/// Why a stream stopped early. The decoder can only end its iterator, so it leaves
+/// the reason here for whoever reports the end of the track.
+#[derive(Clone, Default)]
+pub struct DecodeFailure(Arc<Mutex<Option<String>>>);
+
+impl DecodeFailure {
+    fn set(&self, error: String) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.get_or_insert(error);
+        }
+    }
+
+    pub fn take(&self) -> Option<String> {
+        self.0.lock().ok().and_then(|mut slot| slot.take())
+    }
+}
+
+/// A decoded stream and the slot its mid-stream errors end up in.
+pub struct OpenedSource {
+    pub source: BoxedSource,
+    pub failure: DecodeFailure,
+}
+
+/// Buffered, seekable handle on an audio file.
+///
+/// Unlike a bare `Read + Seek` wrapper this reports the real byte length, which
+/// Symphonia needs to estimate durations and to seek in MP3/M4A without scanning
+/// the whole file. Only the read buffer lives in memory, whatever the file size.
+pub struct FileSource {
+    reader: BufReader<File>,
+    len: u64,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            reader: BufReader::with_capacity(64 * 1024, file),
+            len,
+        })
+    }
+}
+
+impl Read for FileSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for FileSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl MediaSource for FileSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
+
+/// Tracks inside archives are streamed out of them, decompressing as they are read.
+impl MediaSource for EntryReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len())
+    }
+}
+
+/// rodio `Source` that decodes straight from a Symphonia `MediaSource`.
+///
+/// rodio's own `Decoder` only accepts `Read + Seek` and hides the byte length from
+/// Symphonia, so we drive the format reader and codec ourselves.
+pub struct SymphoniaSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    spec: SignalSpec,
+    buffer: SampleBuffer<f32>,
+    offset: usize,
+    total_duration: Option<Duration>,
+    ended: bool,
+    failure: DecodeFailure,
+}
+
+impl SymphoniaSource {
+    pub fn new(media: Box<dyn MediaSource>, extension: Option<&str>) -> Result<Self, String> {
+        let mut hint = Hint::new();
+        if let Some(ext) = extension {
+            hint.with_extension(ext);
+        }
+
+        let mss = MediaSourceStream::new(media, Default::default());
+        let format_opts = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &format_opts, &MetadataOptions::default())
+            .map_err(|e| e.to_string())?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or("No audio track with a supported codec")?;
+        let track_id = track.id;
+        let total_duration = track
+            .codec_params
+            .time_base
+            .zip(track.codec_params.n_frames)
+            .map(|(base, frames)| {
+                let time = base.calc_time(frames);
+                Duration::from_secs_f64(time.seconds as f64 + time.frac)
+            });
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| e.to_string())?;
+
+        // Placeholder until the first packet tells us the real signal spec.
+        let spec = SignalSpec::new(0, Channels::FRONT_LEFT);
+        let mut source = Self {
+            format,
+            decoder,
+            track_id,
+            spec,
+            buffer: SampleBuffer::new(0, spec),
+            offset: 0,
+            total_duration,
+            ended: false,
+            failure: DecodeFailure::default(),
+        };
+        // Decode the first packet so channels and sample rate are known up front.
+        if !source.decode_next_packet()? {
+            return Err("Audio stream contains no samples".to_string());
+        }
+        Ok(source)
+    }
+
+    /// Decode packets until one yields samples. Returns false at end of stream.
+    fn decode_next_packet(&mut self) -> Result<bool, String> {
+        let mut decode_errors = 0;
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    if decoded.frames() == 0 {
+                        continue;
+                    }
+                    let spec = *decoded.spec();
+                    let needed = decoded.capacity() * spec.channels.count();
+                    if spec != self.spec || self.buffer.capacity() < needed {
+                        self.buffer = SampleBuffer::new(decoded.capacity() as u64, spec);
+                        self.spec = spec;
+                    }
+                    self.buffer.copy_interleaved_ref(decoded);
+                    self.offset = 0;
+                    return Ok(true);
+                }
+                Err(SymphoniaError::DecodeError(e)) => {
+                    decode_errors += 1;
+                    if decode_errors > MAX_DECODE_RETRIES {
+                        return Err(e.to_string());
+                    }
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    /// Refill the sample buffer, ending the stream on EOF, errors or decoder panics.
+    fn refill(&mut self) {
+        match catch_unwind(AssertUnwindSafe(|| self.decode_next_packet())) {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => self.ended = true,
+            Ok(Err(e)) => {
+                eprintln!("[Audio] Decoder error: {}", e);
+                self.failure.set(e);
+                self.ended = true;
+            }
+            Err(_) => {
+                eprintln!("[Audio] Panic during audio decoding");
+                self.failure.set("Panic during audio decoding".to_string());
+                self.ended = true;
+            }
+        }
+    }
+}
+
+impl Iterator for SymphoniaSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.ended {
+            return None;
+        }
+        let sample = *self.buffer.samples().get(self.offset)?;
+        self.offset += 1;
+        // Refill eagerly so current_frame_len never reports an empty frame mid-stream.
+        if self.offset >= self.buffer.len() {
+            self.refill();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.ended {
+            Some(0)
+        } else {
+            Some(self.buffer.len() - self.offset)
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        if self.total_duration.is_some_and(|total| pos >= total) {
+            self.ended = true;
+            return Ok(());
+        }
+
+        // Symphonia truncates the time to a timestamp; nudge by half a frame so we
+        // land on the nearest sample rather than the one before it.
+        let half_frame = 0.5 / self.spec.rate.max(1) as f64;
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(pos.as_secs_f64() + half_frame),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| SeekError::Other(Box::new(e)))?;
+        self.decoder.reset();
+        self.ended = false;
+
+        // The reader lands on a packet boundary at or before the target; drop the
+        // frames in between so the first sample out is the requested one.
+        let mut frames_to_skip = seeked.required_ts.saturating_sub(seeked.actual_ts) as usize;
+        loop {
+            self.refill();
+            if self.ended {
+                return Ok(());
+            }
+            let channels = self.spec.channels.count().max(1);
+            let frames = self.buffer.len() / channels;
+            if frames_to_skip < frames {
+                self.offset = frames_to_skip * channels;
+                return Ok(());
+            }
+            frames_to_skip -= frames;
+        }
+    }
+}
+
+/// Decode `path` from a buffered file handle, or from the archive it is in, guarding
+/// against decoder panics on broken files.
+fn open_file(path: &str) -> Result<SymphoniaSource, String> {
+    let path_ref = Path::new(path);
+    let media: std::io::Result<Box<dyn MediaSource>> = match archive::split_entry_path(path) {
+        Some(_) => archive::open_entry(path).map(|entry| Box::new(entry) as Box<dyn MediaSource>),
+        None => FileSource::open(path_ref).map(|file| Box::new(file) as Box<dyn MediaSource>),
+    };
+    let media = media.map_err(|e| {
+        eprintln!("[Audio] Failed to open file: {}", e);
+        e.to_string()
+    })?;
+    let extension = path_ref.extension().map(|e| e.to_string_lossy().to_lowercase());
+    new_decoder(media, extension.as_deref())
+}
+
+fn new_decoder(media: Box<dyn MediaSource>, extension: Option<&str>) -> Result<SymphoniaSource, String> {
+    let source_result = catch_unwind(AssertUnwindSafe(|| SymphoniaSource::new(media, extension)))
+        .map_err(|e| {
+            eprintln!("[Audio] Panic during audio decoding: {:?}", e);
+            "Panic during audio decoding".to_string()
+        })?;
+    let source = source_result.map_err(|e| {
+        eprintln!("[Audio] Decoder error: {}", e);
+        e
+    })?;
+
+    println!("[Audio] Decoder created successfully");
+    Ok(source)
+}
+
+/// Open `path` as a streamed f32 source positioned at `skip_seconds`.
+pub fn open_source(path: &str, skip_seconds: f32) -> Result<BoxedSource, String> {
+    open_tracked(path, skip_seconds).map(|opened| opened.source)
+}
+
+/// Like `open_source`, keeping hold of where a mid-stream decode error is reported.
+pub fn open_tracked(path: &str, skip_seconds: f32) -> Result<OpenedSource, String> {
+    println!("[Audio] Attempting to play: {}", path);
+    open_seeked(|| open_file(path), skip_seconds)
+}
+
+/// Build a decoder from `open` and position it at `skip_seconds`.
+///
+/// Seeking goes through Symphonia's native seek, so only the packets around the
+/// target are read. Formats that cannot seek are reopened and decoded up to the
+/// target instead, which is slow but always works.
+fn open_seeked<F>(open: F, skip_seconds: f32) -> Result<OpenedSource, String>
+where
+    F: Fn() -> Result<SymphoniaSource, String>,
+{
+    let opened = |decoder: SymphoniaSource, skip: Option<Duration>| {
+        let failure = decoder.failure.clone();
+        let source: BoxedSource = match skip {
+            Some(skip) => Box::new(decoder.skip_duration(skip)),
+            None => Box::new(decoder),
+        };
+        OpenedSource { source, failure }
+    };
+    let mut decoder = open()?;
+    if skip_seconds <= 0.0 {
+        return Ok(opened(decoder, None));
+    }
+
+    let target = Duration::from_secs_f32(skip_seconds);
+    match catch_unwind(AssertUnwindSafe(|| decoder.try_seek(target))) {
+        Ok(Ok(())) => return Ok(opened(decoder, None)),
+        Ok(Err(e)) => eprintln!("[Audio] Native seek failed ({}), decoding up to {:.1}s", e, skip_seconds),
+        Err(_) => eprintln!("[Audio] Panic during native seek, decoding up to {:.1}s", skip_seconds),
+    }
+
+    // The failed seek may have left the decoder anywhere in the stream, so start over.
+    let decoder = open()?;
+    Ok(opened(decoder, Some(target)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    /// Counts how many bytes the decoder actually pulls from the file.
+    struct CountingReader {
+        inner: File,
+        len: u64,
+        bytes_read: Arc<AtomicU64>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+            Ok(n)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl MediaSource for CountingReader {
+        fn is_seekable(&self) -> bool {
+            true
+        }
+
+        fn byte_len(&self) -> Option<u64> {
+            Some(self.len)
+        }
+    }
+
+    /// Write a mono 16-bit PCM WAV header and extend the file sparsely to `seconds` of silence.
+    fn write_long_wav(path: &Path, sample_rate: u32, seconds: u32) -> u64 {
+        let data_len = sample_rate * 2 * seconds;
+        let mut f = File::create(path).unwrap();
+        f.write_all(b"RIFF").unwrap();
+        f.write_all(&(36 + data_len).to_le_bytes()).unwrap();
+        f.write_all(b"WAVEfmt ").unwrap();
+        f.write_all(&16u32.to_le_bytes()).unwrap();
+        f.write_all(&1u16.to_le_bytes()).unwrap(); // PCM
+        f.write_all(&1u16.to_le_bytes()).unwrap(); // mono
+        f.write_all(&sample_rate.to_le_bytes()).unwrap();
+        f.write_all(&(sample_rate * 2).to_le_bytes()).unwrap();
+        f.write_all(&2u16.to_le_bytes()).unwrap();
+        f.write_all(&16u16.to_le_bytes()).unwrap();
+        f.write_all(b"data").unwrap();
+        f.write_all(&data_len.to_le_bytes()).unwrap();
+        let total = 44 + data_len as u64;
+        f.set_len(total).unwrap();
+        total
+    }
+
+    #[test]
+    fn seeking_near_the_end_does_not_decode_preceding_audio() {
+        let path = std::env::temp_dir().join(format!("asmr-seek-test-{}.wav", std::process::id()));
+        // 90 minutes of 8 kHz mono audio.
+        let file_len = write_long_wav(&path, 8_000, 90 * 60);
+
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let counter = bytes_read.clone();
+        let open_path = path.clone();
+        let open = move || {
+            let inner = File::open(&open_path).map_err(|e| e.to_string())?;
+            SymphoniaSource::new(
+                Box::new(CountingReader {
+                    inner,
+                    len: file_len,
+                    bytes_read: counter.clone(),
+                }),
+                Some("wav"),
+            )
+        };
+
+        // Seek into the last minute and pull a second of audio.
+        let mut source = open_seeked(open, (89 * 60 + 30) as f32).unwrap().source;
+        let pulled = source.by_ref().take(8_000).count();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(pulled, 8_000);
+        let read = bytes_read.load(Ordering::Relaxed);
+        assert!(
+            read < file_len / 100,
+            "read {} of {} bytes; seek decoded the preceding audio",
+            read,
+            file_len
+        );
+    }
+
+    #[test]
+    fn file_source_reports_byte_length() {
+        let path = std::env::temp_dir().join(format!("asmr-len-test-{}.wav", std::process::id()));
+        let file_len = write_long_wav(&path, 8_000, 1);
+        let source = FileSource::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(source.byte_len(), Some(file_len));
+        assert!(source.is_seekable());
+    }
+}
@@ -0,0 +1,615 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Centre frequencies of the 10-band graphic EQ (octave spacing).
+pub const GRAPHIC_FREQUENCIES: [f64; 10] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+const GRAPHIC_Q: f64 = 1.41;
+
+/// Second-order IIR section (transposed direct form II).
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// Coefficients as in the RBJ cookbook; they are normalised by `a[0]` here.
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        let mut filter = Self {
+            b: [1.0, 0.0, 0.0],
+            a: [1.0, 0.0, 0.0],
+            z: [0.0; 2],
+        };
+        filter.set_coefficients(b, a);
+        filter
+    }
+
+    /// Swap the response while keeping the filter state, so live edits don't click.
+    fn set_coefficients(&mut self, b: [f64; 3], a: [f64; 3]) {
+        let a0 = a[0];
+        self.b = [b[0] / a0, b[1] / a0, b[2] / a0];
+        self.a = [1.0, a[1] / a0, a[2] / a0];
+    }
+
+    fn reset(&mut self) {
+        self.z = [0.0; 2];
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: BandKind,
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl EqBand {
+    fn coefficients(&self, sample_rate: f64) -> ([f64; 3], [f64; 3]) {
+        let frequency = self.frequency.clamp(10.0, sample_rate * 0.45);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q.max(0.05));
+        let a = 10f64.powf(self.gain_db / 40.0);
+
+        match self.kind {
+            BandKind::Peaking => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            BandKind::LowShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    [
+                        a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                        2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                        a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                    ],
+                    [
+                        (a + 1.0) + (a - 1.0) * cos + sq,
+                        -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                        (a + 1.0) + (a - 1.0) * cos - sq,
+                    ],
+                )
+            }
+            BandKind::HighShelf => {
+                let sq = 2.0 * a.sqrt() * alpha;
+                (
+                    [
+                        a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                        -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                        a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                    ],
+                    [
+                        (a + 1.0) - (a - 1.0) * cos + sq,
+                        2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                        (a + 1.0) - (a - 1.0) * cos - sq,
+                    ],
+                )
+            }
+        }
+    }
+}
+
+fn high_pass_coefficients(frequency: f64, sample_rate: f64) -> ([f64; 3], [f64; 3]) {
+    let w0 = 2.0 * PI * frequency.clamp(10.0, sample_rate * 0.45) / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+    (
+        [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+        [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighPassSettings {
+    pub enabled: bool,
+    pub frequency: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimiterSettings {
+    pub enabled: bool,
+    pub threshold_db: f64,
+    pub release_ms: f64,
+}
+
+/// The whole processing chain. Stored as JSON under `audio.dsp` in `app_settings`,
+/// and under `audio.dsp:{work_id}` for a work that overrides it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspSettings {
+    pub enabled: bool,
+    pub preamp_db: f64,
+    pub bands: Vec<EqBand>,
+    pub high_pass: HighPassSettings,
+    pub limiter: LimiterSettings,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preamp_db: 0.0,
+            bands: graphic_bands(&[0.0; 10]),
+            high_pass: HighPassSettings {
+                enabled: false,
+                frequency: 60.0,
+            },
+            limiter: LimiterSettings {
+                enabled: true,
+                threshold_db: -1.0,
+                release_ms: 100.0,
+            },
+        }
+    }
+}
+
+/// Peaking bands at the graphic EQ frequencies with the given gains.
+pub fn graphic_bands(gains_db: &[f64]) -> Vec<EqBand> {
+    GRAPHIC_FREQUENCIES
+        .iter()
+        .zip(gains_db)
+        .map(|(&frequency, &gain_db)| EqBand {
+            kind: BandKind::Peaking,
+            frequency,
+            gain_db,
+            q: GRAPHIC_Q,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DspPreset {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub settings: DspSettings,
+}
+
+pub fn presets() -> Vec<DspPreset> {
+    let base = DspSettings {
+        enabled: true,
+        ..DspSettings::default()
+    };
+    let band = |kind, frequency, gain_db, q| EqBand {
+        kind,
+        frequency,
+        gain_db,
+        q,
+    };
+
+    vec![
+        DspPreset {
+            id: "flat",
+            name: "Flat",
+            settings: base.clone(),
+        },
+        DspPreset {
+            id: "reduce_sibilance",
+            name: "Reduce harsh sibilance",
+            settings: DspSettings {
+                bands: vec![
+                    band(BandKind::Peaking, 6500.0, -6.0, 2.0),
+                    band(BandKind::Peaking, 9000.0, -3.0, 2.0),
+                ],
+                ..base.clone()
+            },
+        },
+        DspPreset {
+            id: "headphone_bass",
+            name: "Bass boost for headphones",
+            settings: DspSettings {
+                preamp_db: -3.0,
+                bands: vec![band(BandKind::LowShelf, 90.0, 6.0, 0.707)],
+                ..base.clone()
+            },
+        },
+        DspPreset {
+            id: "voice_clarity",
+            name: "Voice clarity",
+            settings: DspSettings {
+                bands: vec![
+                    band(BandKind::LowShelf, 200.0, -2.0, 0.707),
+                    band(BandKind::Peaking, 3000.0, 3.0, 1.0),
+                ],
+                ..base.clone()
+            },
+        },
+        DspPreset {
+            id: "remove_rumble",
+            name: "Remove rumble",
+            settings: DspSettings {
+                bands: Vec::new(),
+                high_pass: HighPassSettings {
+                    enabled: true,
+                    frequency: 80.0,
+                },
+                ..base
+            },
+        },
+    ]
+}
+
+/// Settings shared with a stage on the audio thread. The version tells the stage to
+/// rebuild its state without locking on every sample.
+pub struct LiveSettings<T> {
+    settings: Arc<Mutex<T>>,
+    version: Arc<AtomicU64>,
+}
+
+impl<T> Clone for LiveSettings<T> {
+    fn clone(&self) -> Self {
+        Self {
+            settings: self.settings.clone(),
+            version: self.version.clone(),
+        }
+    }
+}
+
+impl<T> LiveSettings<T> {
+    pub fn new(settings: T) -> Self {
+        Self {
+            settings: Arc::new(Mutex::new(settings)),
+            version: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn set(&self, settings: T) {
+        if let Ok(mut current) = self.settings.lock() {
+            *current = settings;
+        }
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        match self.settings.lock() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// The UI thread holds the lock only to swap the value, so a miss just means
+    /// trying again on the next frame.
+    pub fn try_read(&self) -> Option<MutexGuard<'_, T>> {
+        self.settings.try_lock().ok()
+    }
+}
+
+pub type DspControl = LiveSettings<DspSettings>;
+
+/// A processing stage that works on whole interleaved frames.
+pub trait FrameProcessor {
+    /// Called before every frame so the stage can follow format and settings changes.
+    fn prepare(&mut self, sample_rate: u32, channels: u16);
+    fn process(&mut self, frame: &mut [f32]);
+    /// Forget filter history, e.g. after a seek.
+    fn reset(&mut self);
+}
+
+/// Runs a `FrameProcessor` over a source, one frame at a time.
+pub struct FrameSource<I, P>
+where
+    I: Source<Item = f32> + Send,
+    P: FrameProcessor,
+{
+    input: I,
+    processor: P,
+    frame: Vec<f32>,
+    frame_pos: usize,
+}
+
+impl<I, P> FrameSource<I, P>
+where
+    I: Source<Item = f32> + Send,
+    P: FrameProcessor,
+{
+    pub fn new(input: I, processor: P) -> Self {
+        Self {
+            input,
+            processor,
+            frame: Vec::new(),
+            frame_pos: 0,
+        }
+    }
+}
+
+impl<I, P> Iterator for FrameSource<I, P>
+where
+    I: Source<Item = f32> + Send,
+    P: FrameProcessor,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(&sample) = self.frame.get(self.frame_pos) {
+            self.frame_pos += 1;
+            return Some(sample);
+        }
+
+        let channels = self.input.channels().max(1);
+        self.processor.prepare(self.input.sample_rate(), channels);
+
+        self.frame.clear();
+        self.frame_pos = 0;
+        for _ in 0..channels {
+            match self.input.next() {
+                Some(s) => self.frame.push(s),
+                None => break,
+            }
+        }
+        if self.frame.len() == channels as usize {
+            self.processor.process(&mut self.frame);
+        }
+
+        let sample = *self.frame.first()?;
+        self.frame_pos = 1;
+        Some(sample)
+    }
+}
+
+impl<I, P> Source for FrameSource<I, P>
+where
+    I: Source<Item = f32> + Send,
+    P: FrameProcessor,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Samples already pulled into the frame are still ours to hand out.
+        let buffered = self.frame.len() - self.frame_pos;
+        self.input.current_frame_len().map(|n| n + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.frame.clear();
+        self.frame_pos = 0;
+        self.processor.reset();
+        Ok(())
+    }
+}
+
+/// Stereo-linked peak limiter: instant attack, exponential release.
+struct Limiter {
+    threshold: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl Limiter {
+    fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        self.envelope = peak.max(self.envelope * self.release);
+        if self.envelope > self.threshold {
+            let gain = self.threshold / self.envelope;
+            for s in frame.iter_mut() {
+                *s *= gain;
+            }
+        }
+    }
+}
+
+/// High-pass, EQ and limiter.
+pub struct DspChain {
+    control: DspControl,
+    version: Option<u64>,
+    sample_rate: u32,
+    channels: u16,
+    enabled: bool,
+    preamp: f32,
+    // One chain per channel: optional high-pass followed by the EQ bands.
+    filters: Vec<Vec<Biquad>>,
+    limiter: Option<Limiter>,
+}
+
+impl DspChain {
+    pub fn new(control: DspControl) -> Self {
+        Self {
+            control,
+            version: None,
+            sample_rate: 0,
+            channels: 0,
+            enabled: false,
+            preamp: 1.0,
+            filters: Vec::new(),
+            limiter: None,
+        }
+    }
+
+    fn rebuild(&mut self, version: u64) {
+        let Some(settings) = self.control.try_read() else {
+            return;
+        };
+        let rate = self.sample_rate.max(1) as f64;
+
+        let mut coefficients = Vec::new();
+        if settings.high_pass.enabled {
+            coefficients.push(high_pass_coefficients(settings.high_pass.frequency, rate));
+        }
+        coefficients.extend(
+            settings
+                .bands
+                .iter()
+                .filter(|b| b.gain_db != 0.0)
+                .map(|b| b.coefficients(rate)),
+        );
+
+        self.filters.resize_with(self.channels as usize, Vec::new);
+        for chain in &mut self.filters {
+            // Keep the state of filters that survive the edit.
+            chain.truncate(coefficients.len());
+            for (i, &(b, a)) in coefficients.iter().enumerate() {
+                match chain.get_mut(i) {
+                    Some(filter) => filter.set_coefficients(b, a),
+                    None => chain.push(Biquad::new(b, a)),
+                }
+            }
+        }
+
+        self.enabled = settings.enabled;
+        self.preamp = 10f64.powf(settings.preamp_db / 20.0) as f32;
+        self.limiter = settings.limiter.enabled.then(|| Limiter {
+            threshold: 10f64.powf(settings.limiter.threshold_db.min(0.0) / 20.0) as f32,
+            release: (-1.0 / (settings.limiter.release_ms.max(1.0) / 1000.0 * rate)).exp() as f32,
+            envelope: self.limiter.as_ref().map_or(0.0, |l| l.envelope),
+        });
+        self.version = Some(version);
+    }
+}
+
+impl FrameProcessor for DspChain {
+    fn prepare(&mut self, sample_rate: u32, channels: u16) {
+        if sample_rate != self.sample_rate || channels != self.channels {
+            self.sample_rate = sample_rate;
+            self.channels = channels;
+            self.filters.clear();
+            self.version = None;
+        }
+        let version = self.control.version();
+        if self.version != Some(version) {
+            self.rebuild(version);
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        if !self.enabled {
+            return;
+        }
+        for (sample, chain) in frame.iter_mut().zip(&mut self.filters) {
+            let mut x = (*sample * self.preamp) as f64;
+            for filter in chain.iter_mut() {
+                x = filter.process(x);
+            }
+            *sample = x as f32;
+        }
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.process(frame);
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.envelope = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 48_000;
+
+    fn sine(frequency: f64, amplitude: f32, seconds: f64) -> SamplesBuffer<f32> {
+        let samples = (0..(RATE as f64 * seconds) as usize)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f64 / RATE as f64).sin() as f32)
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(1, RATE, samples)
+    }
+
+    /// Peak of the second half, after the filters have settled.
+    fn settled_peak(source: impl Iterator<Item = f32>) -> f32 {
+        let out: Vec<f32> = source.collect();
+        out[out.len() / 2..].iter().fold(0.0, |m, s| m.max(s.abs()))
+    }
+
+    fn settings(f: impl FnOnce(&mut DspSettings)) -> DspControl {
+        let mut s = DspSettings {
+            enabled: true,
+            bands: Vec::new(),
+            ..DspSettings::default()
+        };
+        f(&mut s);
+        DspControl::new(s)
+    }
+
+    #[test]
+    fn high_pass_removes_rumble_but_keeps_voice() {
+        let control = settings(|s| {
+            s.high_pass = HighPassSettings {
+                enabled: true,
+                frequency: 80.0,
+            }
+        });
+        let rumble = settled_peak(FrameSource::new(sine(20.0, 0.5, 1.0), DspChain::new(control.clone())));
+        let voice = settled_peak(FrameSource::new(sine(1000.0, 0.5, 1.0), DspChain::new(control)));
+        assert!(rumble < 0.05, "{}", rumble);
+        assert!((voice - 0.5).abs() < 0.01, "{}", voice);
+    }
+
+    #[test]
+    fn peaking_band_cuts_at_its_frequency() {
+        let control = settings(|s| s.bands = vec![EqBand {
+            kind: BandKind::Peaking,
+            frequency: 6500.0,
+            gain_db: -6.0,
+            q: 2.0,
+        }]);
+        let peak = settled_peak(FrameSource::new(sine(6500.0, 0.5, 0.5), DspChain::new(control)));
+        assert!((peak - 0.5 * 0.501).abs() < 0.01, "{}", peak);
+    }
+
+    #[test]
+    fn limiter_holds_the_threshold() {
+        let control = settings(|s| s.preamp_db = 12.0);
+        let peak = settled_peak(FrameSource::new(sine(440.0, 0.5, 0.5), DspChain::new(control)));
+        assert!(peak <= 10f32.powf(-1.0 / 20.0) + 1e-6, "{}", peak);
+    }
+
+    #[test]
+    fn live_changes_apply_to_a_playing_source() {
+        let control = settings(|_| {});
+        let mut source = FrameSource::new(sine(1000.0, 0.5, 1.0), DspChain::new(control.clone()));
+        let before = settled_peak(source.by_ref().take(RATE as usize / 2));
+        control.set(DspSettings {
+            enabled: true,
+            preamp_db: -6.0,
+            bands: Vec::new(),
+            ..DspSettings::default()
+        });
+        let after = settled_peak(source);
+        assert!((before - 0.5).abs() < 0.01 && (after - 0.25).abs() < 0.01, "{} {}", before, after);
+    }
+}
@@ -0,0 +1,137 @@
+use super::queue::QueueEntry;
+use serde::Serialize;
+
+/// What the player is doing, as sent with `playback-state-changed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+    /// A track has been loaded to play but the audio thread hasn't started it yet.
+    Buffering,
+}
+
+/// Payload of the `track-ended` event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackEnded {
+    pub entry_id: u64,
+    pub track_id: i64,
+    pub path: String,
+}
+
+/// Payload of the `track-error` event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackError {
+    pub entry_id: u64,
+    pub track_id: i64,
+    pub path: String,
+    pub error: String,
+}
+
+/// A playback event for the frontend, with its payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    StateChanged(PlaybackState),
+    TrackEnded(TrackEnded),
+    TrackError(TrackError),
+}
+
+impl PlayerEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlayerEvent::StateChanged(_) => "playback-state-changed",
+            PlayerEvent::TrackEnded(_) => "track-ended",
+            PlayerEvent::TrackError(_) => "track-error",
+        }
+    }
+
+    pub fn ended(entry: &QueueEntry) -> Self {
+        PlayerEvent::TrackEnded(TrackEnded {
+            entry_id: entry.entry_id,
+            track_id: entry.track.id,
+            path: entry.track.path.clone(),
+        })
+    }
+
+    pub fn error(entry: &QueueEntry, error: &str) -> Self {
+        PlayerEvent::TrackError(TrackError {
+            entry_id: entry.entry_id,
+            track_id: entry.track.id,
+            path: entry.track.path.clone(),
+            error: error.to_string(),
+        })
+    }
+}
+
+/// What the frontend hears when the audio thread reports `entry` finished: the decoder
+/// error first if it gave up, then the end of the track either way.
+pub fn finished(entry: &QueueEntry, error: Option<&str>) -> Vec<PlayerEvent> {
+    error
+        .map(|e| PlayerEvent::error(entry, e))
+        .into_iter()
+        .chain([PlayerEvent::ended(entry)])
+        .collect()
+}
+
+/// The event for going from `from` to `to`. Nothing is sent when the state holds.
+pub fn state_changed(from: PlaybackState, to: PlaybackState) -> Option<PlayerEvent> {
+    (from != to).then_some(PlayerEvent::StateChanged(to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::queue::QueueTrack;
+    use super::*;
+    use serde_json::json;
+
+    fn entry() -> QueueEntry {
+        QueueEntry {
+            entry_id: 3,
+            track: QueueTrack {
+                id: 42,
+                work_id: Some(7),
+                title: "01".to_string(),
+                path: "/lib/RJ01234567.zip!/01.mp3".to_string(),
+                duration: 60.0,
+                work_title: None,
+                cover_path: None,
+            },
+        }
+    }
+
+    fn payload(event: &PlayerEvent) -> serde_json::Value {
+        match event {
+            PlayerEvent::StateChanged(state) => serde_json::to_value(state),
+            PlayerEvent::TrackEnded(ended) => serde_json::to_value(ended),
+            PlayerEvent::TrackError(error) => serde_json::to_value(error),
+        }
+        .unwrap()
+    }
+
+    #[test]
+    fn a_failed_track_reports_the_error_before_ending() {
+        let events = finished(&entry(), Some("Decode error: bad frame"));
+        let names: Vec<_> = events.iter().map(PlayerEvent::name).collect();
+        assert_eq!(names, ["track-error", "track-ended"]);
+        assert_eq!(
+            payload(&events[0]),
+            json!({ "entry_id": 3, "track_id": 42, "path": "/lib/RJ01234567.zip!/01.mp3", "error": "Decode error: bad frame" })
+        );
+        assert_eq!(
+            payload(&events[1]),
+            json!({ "entry_id": 3, "track_id": 42, "path": "/lib/RJ01234567.zip!/01.mp3" })
+        );
+
+        let names: Vec<_> = finished(&entry(), None).iter().map(PlayerEvent::name).collect();
+        assert_eq!(names, ["track-ended"]);
+    }
+
+    #[test]
+    fn state_changes_are_sent_once_in_snake_case() {
+        assert_eq!(state_changed(PlaybackState::Playing, PlaybackState::Playing), None);
+        let event = state_changed(PlaybackState::Paused, PlaybackState::Buffering).unwrap();
+        assert_eq!(event.name(), "playback-state-changed");
+        assert_eq!(payload(&event), json!("buffering"));
+    }
+}
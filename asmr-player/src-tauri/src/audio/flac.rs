@@ -0,0 +1,368 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Frames per FLAC frame, the usual size for 44.1 and 48 kHz audio.
+const BLOCK_SIZE: usize = 4096;
+/// Where STREAMINFO starts: after `fLaC` and its block header.
+const STREAMINFO_OFFSET: u64 = 8;
+/// Largest Rice parameter a 4-bit field holds; 15 is the escape code.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Writes integer PCM to a FLAC file. Each channel is coded on its own with the best
+/// of the fixed predictors and a single Rice partition, which comes close to what
+/// encoders do at their fast settings. The MD5 of the audio is left unset, as the
+/// format allows.
+pub struct FlacWriter {
+    file: BufWriter<File>,
+    channels: usize,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    // Interleaved samples of the frame being filled.
+    block: Vec<i32>,
+    frames_written: u64,
+    samples_written: u64,
+    min_frame_bytes: u32,
+    max_frame_bytes: u32,
+}
+
+impl FlacWriter {
+    pub fn create(path: &Path, channels: u16, sample_rate: u32, bits_per_sample: u32) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        if !(1..=8).contains(&channels) {
+            return Err(invalid("FLAC holds 1 to 8 channels"));
+        }
+        if sample_rate == 0 || sample_rate >= 1 << 20 {
+            return Err(invalid("Sample rate out of FLAC's range"));
+        }
+        if !matches!(bits_per_sample, 16 | 24) {
+            return Err(invalid("Only 16 and 24-bit FLAC is written"));
+        }
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            channels: channels as usize,
+            sample_rate,
+            bits_per_sample,
+            block: Vec::with_capacity(BLOCK_SIZE * channels as usize),
+            frames_written: 0,
+            samples_written: 0,
+            min_frame_bytes: 0,
+            max_frame_bytes: 0,
+        };
+        writer.file.write_all(b"fLaC")?;
+        // Last metadata block, STREAMINFO, 34 bytes long.
+        writer.file.write_all(&[0x80, 0, 0, 34])?;
+        let info = writer.stream_info();
+        writer.file.write_all(&info)?;
+        Ok(writer)
+    }
+
+    /// Add one sample; channels are interleaved as in a WAV file.
+    pub fn write_sample(&mut self, sample: i32) -> io::Result<()> {
+        self.block.push(sample);
+        if self.block.len() == BLOCK_SIZE * self.channels {
+            self.write_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Write what is left and fill in the stream's length.
+    pub fn finalize(mut self) -> io::Result<()> {
+        // A partial frame at the very end is dropped, like hound does with WAV.
+        self.block.truncate(self.block.len() - self.block.len() % self.channels);
+        if !self.block.is_empty() {
+            self.write_frame()?;
+        }
+        let info = self.stream_info();
+        self.file.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.file.write_all(&info)?;
+        self.file.flush()
+    }
+
+    fn stream_info(&self) -> [u8; 34] {
+        let mut bits = BitWriter::default();
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(self.min_frame_bytes as u64, 24);
+        bits.write(self.max_frame_bytes as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        bits.write(self.samples_written, 36);
+        // MD5 of the audio, unknown.
+        bits.write(0, 64);
+        bits.write(0, 64);
+        bits.into_bytes().try_into().expect("STREAMINFO is 34 bytes")
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let frames = self.block.len() / self.channels;
+        let mut bits = BitWriter::default();
+
+        bits.write(0b11_1111_1111_1110, 14);
+        bits.write(0, 1);
+        // Fixed block size, numbered by frame.
+        bits.write(0, 1);
+        let size_code = if frames == BLOCK_SIZE { 0b1100 } else { 0b0111 };
+        bits.write(size_code, 4);
+        bits.write(sample_rate_code(self.sample_rate), 4);
+        bits.write(self.channels as u64 - 1, 4);
+        bits.write(if self.bits_per_sample == 16 { 0b100 } else { 0b110 }, 3);
+        bits.write(0, 1);
+        bits.write_utf8(self.frames_written);
+        if size_code == 0b0111 {
+            bits.write(frames as u64 - 1, 16);
+        }
+        let header_crc = crc8(bits.bytes());
+        bits.write(header_crc as u64, 8);
+
+        let mut channel = Vec::with_capacity(frames);
+        for c in 0..self.channels {
+            channel.clear();
+            channel.extend(self.block.iter().skip(c).step_by(self.channels).map(|&s| s as i64));
+            write_subframe(&mut bits, &channel, self.bits_per_sample);
+        }
+        let mut frame = bits.into_bytes();
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        self.file.write_all(&frame)?;
+
+        let len = frame.len() as u32;
+        self.min_frame_bytes = if self.frames_written == 0 { len } else { self.min_frame_bytes.min(len) };
+        self.max_frame_bytes = self.max_frame_bytes.max(len);
+        self.frames_written += 1;
+        self.samples_written += frames as u64;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+/// Code for `rate` in a frame header; 0 defers to STREAMINFO.
+fn sample_rate_code(rate: u32) -> u64 {
+    match rate {
+        88_200 => 0b0001,
+        176_400 => 0b0010,
+        192_000 => 0b0011,
+        8_000 => 0b0100,
+        16_000 => 0b0101,
+        22_050 => 0b0110,
+        24_000 => 0b0111,
+        32_000 => 0b1000,
+        44_100 => 0b1001,
+        48_000 => 0b1010,
+        96_000 => 0b1011,
+        _ => 0,
+    }
+}
+
+/// What's left of `samples` after the fixed predictor of `order`.
+fn residuals(samples: &[i64], order: usize) -> impl Iterator<Item = i64> + '_ {
+    samples.windows(order + 1).map(move |w| {
+        let n = order;
+        match order {
+            0 => w[n],
+            1 => w[n] - w[n - 1],
+            2 => w[n] - 2 * w[n - 1] + w[n - 2],
+            3 => w[n] - 3 * w[n - 1] + 3 * w[n - 2] - w[n - 3],
+            _ => w[n] - 4 * w[n - 1] + 6 * w[n - 2] - 4 * w[n - 3] + w[n - 4],
+        }
+    })
+}
+
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+/// The cheapest Rice parameter for `folded` residuals, and its size in bits.
+fn rice_parameter(folded: &[u64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits: u64 = folded.iter().map(|&u| (u >> k) + 1 + k as u64).sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .expect("parameters to try")
+}
+
+fn write_subframe(bits: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    let verbatim = samples.len() as u64 * bits_per_sample as u64;
+    let best = (0..=4.min(samples.len().saturating_sub(1)))
+        .map(|order| {
+            let folded: Vec<u64> = residuals(samples, order).map(zigzag).collect();
+            let (k, residual_bits) = rice_parameter(&folded);
+            (order, k, folded, order as u64 * bits_per_sample as u64 + 6 + 4 + residual_bits)
+        })
+        .min_by_key(|(_, _, _, size)| *size)
+        .filter(|(_, _, _, size)| *size < verbatim);
+
+    match best {
+        Some((order, k, folded, _)) => {
+            // Zero pad bit, FIXED of `order`, no wasted bits.
+            bits.write(0b0001_0000 | (order as u64) << 1, 8);
+            for &s in &samples[..order] {
+                bits.write_signed(s, bits_per_sample);
+            }
+            // Rice coding with 4-bit parameters, in one partition.
+            bits.write(0, 2);
+            bits.write(0, 4);
+            bits.write(k as u64, 4);
+            for u in folded {
+                bits.write_unary(u >> k);
+                bits.write(u & ((1 << k) - 1), k);
+            }
+        }
+        None => {
+            // Zero pad bit, VERBATIM, no wasted bits.
+            bits.write(0b0000_0010, 8);
+            for &s in samples {
+                bits.write_signed(s, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Packs bits most significant first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1);
+            self.len += 1;
+            if self.len == 8 {
+                self.bytes.push(self.acc as u8);
+                self.acc = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64 & ((1 << bits) - 1), bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        for _ in 0..zeros {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// A frame number, coded like a UTF-8 character stretched to 36 bits.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let continuation = (1..=6).find(|&n| value < 1 << (6 - n + 6 * n)).unwrap_or(6);
+        let marker = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+        self.write(marker | (value >> (6 * continuation)), 8);
+        for i in (0..continuation).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    /// Bytes written so far; only whole bytes.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The bytes, padded with zero bits to the next byte.
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+        self.bytes
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::open_source;
+    use super::*;
+
+    fn write(name: &str, channels: u16, bits_per_sample: u32, samples: &[i32]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("flac-test-{}-{}.flac", std::process::id(), name));
+        let mut writer = FlacWriter::create(&path, channels, 48_000, bits_per_sample).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn decode(path: &Path) -> Vec<f32> {
+        let samples = open_source(&path.to_string_lossy(), 0.0).unwrap().collect();
+        std::fs::remove_file(path).ok();
+        samples
+    }
+
+    #[test]
+    fn round_trips_through_the_decoder() {
+        // A tone, a stretch of silence and some noise, over two frames and a bit.
+        let samples: Vec<i32> = (0..9_000)
+            .flat_map(|i: i32| {
+                let tone = ((i as f32 * 0.05).sin() * 12_000.0) as i32;
+                let noise = (i.wrapping_mul(7_919) % 2_001) - 1_000;
+                match i {
+                    0..=3_999 => [tone, -tone / 2],
+                    4_000..=5_999 => [0, 0],
+                    _ => [noise, i16::MIN as i32 + (i % 3)],
+                }
+            })
+            .collect();
+        let path = write("16", 2, 16, &samples);
+        let size = std::fs::metadata(&path).unwrap().len();
+        let decoded = decode(&path);
+        assert_eq!(decoded.len(), samples.len());
+        for (i, (&d, &s)) in decoded.iter().zip(&samples).enumerate() {
+            assert_eq!(d, s as f32 / 32_768.0, "sample {}", i);
+        }
+        // The tone and silence compress; the noise is stored as it is.
+        assert!(size < samples.len() as u64 * 2, "{} bytes", size);
+    }
+
+    #[test]
+    fn keeps_24_bit_samples() {
+        let samples: Vec<i32> = (0..5_000).map(|i| (i * 1_677) % (1 << 23) - (1 << 22)).collect();
+        let decoded = decode(&write("24", 1, 24, &samples));
+        assert_eq!(decoded.len(), samples.len());
+        for (&d, &s) in decoded.iter().zip(&samples) {
+            assert!((d - s as f32 / 8_388_608.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn frame_numbers_are_coded_like_utf8() {
+        for (value, expected) in [(0x7F, vec![0x7F]), (0x80, vec![0xC2, 0x80]), (0x800, vec![0xE0, 0xA0, 0x80])] {
+            let mut bits = BitWriter::default();
+            bits.write_utf8(value);
+            assert_eq!(bits.into_bytes(), expected);
+        }
+    }
+}
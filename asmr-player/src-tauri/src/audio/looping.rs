@@ -0,0 +1,210 @@
+use super::clock::PlaybackClock;
+use super::dsp::LiveSettings;
+use rodio::source::SeekError;
+use rodio::Source;
+use std::time::Duration;
+
+/// Length of the fade on either side of the loop point, so the jump doesn't click.
+const DECLICK: Duration = Duration::from_millis(8);
+
+/// Part of a track to repeat. Without an end it runs to the end of the track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopRegion {
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+pub type LoopControl = LiveSettings<Option<LoopRegion>>;
+
+/// Repeats a `LoopRegion` by seeking the decoder back to its start whenever the
+/// audible stream reaches its end. Sits right after the `ClockSource`, whose clock
+/// both tells it where playback is and gets reset by the seek.
+pub struct LoopSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    control: LoopControl,
+    clock: PlaybackClock,
+    version: Option<u64>,
+    region: Option<LoopRegion>,
+    channels: u16,
+    sample_in_frame: u16,
+    gain: f32,
+    // Frames left of the fade-in after a jump.
+    fade_in: u32,
+}
+
+impl<I> LoopSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, control: LoopControl, clock: PlaybackClock) -> Self {
+        Self {
+            input,
+            control,
+            clock,
+            version: None,
+            region: None,
+            channels: 1,
+            sample_in_frame: 0,
+            gain: 1.0,
+            fade_in: 0,
+        }
+    }
+
+    fn declick_frames(&self) -> u32 {
+        (DECLICK.as_secs_f64() * self.input.sample_rate() as f64) as u32
+    }
+
+    fn update_region(&mut self) {
+        let version = self.control.version();
+        if self.version == Some(version) {
+            return;
+        }
+        if let Some(region) = self.control.try_read() {
+            self.region = region.filter(|r| r.end.is_none_or(|end| end > r.start));
+            self.version = Some(version);
+        }
+    }
+
+    /// Where the region ends, falling back to the end of the track.
+    fn region_end(&self) -> Option<Duration> {
+        self.region?.end.or_else(|| self.input.total_duration())
+    }
+
+    /// Seek back to the start of the region. False if there is no region or the seek failed.
+    fn jump(&mut self) -> bool {
+        let Some(region) = self.region else {
+            return false;
+        };
+        match self.input.try_seek(region.start) {
+            Ok(()) => {
+                self.fade_in = self.declick_frames();
+                true
+            }
+            Err(e) => {
+                // Play on rather than retrying the seek every frame.
+                eprintln!("[Audio] Loop seek failed: {}", e);
+                self.region = None;
+                false
+            }
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.update_region();
+        self.channels = self.input.channels().max(1);
+        self.gain = 1.0;
+
+        if let Some(end) = self.region_end() {
+            let position = self.clock.pulled();
+            if position >= end {
+                self.jump();
+            } else if self.region.is_some() {
+                let left = (end - position).as_secs_f32() / DECLICK.as_secs_f32();
+                self.gain = left.min(1.0);
+            }
+        }
+        if self.fade_in > 0 {
+            let total = self.declick_frames().max(1);
+            self.gain *= 1.0 - self.fade_in as f32 / total as f32;
+            self.fade_in -= 1;
+        }
+    }
+}
+
+impl<I> Iterator for LoopSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample_in_frame == 0 {
+            self.start_frame();
+        }
+        let sample = match self.input.next() {
+            Some(sample) => sample,
+            // The track ran out before the end of the region (or the region runs to the end).
+            None if self.sample_in_frame == 0 && self.jump() => self.input.next()?,
+            None => return None,
+        };
+        self.sample_in_frame = (self.sample_in_frame + 1) % self.channels;
+        Some(sample * self.gain)
+    }
+}
+
+impl<I> Source for LoopSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.sample_in_frame = 0;
+        self.fade_in = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::clock::{ClockSource, OutputLatency};
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 8_000;
+
+    /// Each sample holds its own timestamp in seconds, so the output shows where it came from.
+    fn looped(region: LoopRegion) -> (LoopSource<ClockSource<SamplesBuffer<f32>>>, PlaybackClock) {
+        let ramp = (0..RATE * 2).map(|n| n as f32 / RATE as f32).collect::<Vec<_>>();
+        let clock = PlaybackClock::new(Duration::ZERO, OutputLatency::default());
+        let input = ClockSource::new(SamplesBuffer::new(1, RATE, ramp), Duration::ZERO, clock.clone());
+        (LoopSource::new(input, LiveSettings::new(Some(region)), clock.clone()), clock)
+    }
+
+    #[test]
+    fn jumps_back_to_the_start_at_the_end() {
+        let (source, clock) = looped(LoopRegion {
+            start: Duration::from_millis(500),
+            end: Some(Duration::from_millis(1000)),
+        });
+        let out: Vec<f32> = source.take(RATE as usize * 3).collect();
+        assert_eq!(out.len(), RATE as usize * 3);
+        // Nothing past the end, give or take one clock update.
+        assert!(out.iter().all(|&t| t < 1.005));
+        // 0..1 s once, then 0.5..1 s four more times.
+        let middle = out.iter().filter(|&&t| t > 0.55 && t < 0.95).count();
+        assert!((middle as f32 / (0.4 * RATE as f32) - 5.0).abs() < 0.1, "{}", middle);
+        let position = clock.pulled();
+        assert!(position >= Duration::from_millis(500) && position <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn open_region_loops_at_the_end_of_the_track() {
+        let (source, _) = looped(LoopRegion {
+            start: Duration::from_millis(1500),
+            end: None,
+        });
+        let out: Vec<f32> = source.take(RATE as usize * 3).collect();
+        assert_eq!(out.len(), RATE as usize * 3);
+        // Halfway through the first repeat of 1.5..2 s.
+        assert!((out[RATE as usize * 9 / 4] - 1.75).abs() < 0.01, "{}", out[RATE as usize * 9 / 4]);
+    }
+}
@@ -0,0 +1,501 @@
+use super::decoder::open_source;
+use super::dsp::Biquad;
+use super::silence::{Silence, SilenceMeter};
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// ReplayGain 2.0 gains are relative to this loudness.
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+/// Normalization never pushes the (true) peak above this.
+const PEAK_CEILING_DB: f64 = -1.0;
+/// BS.1770 gating thresholds.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// True peak is measured by interpolating this many points per sample.
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationMode {
+    Off,
+    Track,
+    /// One gain for the whole work, so the level differences between its tracks survive.
+    Work,
+}
+
+/// Stored as JSON under `audio.normalization` in `app_settings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NormalizationSettings {
+    pub mode: NormalizationMode,
+    pub target_lufs: f64,
+}
+
+impl Default for NormalizationSettings {
+    fn default() -> Self {
+        Self {
+            mode: NormalizationMode::Off,
+            target_lufs: REPLAYGAIN_REFERENCE_LUFS,
+        }
+    }
+}
+
+/// Integrated loudness and linear true peak (1.0 = full scale) of a track or work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Build from `REPLAYGAIN_TRACK_GAIN` ("-6.50 dB") and `REPLAYGAIN_TRACK_PEAK` tag values.
+    pub fn from_replaygain(gain: &str, peak: Option<&str>) -> Option<Self> {
+        let gain_db: f64 = gain.trim().trim_end_matches("dB").trim().parse().ok()?;
+        let true_peak = peak.and_then(|p| p.trim().parse().ok()).unwrap_or(1.0);
+        Some(Self {
+            integrated_lufs: REPLAYGAIN_REFERENCE_LUFS - gain_db,
+            true_peak,
+        })
+    }
+
+    /// Loudness of several tracks played back to back, from their loudness and duration.
+    /// Energy-weighted, which is what measuring the concatenation would give minus gating.
+    pub fn combine(parts: &[(Loudness, f64)]) -> Option<Self> {
+        let total: f64 = parts.iter().map(|(_, d)| d.max(0.0)).sum();
+        if parts.is_empty() || total <= 0.0 {
+            return None;
+        }
+        let energy: f64 = parts
+            .iter()
+            .map(|(l, d)| d.max(0.0) * lufs_to_energy(l.integrated_lufs))
+            .sum::<f64>()
+            / total;
+        Some(Self {
+            integrated_lufs: energy_to_lufs(energy),
+            true_peak: parts.iter().map(|(l, _)| l.true_peak).fold(0.0, f64::max),
+        })
+    }
+}
+
+/// Linear gain bringing `measured` to the target, capped so the peak stays under the ceiling.
+pub fn normalization_gain(settings: &NormalizationSettings, track: Option<Loudness>, work: Option<Loudness>) -> f32 {
+    let measured = match settings.mode {
+        NormalizationMode::Off => return 1.0,
+        NormalizationMode::Track => track,
+        NormalizationMode::Work => work.or(track),
+    };
+    let Some(measured) = measured else {
+        return 1.0;
+    };
+
+    let mut gain = db_to_linear(settings.target_lufs - measured.integrated_lufs);
+    if measured.true_peak > 0.0 {
+        gain = gain.min(db_to_linear(PEAK_CEILING_DB) / measured.true_peak);
+    }
+    gain as f32
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn lufs_to_energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+// ============ Measurement ============
+
+/// The BS.1770 K-weighting pre-filter (high shelf + RLB high-pass), derived for any
+/// sample rate from the analog prototypes rather than the 48 kHz coefficient table.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = db_to_linear(g);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [vh + vb * k / q + k * k, 2.0 * (k * k - vh), vh - vb * k / q + k * k],
+        [a0, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new([1.0, -2.0, 1.0], [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]);
+
+    [shelf, high_pass]
+}
+
+/// Windowed-sinc interpolator used to find peaks between samples.
+struct TruePeak {
+    coeffs: Vec<f64>,
+    history: Vec<Vec<f64>>,
+    pos: usize,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(channels: usize) -> Self {
+        let len = OVERSAMPLING * TAPS_PER_PHASE;
+        let centre = (len - 1) as f64 / 2.0;
+        let coeffs = (0..len)
+            .map(|n| {
+                let t = (n as f64 - centre) / OVERSAMPLING as f64;
+                let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / (len - 1) as f64).cos();
+                sinc * window
+            })
+            .collect();
+        Self {
+            coeffs,
+            history: vec![vec![0.0; TAPS_PER_PHASE]; channels],
+            pos: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) {
+        self.pos = (self.pos + 1) % TAPS_PER_PHASE;
+        for (history, &sample) in self.history.iter_mut().zip(frame) {
+            history[self.pos] = sample as f64;
+            self.peak = self.peak.max((sample as f64).abs());
+            for phase in 0..OVERSAMPLING {
+                let mut acc = 0.0;
+                for tap in 0..TAPS_PER_PHASE {
+                    let x = history[(self.pos + TAPS_PER_PHASE - tap) % TAPS_PER_PHASE];
+                    acc += self.coeffs[tap * OVERSAMPLING + phase] * x;
+                }
+                self.peak = self.peak.max(acc.abs());
+            }
+        }
+    }
+}
+
+/// Streaming BS.1770-4 meter: gated integrated loudness over 400 ms blocks with 75%
+/// overlap, plus true peak. All channels are weighted equally (no surround weighting).
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    // 100 ms steps; a block is the last four of them.
+    step_frames: usize,
+    step_pos: usize,
+    step_energy: f64,
+    recent_steps: [f64; 4],
+    steps_seen: usize,
+    blocks: Vec<f64>,
+    true_peak: Option<TruePeak>,
+    frame: Vec<f32>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_pos: 0,
+            step_energy: 0.0,
+            recent_steps: [0.0; 4],
+            steps_seen: 0,
+            blocks: Vec::new(),
+            // Above 96 kHz the sample peak is close enough, as BS.1770 allows.
+            true_peak: (sample_rate < 96_000).then(|| TruePeak::new(channels)),
+            frame: Vec::with_capacity(channels),
+        }
+    }
+
+    /// Feed one interleaved sample.
+    pub fn push(&mut self, sample: f32) {
+        self.frame.push(sample);
+        if self.frame.len() < self.channels {
+            return;
+        }
+
+        for ([shelf, high_pass], &s) in self.filters.iter_mut().zip(&self.frame) {
+            let weighted = high_pass.process(shelf.process(s as f64));
+            self.step_energy += weighted * weighted;
+        }
+        if let Some(ref mut tp) = self.true_peak {
+            tp.push_frame(&self.frame);
+        }
+        self.frame.clear();
+
+        self.step_pos += 1;
+        if self.step_pos == self.step_frames {
+            self.recent_steps[self.steps_seen % 4] = self.step_energy / self.step_frames as f64;
+            self.steps_seen += 1;
+            if self.steps_seen >= 4 {
+                self.blocks.push(self.recent_steps.iter().sum::<f64>() / 4.0);
+            }
+            self.step_pos = 0;
+            self.step_energy = 0.0;
+        }
+    }
+
+    /// None for silence or anything shorter than one 400 ms block.
+    pub fn finish(self) -> Option<Loudness> {
+        let absolute_gate = lufs_to_energy(ABSOLUTE_GATE_LUFS);
+        let above_absolute: Vec<f64> = self.blocks.into_iter().filter(|&e| e > absolute_gate).collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+        let mean = above_absolute.iter().sum::<f64>() / above_absolute.len() as f64;
+        let relative_gate = lufs_to_energy(energy_to_lufs(mean) + RELATIVE_GATE_LU);
+        let gated: Vec<f64> = above_absolute.into_iter().filter(|&e| e > relative_gate).collect();
+        let energy = gated.iter().sum::<f64>() / gated.len().max(1) as f64;
+
+        Some(Loudness {
+            integrated_lufs: energy_to_lufs(energy),
+            true_peak: self.true_peak.map_or(0.0, |tp| tp.peak),
+        })
+    }
+}
+
+/// Decode a whole file and measure it. This reads every sample, so keep it off the
+/// async runtime.
+pub fn analyze_loudness(path: &str) -> Result<Option<Loudness>, String> {
+    let source = open_source(path, 0.0)?;
+    let mut meter = LoudnessMeter::new(source.sample_rate(), source.channels());
+    for sample in source {
+        meter.push(sample);
+    }
+    Ok(meter.finish())
+}
+
+/// Measure loudness and the silence at either end in one pass over the file, for
+/// tracks that need both.
+pub fn analyze_loudness_and_silence(path: &str) -> Result<(Option<Loudness>, Silence), String> {
+    let source = open_source(path, 0.0)?;
+    let mut meter = LoudnessMeter::new(source.sample_rate(), source.channels());
+    let mut silence = SilenceMeter::new(source.sample_rate(), source.channels());
+    for sample in source {
+        meter.push(sample);
+        silence.push(sample);
+    }
+    Ok((meter.finish(), silence.finish()))
+}
+
+// ============ Playback ============
+
+/// Gain of a source in the sink, adjustable while it plays.
+#[derive(Clone)]
+pub struct SharedGain(Arc<AtomicU32>);
+
+impl SharedGain {
+    pub fn new(gain: f32) -> Self {
+        Self(Arc::new(AtomicU32::new(gain.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+pub struct GainSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    gain: SharedGain,
+}
+
+impl<I> GainSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, gain: SharedGain) -> Self {
+        Self { input, gain }
+    }
+}
+
+impl<I> Iterator for GainSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.input.next().map(|s| s * self.gain.get())
+    }
+}
+
+impl<I> Source for GainSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+// ============ Database ============
+
+/// Per-track and per-work loudness for the works of some queued tracks.
+#[derive(Default)]
+pub struct LoudnessLookup {
+    pub tracks: HashMap<i64, Loudness>,
+    pub works: HashMap<i64, Loudness>,
+}
+
+/// Read the measured loudness of every track in `work_ids`, and combine each work's tracks.
+pub async fn load_loudness(pool: &SqlitePool, work_ids: &[i64]) -> Result<LoudnessLookup, sqlx::Error> {
+    let mut lookup = LoudnessLookup::default();
+    for &work_id in work_ids {
+        let rows = sqlx::query(
+            "SELECT id, loudness_lufs, true_peak, duration_sec FROM tracks WHERE work_id = ? AND loudness_lufs IS NOT NULL"
+        )
+        .bind(work_id)
+        .fetch_all(pool)
+        .await?;
+
+        let mut parts = Vec::with_capacity(rows.len());
+        for row in rows {
+            let loudness = Loudness {
+                integrated_lufs: row.get(1),
+                true_peak: row.get::<Option<f64>, _>(2).unwrap_or(0.0),
+            };
+            lookup.tracks.insert(row.get(0), loudness);
+            parts.push((loudness, row.get::<i64, _>(3) as f64));
+        }
+        if let Some(work) = Loudness::combine(&parts) {
+            lookup.works.insert(work_id, work);
+        }
+    }
+    Ok(lookup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measure(sample_rate: u32, seconds: f64, signal: impl Fn(f64) -> f32) -> Loudness {
+        let mut meter = LoudnessMeter::new(sample_rate, 1);
+        for n in 0..(sample_rate as f64 * seconds) as usize {
+            meter.push(signal(n as f64 / sample_rate as f64));
+        }
+        meter.finish().unwrap()
+    }
+
+    #[test]
+    fn sine_at_minus_20_dbfs_reads_minus_23_lufs() {
+        // BS.1770: a 997 Hz full-scale sine in one channel measures -3.01 LKFS.
+        for rate in [44_100, 48_000] {
+            let l = measure(rate, 5.0, |t| 0.1 * (2.0 * PI * 997.0 * t).sin() as f32);
+            assert!((l.integrated_lufs + 23.01).abs() < 0.1, "{} Hz: {}", rate, l.integrated_lufs);
+        }
+    }
+
+    #[test]
+    fn true_peak_finds_peaks_between_samples() {
+        // fs/4 with a 45 degree phase: every sample sits at 0.707 of the real peak.
+        let l = measure(48_000, 1.0, |t| 0.5 * (2.0 * PI * 12_000.0 * t + PI / 4.0).sin() as f32);
+        assert!((l.true_peak - 0.5).abs() < 0.03, "{}", l.true_peak);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut meter = LoudnessMeter::new(48_000, 2);
+        for _ in 0..48_000 * 2 {
+            meter.push(0.0);
+        }
+        assert_eq!(meter.finish(), None);
+    }
+
+    #[test]
+    fn gain_is_capped_by_the_peak() {
+        let settings = NormalizationSettings {
+            mode: NormalizationMode::Track,
+            target_lufs: -18.0,
+        };
+        let quiet = Loudness {
+            integrated_lufs: -24.0,
+            true_peak: 0.3,
+        };
+        assert!((normalization_gain(&settings, Some(quiet), None) - 1.995).abs() < 0.01);
+
+        let peaky = Loudness { true_peak: 0.8, ..quiet };
+        assert!((normalization_gain(&settings, Some(peaky), None) - 0.891 / 0.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn work_mode_prefers_the_work_figure() {
+        let settings = NormalizationSettings {
+            mode: NormalizationMode::Work,
+            target_lufs: -20.0,
+        };
+        let track = Loudness {
+            integrated_lufs: -26.0,
+            true_peak: 0.1,
+        };
+        let work = Loudness {
+            integrated_lufs: -20.0,
+            true_peak: 0.5,
+        };
+        assert_eq!(normalization_gain(&settings, Some(track), Some(work)), 1.0);
+        assert!(normalization_gain(&settings, Some(track), None) > 1.9);
+    }
+
+    #[test]
+    fn combine_weights_by_duration() {
+        let a = Loudness {
+            integrated_lufs: -20.0,
+            true_peak: 0.5,
+        };
+        let b = Loudness {
+            integrated_lufs: -30.0,
+            true_peak: 0.9,
+        };
+        let same = Loudness::combine(&[(a, 60.0), (a, 120.0)]).unwrap();
+        assert!((same.integrated_lufs + 20.0).abs() < 1e-9);
+
+        let mixed = Loudness::combine(&[(a, 60.0), (b, 60.0)]).unwrap();
+        // Half the time at -20 and half at -30 is about 2.6 dB under -20.
+        assert!((mixed.integrated_lufs + 22.6).abs() < 0.05, "{}", mixed.integrated_lufs);
+        assert_eq!(mixed.true_peak, 0.9);
+    }
+
+    #[test]
+    fn replaygain_tags_convert_to_loudness() {
+        let l = Loudness::from_replaygain("-6.50 dB", Some("0.988")).unwrap();
+        assert!((l.integrated_lufs + 11.5).abs() < 1e-9);
+        assert!((l.true_peak - 0.988).abs() < 1e-9);
+        assert_eq!(Loudness::from_replaygain("loud", None), None);
+    }
+}
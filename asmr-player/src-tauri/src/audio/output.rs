@@ -0,0 +1,289 @@
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::cpal::{Device, Host};
+use rodio::{OutputStream, OutputStreamHandle, Source};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the output thread checks that the device is alive and looks for the
+/// preferred one coming back.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Handle on an open stream and the device it plays on.
+#[derive(Clone)]
+pub struct OpenedOutput {
+    pub handle: OutputStreamHandle,
+    pub device: String,
+}
+
+/// Told about devices the output thread switched to by itself; None when no device is left.
+pub type OutputWatcher = Box<dyn Fn(Option<OpenedOutput>) + Send>;
+
+enum Request {
+    Open {
+        device: Option<String>,
+        reply: mpsc::Sender<Result<OpenedOutput, String>>,
+    },
+    Watch(OutputWatcher),
+}
+
+/// The output stream lives on a thread of its own, since `OutputStream` can't leave
+/// the thread that created it. Switching devices drops the old stream there rather
+/// than leaking it.
+#[derive(Clone)]
+pub struct Output {
+    requests: mpsc::Sender<Request>,
+}
+
+impl Output {
+    pub fn start() -> Self {
+        let (requests, rx) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("audio-output".to_string())
+            .spawn(move || OutputThread::default().run(rx));
+        if let Err(e) = spawned {
+            eprintln!("[Audio] Failed to start output thread: {}", e);
+        }
+        Self { requests }
+    }
+
+    /// Play through `device` (None for the system default) whenever it is available.
+    /// Returns the output now in use, which stays on another device if this one can't
+    /// be opened right now; fails only if nothing can be opened at all.
+    pub fn open(&self, device: Option<String>) -> Result<OpenedOutput, String> {
+        let (reply, rx) = mpsc::channel();
+        self.requests
+            .send(Request::Open { device, reply })
+            .map_err(|_| "Audio output thread has stopped".to_string())?;
+        rx.recv().map_err(|_| "Audio output thread has stopped".to_string())?
+    }
+
+    pub fn watch(&self, watcher: OutputWatcher) {
+        let _ = self.requests.send(Request::Watch(watcher));
+    }
+}
+
+pub fn list_devices() -> Result<Vec<OutputDevice>, String> {
+    let host = rodio::cpal::default_host();
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+    let devices = host.output_devices().map_err(|e| e.to_string())?;
+    Ok(devices
+        .filter_map(|d| d.name().ok())
+        .map(|name| OutputDevice {
+            is_default: default.as_deref() == Some(name.as_str()),
+            name,
+        })
+        .collect())
+}
+
+/// Open the `preferred` device (None for the default), else keep `current`, else fall
+/// back to the default when the preferred device is gone.
+fn open_with_fallback<T>(
+    preferred: Option<&str>,
+    current: Option<T>,
+    mut open: impl FnMut(Option<&str>) -> Result<T, String>,
+) -> Result<T, String> {
+    match open(preferred) {
+        Ok(opened) => Ok(opened),
+        Err(e) => {
+            eprintln!("[Audio] Can't open output: {}", e);
+            match current {
+                Some(current) => Ok(current),
+                None if preferred.is_some() => open(None),
+                None => Err(e),
+            }
+        }
+    }
+}
+
+fn find_device(host: &Host, name: &str) -> Option<Device> {
+    host.output_devices()
+        .ok()?
+        .find(|d| d.name().is_ok_and(|n| n == name))
+}
+
+/// Endless silence mixed into the stream. Its sample count keeps rising for as long
+/// as the device pulls audio, paused or not, so a stall means the device is gone.
+struct Heartbeat(Arc<AtomicU64>);
+
+impl Iterator for Heartbeat {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Some(0.0)
+    }
+}
+
+impl Source for Heartbeat {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        8_000
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[derive(Default)]
+struct OutputThread {
+    stream: Option<OutputStream>,
+    current: Option<OpenedOutput>,
+    // What the user picked; None follows the system default.
+    preferred: Option<String>,
+    heartbeat: Arc<AtomicU64>,
+    last_beat: u64,
+    watcher: Option<OutputWatcher>,
+}
+
+impl OutputThread {
+    fn run(mut self, requests: mpsc::Receiver<Request>) {
+        loop {
+            match requests.recv_timeout(POLL_INTERVAL) {
+                Ok(Request::Open { device, reply }) => {
+                    self.preferred = device;
+                    let _ = reply.send(self.open_preferred());
+                }
+                Ok(Request::Watch(watcher)) => self.watcher = Some(watcher),
+                Err(RecvTimeoutError::Timeout) => self.check_device(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn open(&mut self, device: Option<&str>) -> Result<OpenedOutput, String> {
+        let host = rodio::cpal::default_host();
+        let target = match device {
+            Some(name) => find_device(&host, name).ok_or_else(|| format!("Output device not found: {}", name))?,
+            None => host.default_output_device().ok_or("No default output device")?,
+        };
+        let name = target.name().map_err(|e| e.to_string())?;
+        // Some devices only take one stream, so let go of ours before reopening it.
+        if self.device() == Some(name.as_str()) {
+            self.close();
+        }
+        let (stream, handle) = OutputStream::try_from_device(&target).map_err(|e| e.to_string())?;
+
+        self.heartbeat = Arc::new(AtomicU64::new(0));
+        self.last_beat = 0;
+        handle
+            .play_raw(Heartbeat(self.heartbeat.clone()))
+            .map_err(|e| e.to_string())?;
+        // Replacing the old stream drops it, which stops its callback and frees the device.
+        self.stream = Some(stream);
+        println!("[Audio] Output opened on {}", name);
+        let opened = OpenedOutput { handle, device: name };
+        self.current = Some(opened.clone());
+        Ok(opened)
+    }
+
+    fn open_preferred(&mut self) -> Result<OpenedOutput, String> {
+        let preferred = self.preferred.clone();
+        let current = self.current.clone();
+        open_with_fallback(preferred.as_deref(), current, |device| self.open(device))
+    }
+
+    fn device(&self) -> Option<&str> {
+        self.current.as_ref().map(|c| c.device.as_str())
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+        self.current = None;
+    }
+
+    fn check_device(&mut self) {
+        let Some(watcher) = self.watcher.take() else {
+            return;
+        };
+        if let Some(change) = self.follow_device() {
+            watcher(change);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    /// Reopen when the device stops pulling audio, move back to the preferred device
+    /// once it is plugged in again, and keep trying while there is no device at all.
+    /// Returns the new output if the stream changed.
+    fn follow_device(&mut self) -> Option<Option<OpenedOutput>> {
+        let beat = self.heartbeat.load(Ordering::Relaxed);
+        let alive = self.stream.is_some() && beat != self.last_beat;
+        self.last_beat = beat;
+
+        if alive {
+            let preferred = self.preferred.clone().filter(|p| self.device() != Some(p.as_str()))?;
+            find_device(&rodio::cpal::default_host(), &preferred)?;
+            return self.open(Some(&preferred)).ok().map(Some);
+        }
+
+        let had_device = self.stream.is_some();
+        // Whatever we had is dead, so don't fall back to it.
+        self.close();
+        match self.open_preferred() {
+            Ok(opened) => Some(Some(opened)),
+            Err(e) if had_device => {
+                eprintln!("[Audio] Output device lost: {}", e);
+                Some(None)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens by name among `present`; None opens the default, when there is one.
+    fn opener<'a>(
+        present: &'a [&'a str],
+        default: Option<&'a str>,
+    ) -> impl FnMut(Option<&str>) -> Result<String, String> + 'a {
+        move |device| match device.or(default) {
+            Some(name) if present.contains(&name) => Ok(name.to_string()),
+            Some(name) => Err(format!("Output device not found: {}", name)),
+            None => Err("No default output device".to_string()),
+        }
+    }
+
+    #[test]
+    fn a_saved_device_that_is_gone_falls_back_to_the_default() {
+        let present = ["Speakers"];
+        let opened = open_with_fallback(Some("USB DAC"), None, opener(&present, Some("Speakers")));
+        assert_eq!(opened, Ok("Speakers".to_string()));
+
+        let opened = open_with_fallback(Some("USB DAC"), None, opener(&present, None));
+        assert_eq!(opened, Err("No default output device".to_string()));
+    }
+
+    #[test]
+    fn a_device_that_is_gone_keeps_playing_where_it_was() {
+        let present = ["Speakers", "Headphones"];
+        let opened = open_with_fallback(
+            Some("USB DAC"),
+            Some("Headphones".to_string()),
+            opener(&present, Some("Speakers")),
+        );
+        assert_eq!(opened, Ok("Headphones".to_string()));
+
+        let opened = open_with_fallback(Some("Headphones"), Some("Speakers".to_string()), opener(&present, None));
+        assert_eq!(opened, Ok("Headphones".to_string()));
+    }
+}
@@ -0,0 +1,95 @@
+use sqlx::SqlitePool;
+
+/// Upsert the resume position for a work into `track_progress`.
+pub async fn save_progress(
+    pool: &SqlitePool,
+    work_id: i64,
+    track_id: i64,
+    position_sec: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO track_progress (work_id, track_id, position_sec, updated_at)
+        VALUES (?, ?, ?, CURRENT_TIMESTAMP)
+        ON CONFLICT(work_id) DO UPDATE SET
+            track_id = excluded.track_id,
+            position_sec = excluded.position_sec,
+            updated_at = CURRENT_TIMESTAMP
+        "#
+    )
+    .bind(work_id)
+    .bind(track_id)
+    .bind(position_sec)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Upsert the resume position for the library track stored at `path`, for entries
+/// queued without their ids. Returns false if no track is stored there.
+pub async fn save_progress_at_path(pool: &SqlitePool, path: &str, position_sec: f64) -> Result<bool, sqlx::Error> {
+    let ids: Option<(i64, i64)> = sqlx::query_as("SELECT work_id, id FROM tracks WHERE path = ?")
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+
+    match ids {
+        Some((work_id, track_id)) => {
+            save_progress(pool, work_id, track_id, position_sec).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bookmarks::load_track_by_path;
+    use super::*;
+    use crate::test_db;
+
+    /// One work with one track.
+    async fn library() -> SqlitePool {
+        let pool = test_db::library().await;
+        sqlx::query("INSERT INTO works (id, title, dir_path) VALUES (7, 'Work', '/lib/RJ01234567')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO tracks (id, work_id, title, path) VALUES (70, 7, '01', '/lib/RJ01234567/01.mp3')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    async fn saved(pool: &SqlitePool) -> Option<(i64, i64, f64)> {
+        sqlx::query_as("SELECT work_id, track_id, position_sec FROM track_progress")
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn progress_is_saved_for_a_track_played_by_path() {
+        let pool = library().await;
+
+        // play_track resolves the library entry, so its progress is keyed like any other.
+        let track = load_track_by_path(&pool, "/lib/RJ01234567/01.mp3").await.unwrap().unwrap();
+        assert_eq!((track.id, track.work_id), (70, Some(7)));
+        save_progress(&pool, 7, track.id, 12.5).await.unwrap();
+        assert_eq!(saved(&pool).await, Some((7, 70, 12.5)));
+
+        // An entry queued without its ids is matched by path.
+        assert!(save_progress_at_path(&pool, "/lib/RJ01234567/01.mp3", 30.0).await.unwrap());
+        assert_eq!(saved(&pool).await, Some((7, 70, 30.0)));
+    }
+
+    #[tokio::test]
+    async fn files_outside_the_library_are_not_saved() {
+        let pool = library().await;
+        assert!(load_track_by_path(&pool, "/downloads/01.mp3").await.unwrap().is_none());
+        assert!(!save_progress_at_path(&pool, "/downloads/01.mp3", 30.0).await.unwrap());
+        assert_eq!(saved(&pool).await, None);
+    }
+}
@@ -0,0 +1,600 @@
+use super::shuffle::ShuffleOrder;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A track as sent by the frontend when building the play queue.
+/// Field names match the `Track` shape returned by `get_work_tracks` / `get_playlist_tracks`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueTrack {
+    pub id: i64,
+    #[serde(default)]
+    pub work_id: Option<i64>,
+    pub title: String,
+    pub path: String,
+    #[serde(default)]
+    pub duration: f64,
+    #[serde(default)]
+    pub work_title: Option<String>,
+    #[serde(default)]
+    pub cover_path: Option<String>,
+}
+
+/// One slot in the queue. The same track may be queued twice, so every slot
+/// gets its own `entry_id` which is what the playback engine keys on.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueEntry {
+    pub entry_id: u64,
+    #[serde(flatten)]
+    pub track: QueueTrack,
+}
+
+/// Payload of the `queue-changed` event and the `get_queue` command.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSnapshot {
+    pub entries: Vec<QueueEntry>,
+    pub current_index: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    /// Loop the run of consecutive entries from the current work.
+    Work,
+    Playlist,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Tracks,
+    /// Shuffle works but play each work's tracks in queue order.
+    Works,
+}
+
+/// Payload of the `playback-mode-changed` event and the `get_playback_mode` command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaybackMode {
+    pub repeat: RepeatMode,
+    pub shuffle: ShuffleMode,
+    /// Same seed and same queue, same shuffled order.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// What happened to playback when an entry was removed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RemoveOutcome {
+    /// An entry other than the current one was removed.
+    Other,
+    /// The current entry was removed and the one that followed it is now current.
+    CurrentReplaced,
+    /// The current entry was removed and nothing follows it.
+    CurrentRemovedAtEnd,
+}
+
+#[derive(Default)]
+pub struct PlayQueue {
+    entries: Vec<QueueEntry>,
+    current: Option<usize>,
+    next_entry_id: u64,
+    mode: PlaybackMode,
+    // Only while shuffling.
+    shuffle: Option<ShuffleOrder>,
+}
+
+/// Whether two entries belong to the same work. Ad-hoc tracks are each their own work.
+pub fn same_work(a: &QueueEntry, b: &QueueEntry) -> bool {
+    a.track.work_id.is_some() && a.track.work_id == b.track.work_id
+}
+
+impl PlayQueue {
+    fn make_entries(&mut self, tracks: Vec<QueueTrack>) -> Vec<QueueEntry> {
+        tracks
+            .into_iter()
+            .map(|track| {
+                self.next_entry_id += 1;
+                QueueEntry {
+                    entry_id: self.next_entry_id,
+                    track,
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the whole queue and point at `start_index`.
+    pub fn replace(&mut self, tracks: Vec<QueueTrack>, start_index: usize) -> Option<&QueueEntry> {
+        self.entries = self.make_entries(tracks);
+        self.current = if start_index < self.entries.len() {
+            Some(start_index)
+        } else if self.entries.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+        self.reshuffle();
+        self.current()
+    }
+
+    /// Append tracks to the end of the queue. While shuffling they are mixed into
+    /// the part of the order that hasn't played yet.
+    pub fn enqueue(&mut self, tracks: Vec<QueueTrack>) {
+        let new_entries = self.make_entries(tracks);
+        let units = self.units_of(&new_entries);
+        self.entries.extend(new_entries);
+
+        let works = self.mode.shuffle == ShuffleMode::Works;
+        let entries = &self.entries;
+        if let Some(order) = self.shuffle.as_mut() {
+            let find = |id: u64| entries.iter().find(|e| e.entry_id == id);
+            order.insert_random(units, |a, b| {
+                !works || !matches!((find(a), find(b)), (Some(a), Some(b)) if same_work(a, b))
+            });
+        }
+    }
+
+    /// Insert tracks right after the current entry (or at the front if nothing is current).
+    pub fn insert_next(&mut self, tracks: Vec<QueueTrack>) {
+        let at = self.current.map(|i| i + 1).unwrap_or(0);
+        let new_entries = self.make_entries(tracks);
+        if let Some(order) = self.shuffle.as_mut() {
+            let ids: Vec<u64> = new_entries.iter().map(|e| e.entry_id).collect();
+            order.insert_next(&ids);
+        }
+        self.entries.splice(at..at, new_entries);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<RemoveOutcome, String> {
+        if index >= self.entries.len() {
+            return Err(format!("Queue index {} out of range", index));
+        }
+        let removed = self.entries.remove(index);
+        let replacement = self.shuffle.as_mut().and_then(|o| o.remove(removed.entry_id));
+
+        match self.current {
+            Some(cur) if cur == index => {
+                let next = match self.shuffle {
+                    Some(_) => replacement.and_then(|id| self.index_of(id)),
+                    None => (index < self.entries.len()).then_some(index),
+                };
+                self.current = next;
+                if next.is_some() {
+                    Ok(RemoveOutcome::CurrentReplaced)
+                } else {
+                    Ok(RemoveOutcome::CurrentRemovedAtEnd)
+                }
+            }
+            Some(cur) if cur > index => {
+                self.current = Some(cur - 1);
+                Ok(RemoveOutcome::Other)
+            }
+            _ => Ok(RemoveOutcome::Other),
+        }
+    }
+
+    /// Move the entry at `from` so that it ends up at `to`, keeping the current entry tracked.
+    pub fn move_entry(&mut self, from: usize, to: usize) -> Result<(), String> {
+        let len = self.entries.len();
+        if from >= len || to >= len {
+            return Err(format!("Queue index out of range ({} -> {}, len {})", from, to, len));
+        }
+        let current_id = self.current().map(|e| e.entry_id);
+        let entry = self.entries.remove(from);
+        self.entries.insert(to, entry);
+        if let Some(id) = current_id {
+            self.current = self.index_of(id);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.current = None;
+        self.reshuffle();
+    }
+
+    pub fn mode(&self) -> PlaybackMode {
+        self.mode
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.mode.repeat = repeat;
+    }
+
+    /// Switch shuffling. A new order starts from the current entry.
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode, seed: u64) {
+        self.mode.shuffle = shuffle;
+        self.mode.seed = seed;
+        self.reshuffle();
+    }
+
+    fn reshuffle(&mut self) {
+        self.shuffle = match self.mode.shuffle {
+            ShuffleMode::Off => None,
+            _ => Some(ShuffleOrder::new(
+                self.mode.seed,
+                self.units_of(&self.entries),
+                self.current().map(|e| e.entry_id),
+            )),
+        };
+    }
+
+    /// What the shuffle keeps together: single entries, or runs of the same work.
+    fn units_of(&self, entries: &[QueueEntry]) -> Vec<Vec<u64>> {
+        let ids = |run: &[QueueEntry]| run.iter().map(|e| e.entry_id).collect();
+        match self.mode.shuffle {
+            ShuffleMode::Works => entries.chunk_by(same_work).map(ids).collect(),
+            _ => entries.chunks(1).map(ids).collect(),
+        }
+    }
+
+    /// The run of consecutive entries from the same work around `index`.
+    fn work_block(&self, index: usize) -> Range<usize> {
+        let entry = &self.entries[index];
+        let mut start = index;
+        while start > 0 && same_work(&self.entries[start - 1], entry) {
+            start -= 1;
+        }
+        let mut end = index + 1;
+        while end < self.entries.len() && same_work(&self.entries[end], entry) {
+            end += 1;
+        }
+        start..end
+    }
+
+    pub fn current(&self) -> Option<&QueueEntry> {
+        self.current.and_then(|i| self.entries.get(i))
+    }
+
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn index_of(&self, entry_id: u64) -> Option<usize> {
+        self.entries.iter().position(|e| e.entry_id == entry_id)
+    }
+
+    /// Where a library track sits in the queue, preferring the current entry.
+    pub fn index_of_track(&self, track_id: i64) -> Option<usize> {
+        if self.current().is_some_and(|e| e.track.id == track_id) {
+            return self.current;
+        }
+        self.entries.iter().position(|e| e.track.id == track_id)
+    }
+
+    pub fn entry(&self, index: usize) -> Option<&QueueEntry> {
+        self.entries.get(index)
+    }
+
+    /// Index of the entry that plays after `index` under `repeat`.
+    fn successor(&self, index: usize, repeat: RepeatMode) -> Option<usize> {
+        let entry = self.entries.get(index)?;
+        match repeat {
+            RepeatMode::One => Some(index),
+            RepeatMode::Work => {
+                let block = self.work_block(index);
+                Some(if index + 1 < block.end { index + 1 } else { block.start })
+            }
+            RepeatMode::Off | RepeatMode::Playlist => {
+                let wrap = repeat == RepeatMode::Playlist;
+                match self.shuffle {
+                    Some(ref order) => {
+                        let next = order.next_after(entry.entry_id).or_else(|| {
+                            wrap.then(|| order.peek_next_pass(self.units_of(&self.entries), entry.entry_id))
+                                .flatten()
+                        })?;
+                        self.index_of(next)
+                    }
+                    None if index + 1 < self.entries.len() => Some(index + 1),
+                    None => (wrap && !self.entries.is_empty()).then_some(0),
+                }
+            }
+        }
+    }
+
+    /// Where playback starts when nothing is current.
+    fn first_index(&self) -> Option<usize> {
+        match self.shuffle {
+            Some(ref order) => order.first().and_then(|id| self.index_of(id)),
+            None => (!self.entries.is_empty()).then_some(0),
+        }
+    }
+
+    /// Index of the entry that plays after `index` finishes on its own.
+    pub fn successor_of(&self, index: usize) -> Option<usize> {
+        self.successor(index, self.mode.repeat)
+    }
+
+    /// Index of the entry that should play after the current one finishes.
+    pub fn next_index(&self) -> Option<usize> {
+        match self.current {
+            Some(i) => self.successor(i, self.mode.repeat),
+            None => self.first_index(),
+        }
+    }
+
+    /// Where "next" goes. Repeat-one only holds on to a track that ends by itself.
+    pub fn skip_index(&self) -> Option<usize> {
+        let repeat = match self.mode.repeat {
+            RepeatMode::One => RepeatMode::Off,
+            repeat => repeat,
+        };
+        match self.current {
+            Some(i) => self.successor(i, repeat),
+            None => self.first_index(),
+        }
+    }
+
+    /// Where "previous" goes: the entry played before the current one in this pass.
+    /// At the start it restarts the current entry.
+    pub fn previous_index(&self) -> Option<usize> {
+        let current = self.current?;
+        let previous = match self.shuffle {
+            Some(ref order) => order
+                .previous_before(self.entries[current].entry_id)
+                .and_then(|id| self.index_of(id)),
+            None => current.checked_sub(1),
+        };
+        Some(previous.unwrap_or(current))
+    }
+
+    pub fn peek_next(&self) -> Option<&QueueEntry> {
+        self.next_index().and_then(|i| self.entries.get(i))
+    }
+
+    /// Point at `index` and keep the shuffle order in step.
+    fn enter(&mut self, index: usize) {
+        let previous = self.current().map(|e| e.entry_id);
+        self.current = Some(index);
+        let entry_id = self.entries[index].entry_id;
+        let following: Vec<u64> = match self.mode.shuffle {
+            ShuffleMode::Works => {
+                let block = self.work_block(index);
+                self.entries[index + 1..block.end].iter().map(|e| e.entry_id).collect()
+            }
+            _ => Vec::new(),
+        };
+        let units = self.units_of(&self.entries);
+        let wraps = self.mode.repeat == RepeatMode::Playlist;
+        let Some(order) = self.shuffle.as_mut() else {
+            return;
+        };
+        // Repeat-playlist rolled over to the start of a fresh pass.
+        if let Some(previous) = previous.filter(|_| wraps && order.is_exhausted()) {
+            if previous != entry_id && order.peek_next_pass(units.clone(), previous) == Some(entry_id) {
+                order.start_next_pass(units, previous);
+            }
+        }
+        order.advance_to(entry_id, &following);
+    }
+
+    /// Make `index` the current entry.
+    pub fn jump(&mut self, index: usize) -> Option<&QueueEntry> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.enter(index);
+        self.current()
+    }
+
+    /// Make the entry with `entry_id` current. Used when the engine reports
+    /// that a preloaded entry has started playing.
+    pub fn set_current_entry(&mut self, entry_id: u64) -> bool {
+        match self.index_of(entry_id) {
+            Some(i) => {
+                self.enter(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            entries: self.entries.clone(),
+            current_index: self.current,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A minute-long track, for queues built in tests.
+    pub(crate) fn track(id: i64, work_id: Option<i64>) -> QueueTrack {
+        QueueTrack {
+            id,
+            work_id,
+            title: format!("Track {}", id),
+            path: format!("/tmp/{}.flac", id),
+            duration: 60.0,
+            work_title: None,
+            cover_path: None,
+        }
+    }
+
+    /// Three works of three tracks each; track ids are work * 10 + part.
+    fn queue_of_works() -> PlayQueue {
+        let mut queue = PlayQueue::default();
+        let tracks = (1..=3).flat_map(|w| (1..=3).map(move |p| track(w * 10 + p, Some(w)))).collect();
+        queue.replace(tracks, 0);
+        queue
+    }
+
+    /// Track ids in the order playback would visit them, following automatic advances.
+    fn play_order(queue: &mut PlayQueue, count: usize) -> Vec<i64> {
+        let mut played = vec![queue.current().unwrap().track.id];
+        for _ in 1..count {
+            let Some(next) = queue.next_index() else { break };
+            queue.jump(next);
+            played.push(queue.current().unwrap().track.id);
+        }
+        played
+    }
+
+    #[test]
+    fn repeat_modes_pick_the_next_entry() {
+        let mut queue = queue_of_works();
+        queue.jump(2);
+        assert_eq!(queue.next_index(), Some(3));
+
+        queue.set_repeat(RepeatMode::One);
+        assert_eq!(queue.next_index(), Some(2));
+        assert_eq!(queue.skip_index(), Some(3));
+
+        queue.set_repeat(RepeatMode::Work);
+        assert_eq!(queue.next_index(), Some(0));
+
+        queue.jump(8);
+        queue.set_repeat(RepeatMode::Off);
+        assert_eq!(queue.next_index(), None);
+        queue.set_repeat(RepeatMode::Playlist);
+        assert_eq!(queue.next_index(), Some(0));
+    }
+
+    #[test]
+    fn next_and_previous_stop_at_the_ends() {
+        let mut queue = queue_of_works();
+        assert_eq!(queue.previous_index(), Some(0));
+        queue.jump(1);
+        assert_eq!(queue.previous_index(), Some(0));
+
+        queue.jump(8);
+        assert_eq!(queue.next_index(), None);
+        assert_eq!(queue.skip_index(), None);
+        assert_eq!(queue.previous_index(), Some(7));
+        assert!(queue.jump(9).is_none());
+        assert_eq!(queue.current_index(), Some(8));
+
+        let mut empty = PlayQueue::default();
+        assert_eq!(empty.next_index(), None);
+        assert_eq!(empty.previous_index(), None);
+        assert!(empty.replace(Vec::new(), 0).is_none());
+    }
+
+    #[test]
+    fn repeat_work_loops_within_the_work_and_ad_hoc_tracks_on_themselves() {
+        let mut queue = queue_of_works();
+        queue.set_repeat(RepeatMode::Work);
+        queue.jump(4);
+        assert_eq!(queue.next_index(), Some(5));
+        queue.jump(5);
+        assert_eq!(queue.next_index(), Some(3));
+        queue.set_repeat(RepeatMode::Playlist);
+        assert_eq!(queue.next_index(), Some(6));
+
+        let mut ad_hoc = PlayQueue::default();
+        ad_hoc.replace(vec![track(0, None), track(0, None)], 1);
+        ad_hoc.set_repeat(RepeatMode::Work);
+        assert_eq!(ad_hoc.next_index(), Some(1));
+        ad_hoc.set_repeat(RepeatMode::Playlist);
+        assert_eq!(ad_hoc.next_index(), Some(0));
+    }
+
+    #[test]
+    fn edits_before_the_current_entry_keep_it_current() {
+        let mut queue = queue_of_works();
+        queue.jump(4);
+        let current = queue.current().unwrap().entry_id;
+
+        assert_eq!(queue.remove(1), Ok(RemoveOutcome::Other));
+        assert_eq!(queue.current_index(), Some(3));
+        assert_eq!(queue.current().unwrap().entry_id, current);
+
+        queue.move_entry(5, 0).unwrap();
+        assert_eq!(queue.current_index(), Some(4));
+        assert_eq!(queue.current().unwrap().entry_id, current);
+
+        queue.insert_next(vec![track(41, Some(4))]);
+        assert_eq!(queue.current_index(), Some(4));
+        assert_eq!(queue.entry(5).unwrap().track.id, 41);
+        assert_eq!(queue.next_index(), Some(5));
+
+        queue.jump(queue.len() - 1);
+        assert_eq!(queue.remove(queue.len() - 1), Ok(RemoveOutcome::CurrentRemovedAtEnd));
+        assert_eq!(queue.current_index(), None);
+        assert!(queue.remove(queue.len()).is_err());
+    }
+
+    #[test]
+    fn entries_of_a_replaced_queue_go_stale() {
+        let mut queue = queue_of_works();
+        let old = queue.entry(3).unwrap().entry_id;
+
+        queue.replace((1..=3).map(|p| track(10 + p, Some(1))).collect(), 0);
+        assert_eq!(queue.index_of(old), None);
+        assert!(!queue.set_current_entry(old));
+        assert_eq!(queue.current_index(), Some(0));
+
+        // The same track queued again gets a fresh entry id.
+        let ids: Vec<u64> = (0..queue.len()).map(|i| queue.entry(i).unwrap().entry_id).collect();
+        assert!(ids.iter().all(|&id| id > old));
+        assert!(queue.set_current_entry(ids[2]));
+        assert_eq!(queue.current_index(), Some(2));
+    }
+
+    #[test]
+    fn seeded_shuffle_is_reproducible_and_covers_everything() {
+        let mut a = queue_of_works();
+        let mut b = queue_of_works();
+        a.set_shuffle(ShuffleMode::Tracks, 99);
+        b.set_shuffle(ShuffleMode::Tracks, 99);
+        let order = play_order(&mut a, 20);
+        assert_eq!(order, play_order(&mut b, 20));
+
+        assert_eq!(order.len(), 9);
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, vec![11, 12, 13, 21, 22, 23, 31, 32, 33]);
+    }
+
+    #[test]
+    fn repeat_playlist_reshuffles_each_pass() {
+        let mut queue = queue_of_works();
+        queue.set_shuffle(ShuffleMode::Tracks, 3);
+        queue.set_repeat(RepeatMode::Playlist);
+        let order = play_order(&mut queue, 27);
+        assert_eq!(order.len(), 27);
+        for pass in order.chunks(9) {
+            let mut sorted = pass.to_vec();
+            sorted.sort_unstable();
+            sorted.dedup();
+            assert_eq!(sorted.len(), 9, "{:?}", order);
+        }
+        assert_ne!(order[8], order[9]);
+        assert_ne!(order[..9], order[9..18]);
+    }
+
+    #[test]
+    fn shuffled_works_keep_their_tracks_in_order() {
+        let mut queue = queue_of_works();
+        queue.jump(1);
+        queue.set_shuffle(ShuffleMode::Works, 11);
+        let order = play_order(&mut queue, 20);
+        assert_eq!(&order[..2], &[12, 13]);
+        assert_eq!(order.len(), 8);
+        for work in order[2..].chunks(3) {
+            assert_eq!(work[1], work[0] + 1);
+            assert_eq!(work[2], work[0] + 2);
+        }
+    }
+
+    #[test]
+    fn removing_the_current_entry_while_shuffled_moves_on_in_shuffle_order() {
+        let mut queue = queue_of_works();
+        queue.set_shuffle(ShuffleMode::Tracks, 5);
+        let next = queue.next_index().unwrap();
+        let next_id = queue.entry(next).unwrap().entry_id;
+        assert_eq!(queue.remove(0), Ok(RemoveOutcome::CurrentReplaced));
+        assert_eq!(queue.current().map(|e| e.entry_id), Some(next_id));
+    }
+}
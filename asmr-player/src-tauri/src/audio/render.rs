@@ -0,0 +1,585 @@
+use super::flac::FlacWriter;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::dynamic_mixer::{self, DynamicMixer, DynamicMixerController};
+use rodio::queue::SourcesQueueOutput;
+use rodio::source::SeekError;
+use rodio::{Sink, Source};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Slice of audio the render thread pulls at a time.
+const BLOCK: Duration = Duration::from_millis(10);
+
+/// Sample format of the file. FLAC has no floating point, so `Float32` is written to
+/// it as 24-bit integers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderFormat {
+    #[default]
+    Int16,
+    Float32,
+}
+
+/// Where and how a render writes. The file is WAV or FLAC, by its extension.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// WAV or FLAC file to write; None plays into nothing (the null output).
+    pub path: Option<String>,
+    /// Pace the stream like a sound card instead of rendering as fast as it decodes.
+    pub realtime: bool,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: RenderFormat,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            realtime: false,
+            sample_rate: 48_000,
+            channels: 2,
+            format: RenderFormat::Int16,
+        }
+    }
+}
+
+/// Payload of `get_render_status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderStatus {
+    pub path: Option<String>,
+    pub realtime: bool,
+    /// Audio written so far.
+    pub seconds: f64,
+}
+
+/// Payload of `stop_render` and the `render-finished` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderSummary {
+    pub path: Option<String>,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+/// State shared between a `RenderOutput` and its thread.
+#[derive(Default)]
+struct Shared {
+    // Whether the player is playing; paused and stopped stretches aren't recorded.
+    active: AtomicBool,
+    tracks: Arc<TrackCount>,
+    stop: AtomicBool,
+    frames: AtomicU64,
+    error: Mutex<Option<String>>,
+}
+
+/// Stands in for the sound card: sinks opened on it are mixed and written to a WAV
+/// file, or thrown away, either in real time or as fast as they decode.
+pub struct RenderOutput {
+    mixer: Arc<DynamicMixerController<f32>>,
+    shared: Arc<Shared>,
+    settings: RenderSettings,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderOutput {
+    pub fn start(settings: RenderSettings) -> Result<Self, String> {
+        if settings.sample_rate == 0 || settings.channels == 0 {
+            return Err("Sample rate and channel count must not be zero".to_string());
+        }
+        let writer = match settings.path {
+            Some(ref path) => Some(Writer::create(Path::new(path), &settings)?),
+            None => None,
+        };
+
+        let (mixer, output) = dynamic_mixer::mixer(settings.channels, settings.sample_rate);
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+        let realtime = settings.realtime;
+        let thread = thread::Builder::new()
+            .name("audio-render".to_string())
+            .spawn(move || run(output, writer, realtime, thread_shared))
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            mixer,
+            shared,
+            settings,
+            thread: Some(thread),
+        })
+    }
+
+    /// A sink that plays into this render.
+    pub fn sink(&self) -> OutputSink {
+        let (sink, queue) = Sink::new_idle();
+        let alive = Arc::new(());
+        self.mixer.add(SinkInput::new(queue, Arc::downgrade(&alive)));
+        OutputSink {
+            sink,
+            render: Some(RenderInput {
+                _alive: alive,
+                tracks: self.shared.tracks.clone(),
+            }),
+        }
+    }
+
+    pub fn set_active(&self, active: bool) {
+        self.shared.active.store(active, Ordering::Release);
+    }
+
+    fn seconds(&self) -> f64 {
+        self.shared.frames.load(Ordering::Relaxed) as f64 / self.settings.sample_rate as f64
+    }
+
+    pub fn status(&self) -> RenderStatus {
+        RenderStatus {
+            path: self.settings.path.clone(),
+            realtime: self.settings.realtime,
+            seconds: self.seconds(),
+        }
+    }
+
+    /// Stop the thread and close the file.
+    pub fn finish(mut self) -> RenderSummary {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                self.record_error("Render thread panicked".to_string());
+            }
+        }
+        RenderSummary {
+            path: self.settings.path.clone(),
+            seconds: self.seconds(),
+            error: self.shared.error.lock().ok().and_then(|e| e.clone()),
+        }
+    }
+
+    fn record_error(&self, error: String) {
+        if let Ok(mut slot) = self.shared.error.lock() {
+            slot.get_or_insert(error);
+        }
+    }
+}
+
+impl Drop for RenderOutput {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+    }
+}
+
+/// The file a render writes to.
+enum Writer {
+    Wav(WavWriter<BufWriter<File>>, RenderFormat),
+    // With its bits per sample.
+    Flac(FlacWriter, u32),
+}
+
+impl Writer {
+    fn create(path: &Path, settings: &RenderSettings) -> Result<Self, String> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("wav") => {
+                let (bits_per_sample, sample_format) = match settings.format {
+                    RenderFormat::Int16 => (16, SampleFormat::Int),
+                    RenderFormat::Float32 => (32, SampleFormat::Float),
+                };
+                let spec = WavSpec {
+                    channels: settings.channels,
+                    sample_rate: settings.sample_rate,
+                    bits_per_sample,
+                    sample_format,
+                };
+                let writer = WavWriter::create(path, spec).map_err(|e| e.to_string())?;
+                Ok(Writer::Wav(writer, settings.format))
+            }
+            Some("flac") => {
+                let bits_per_sample = match settings.format {
+                    RenderFormat::Int16 => 16,
+                    RenderFormat::Float32 => 24,
+                };
+                let writer = FlacWriter::create(path, settings.channels, settings.sample_rate, bits_per_sample)
+                    .map_err(|e| e.to_string())?;
+                Ok(Writer::Flac(writer, bits_per_sample))
+            }
+            _ => Err(format!("Can only render to .wav or .flac files, not {}", path.display())),
+        }
+    }
+
+    fn write_frame(&mut self, frame: &[f32]) -> Result<(), String> {
+        match self {
+            Writer::Wav(w, RenderFormat::Int16) => frame
+                .iter()
+                .try_for_each(|&s| w.write_sample(to_int(s, 16) as i16))
+                .map_err(|e| e.to_string()),
+            Writer::Wav(w, RenderFormat::Float32) => {
+                frame.iter().try_for_each(|&s| w.write_sample(s)).map_err(|e| e.to_string())
+            }
+            Writer::Flac(w, bits) => frame
+                .iter()
+                .try_for_each(|&s| w.write_sample(to_int(s, *bits)))
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    fn finalize(self) -> Result<(), String> {
+        match self {
+            Writer::Wav(w, _) => w.finalize().map_err(|e| e.to_string()),
+            Writer::Flac(w, _) => w.finalize().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// `sample` as a signed integer of `bits` bits.
+fn to_int(sample: f32, bits: u32) -> i32 {
+    (sample.clamp(-1.0, 1.0) * ((1 << (bits - 1)) - 1) as f32) as i32
+}
+
+fn run(
+    mut mixer: DynamicMixer<f32>,
+    mut writer: Option<Writer>,
+    realtime: bool,
+    shared: Arc<Shared>,
+) {
+    let channels = mixer.channels() as usize;
+    let rate = mixer.sample_rate() as u64;
+    let block_frames = (rate * BLOCK.as_millis() as u64 / 1000).max(1);
+    // When recording started, and how many frames had been written by then.
+    let mut pace: Option<(Instant, u64)> = None;
+    let mut frame = vec![0.0f32; channels];
+
+    while !shared.stop.load(Ordering::Acquire) {
+        let idle = shared.tracks.queued.load(Ordering::Acquire) == 0;
+        if idle || !shared.active.load(Ordering::Acquire) {
+            // Keep pulling like a sound card would: seeks and stops only reach sources
+            // that are pulled, and idle sinks only pick up a new track between silences.
+            for _ in 0..block_frames * channels as u64 {
+                if mixer.next().is_none() {
+                    break;
+                }
+            }
+            pace = None;
+            thread::sleep(BLOCK);
+            continue;
+        }
+
+        let mut written = 0;
+        let mut pulled = 0;
+        for _ in 0..block_frames {
+            // The mixer stays frame-aligned across gaps, so pull whole frames.
+            let mut any = false;
+            for slot in frame.iter_mut() {
+                let sample = mixer.next();
+                any |= sample.is_some();
+                *slot = sample.unwrap_or(0.0);
+            }
+            if !any {
+                break;
+            }
+            pulled += 1;
+            // An empty sink fills in silence until its next track comes round; leave it out.
+            if shared.tracks.sounding.load(Ordering::Acquire) == 0 {
+                continue;
+            }
+            if let Some(ref mut w) = writer {
+                if let Err(e) = w.write_frame(&frame) {
+                    eprintln!("[Audio] Render write failed: {}", e);
+                    if let Ok(mut slot) = shared.error.lock() {
+                        slot.get_or_insert(e);
+                    }
+                    // Carry on into nothing so the player doesn't stall.
+                    writer = None;
+                }
+            }
+            written += 1;
+        }
+        let total = shared.frames.fetch_add(written, Ordering::Relaxed) + written;
+        if pulled == 0 {
+            thread::sleep(BLOCK);
+            continue;
+        }
+
+        if realtime && written > 0 {
+            let (start, start_frames) = *pace.get_or_insert((Instant::now(), total - written));
+            let due = start + Duration::from_secs_f64((total - start_frames) as f64 / rate as f64);
+            if let Some(ahead) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(ahead);
+            }
+        }
+    }
+
+    if let Some(w) = writer {
+        if let Err(e) = w.finalize() {
+            if let Ok(mut slot) = shared.error.lock() {
+                slot.get_or_insert(e);
+            }
+        }
+    }
+}
+
+/// The output of one render sink, mixed in until the sink is dropped.
+///
+/// Reads a sample ahead: the queue describes the track it just finished until it is
+/// pulled again, which would have the mixer convert the start of the next one wrongly.
+struct SinkInput {
+    queue: SourcesQueueOutput<f32>,
+    alive: Weak<()>,
+    next: Option<f32>,
+}
+
+impl SinkInput {
+    fn new(mut queue: SourcesQueueOutput<f32>, alive: Weak<()>) -> Self {
+        let next = queue.next();
+        Self { queue, alive, next }
+    }
+}
+
+impl Iterator for SinkInput {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.alive.upgrade()?;
+        let sample = self.next.take()?;
+        self.next = self.queue.next();
+        Some(sample)
+    }
+}
+
+impl Source for SinkInput {
+    fn current_frame_len(&self) -> Option<usize> {
+        let held = self.next.is_some() as usize;
+        self.queue.current_frame_len().map(|len| len + held)
+    }
+
+    fn channels(&self) -> u16 {
+        self.queue.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.queue.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.queue.try_seek(pos)
+    }
+}
+
+struct RenderInput {
+    _alive: Arc<()>,
+    tracks: Arc<TrackCount>,
+}
+
+/// A sink on the sound card or on a `RenderOutput`.
+pub struct OutputSink {
+    sink: Sink,
+    render: Option<RenderInput>,
+}
+
+impl OutputSink {
+    pub fn device(sink: Sink) -> Self {
+        Self { sink, render: None }
+    }
+
+    pub fn append<S>(&self, source: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        match self.render {
+            Some(ref render) => self.sink.append(Counted::new(source, render.tracks.clone())),
+            None => self.sink.append(source),
+        }
+    }
+}
+
+impl Deref for OutputSink {
+    type Target = Sink;
+
+    fn deref(&self) -> &Sink {
+        &self.sink
+    }
+}
+
+/// Tracks appended to render sinks, and those of them being pulled right now.
+#[derive(Default)]
+struct TrackCount {
+    queued: AtomicUsize,
+    sounding: AtomicUsize,
+}
+
+/// Keeps `TrackCount` up to date for one track, so the render can tell its audio
+/// from the silence an empty sink plays while it waits for the next one.
+struct Counted<I> {
+    input: I,
+    tracks: Arc<TrackCount>,
+    sounding: bool,
+}
+
+impl<I> Counted<I> {
+    fn new(input: I, tracks: Arc<TrackCount>) -> Self {
+        tracks.queued.fetch_add(1, Ordering::AcqRel);
+        Self {
+            input,
+            tracks,
+            sounding: false,
+        }
+    }
+}
+
+impl<I> Drop for Counted<I> {
+    fn drop(&mut self) {
+        self.tracks.queued.fetch_sub(1, Ordering::AcqRel);
+        if self.sounding {
+            self.tracks.sounding.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl<I> Iterator for Counted<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.sounding {
+            self.sounding = true;
+            self.tracks.sounding.fetch_add(1, Ordering::AcqRel);
+        }
+        self.input.next()
+    }
+}
+
+impl<I> Source for Counted<I>
+where
+    I: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::open_source;
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn wait_until_empty(sink: &OutputSink) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !sink.empty() {
+            assert!(Instant::now() < deadline, "render stalled");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn renders_the_stream_to_wav_faster_than_real_time() {
+        let path = std::env::temp_dir().join(format!("render-test-{}.wav", std::process::id()));
+        let output = RenderOutput::start(RenderSettings {
+            path: Some(path.to_string_lossy().to_string()),
+            realtime: false,
+            sample_rate: 8_000,
+            channels: 2,
+            format: RenderFormat::Float32,
+        })
+        .unwrap();
+        output.set_active(true);
+
+        let started = Instant::now();
+        let sink = output.sink();
+        // 30 s of a stereo signal, then a second track right behind it.
+        let first: Vec<f32> = (0..8_000 * 30).flat_map(|_| [0.5, -0.25]).collect();
+        sink.append(SamplesBuffer::new(2, 8_000, first));
+        sink.append(SamplesBuffer::new(2, 8_000, vec![0.125; 8_000 * 2]));
+        wait_until_empty(&sink);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let summary = output.finish();
+        assert_eq!(summary.error, None);
+        assert!((summary.seconds - 31.0).abs() < 0.05, "{}", summary.seconds);
+
+        let samples: Vec<f32> = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(&samples[..4], &[0.5, -0.25, 0.5, -0.25]);
+        assert_eq!(samples[8_000 * 2 * 30 + 2], 0.125);
+        assert_eq!(samples.len() % 2, 0);
+    }
+
+    #[test]
+    fn nothing_is_recorded_while_paused() {
+        let output = RenderOutput::start(RenderSettings::default()).unwrap();
+        let sink = output.sink();
+        sink.pause();
+        sink.append(SamplesBuffer::new(2, 48_000, vec![0.5; 48_000 * 2]));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(output.status().seconds, 0.0);
+
+        sink.play();
+        output.set_active(true);
+        wait_until_empty(&sink);
+        let summary = output.finish();
+        assert!((summary.seconds - 1.0).abs() < 0.011, "{}", summary.seconds);
+    }
+
+    #[test]
+    fn renders_to_flac() {
+        let path = std::env::temp_dir().join(format!("render-test-{}.flac", std::process::id()));
+        let output = RenderOutput::start(RenderSettings {
+            path: Some(path.to_string_lossy().to_string()),
+            sample_rate: 8_000,
+            ..RenderSettings::default()
+        })
+        .unwrap();
+        output.set_active(true);
+        let sink = output.sink();
+        sink.append(SamplesBuffer::new(2, 8_000, vec![0.5; 8_000 * 2]));
+        wait_until_empty(&sink);
+        let summary = output.finish();
+        assert_eq!(summary.error, None);
+
+        let samples: Vec<f32> = open_source(&path.to_string_lossy(), 0.0).unwrap().collect();
+        std::fs::remove_file(&path).ok();
+        // Everything the render counted, and at its level.
+        assert!((summary.seconds - 1.0).abs() < 0.011, "{}", summary.seconds);
+        assert_eq!(samples.len(), (summary.seconds * 8_000.0).round() as usize * 2);
+        assert!(samples.iter().all(|&s| (s - 0.5).abs() < 1e-4));
+    }
+
+    #[test]
+    fn refuses_formats_it_cannot_write() {
+        let settings = RenderSettings {
+            path: Some("/tmp/mix.mp3".to_string()),
+            ..RenderSettings::default()
+        };
+        assert!(RenderOutput::start(settings).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+pub const NORMALIZATION_KEY: &str = "audio.normalization";
+pub const DSP_KEY: &str = "audio.dsp";
+pub const STEREO_KEY: &str = "audio.stereo";
+pub const SPEED_KEY: &str = "audio.speed";
+pub const PLAYBACK_MODE_KEY: &str = "audio.playback_mode";
+pub const OUTPUT_DEVICE_KEY: &str = "audio.output_device";
+pub const TRANSITION_KEY: &str = "audio.transition";
+pub const SKIP_SILENCE_KEY: &str = "audio.skip_silence";
+pub const SPECTRUM_KEY: &str = "audio.spectrum";
+
+/// DSP settings of a work that overrides the user's chain.
+pub fn work_dsp_key(work_id: i64) -> String {
+    format!("{}:{}", DSP_KEY, work_id)
+}
+
+/// Playback speed remembered for a work.
+pub fn work_speed_key(work_id: i64) -> String {
+    format!("{}:{}", SPEED_KEY, work_id)
+}
+
+/// Read a JSON-encoded audio setting from `app_settings`. A value that no longer
+/// parses is treated as unset so a format change can't break startup.
+pub async fn load_setting<T: DeserializeOwned>(pool: &SqlitePool, key: &str) -> Result<Option<T>, sqlx::Error> {
+    let value: Option<String> = sqlx::query_scalar("SELECT value FROM app_settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(value.and_then(|v| match serde_json::from_str(&v) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            eprintln!("[Audio] Ignoring unreadable setting {}: {}", key, e);
+            None
+        }
+    }))
+}
+
+/// `load_setting` for restoring settings at startup, where a failed read just leaves
+/// the default in place.
+pub async fn restore_setting<T: DeserializeOwned>(pool: &SqlitePool, key: &str) -> Option<T> {
+    match load_setting(pool, key).await {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("[Audio] Failed to load setting {}: {}", key, e);
+            None
+        }
+    }
+}
+
+pub async fn save_setting<T: Serialize>(pool: &SqlitePool, key: &str, value: &T) -> Result<(), sqlx::Error> {
+    let json = serde_json::to_string(value).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    sqlx::query(
+        "INSERT INTO app_settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value"
+    )
+    .bind(key)
+    .bind(json)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_setting(pool: &SqlitePool, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM app_settings WHERE key = ?")
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+/// SplitMix64. Small and plenty for picking a play order; the same seed always
+/// gives the same order.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; `n` must not be zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Shuffle `units` (groups of entry ids that stay together) and flatten them.
+/// `first` is moved to the front, dropping whatever precedes it in its unit; `avoid`
+/// is kept off the front so a new pass doesn't repeat the entry that just played.
+fn shuffled(rng: &mut Rng, mut units: Vec<Vec<u64>>, first: Option<u64>, avoid: Option<u64>) -> Vec<u64> {
+    for i in (1..units.len()).rev() {
+        let j = rng.below(i + 1);
+        units.swap(i, j);
+    }
+    if let Some(first) = first {
+        if let Some(u) = units.iter().position(|unit| unit.contains(&first)) {
+            let mut unit = units.remove(u);
+            let at = unit.iter().position(|&id| id == first).unwrap_or(0);
+            unit.drain(..at);
+            units.insert(0, unit);
+        }
+    } else if let Some(avoid) = avoid {
+        if units.len() > 1 && units[0].contains(&avoid) {
+            units.swap(0, 1);
+        }
+    }
+    units.into_iter().flatten().collect()
+}
+
+/// One pass through the queue in shuffled order. Entries up to `position` have been
+/// played in this pass; nothing repeats until the pass is used up.
+#[derive(Debug, Clone)]
+pub struct ShuffleOrder {
+    rng: Rng,
+    order: Vec<u64>,
+    position: Option<usize>,
+}
+
+impl ShuffleOrder {
+    pub fn new(seed: u64, units: Vec<Vec<u64>>, current: Option<u64>) -> Self {
+        let mut rng = Rng::new(seed);
+        let order = shuffled(&mut rng, units, current, None);
+        let position = current.and_then(|id| order.iter().position(|&o| o == id));
+        Self { rng, order, position }
+    }
+
+    fn index_of(&self, id: u64) -> Option<usize> {
+        self.order.iter().position(|&o| o == id)
+    }
+
+    /// First entry of the pass, for starting playback with nothing current.
+    pub fn first(&self) -> Option<u64> {
+        self.order.first().copied()
+    }
+
+    pub fn next_after(&self, id: u64) -> Option<u64> {
+        self.index_of(id).and_then(|i| self.order.get(i + 1)).copied()
+    }
+
+    pub fn previous_before(&self, id: u64) -> Option<u64> {
+        self.index_of(id)
+            .and_then(|i| i.checked_sub(1))
+            .map(|i| self.order[i])
+    }
+
+    /// Where the next pass would start, without starting it.
+    pub fn peek_next_pass(&self, units: Vec<Vec<u64>>, current: u64) -> Option<u64> {
+        shuffled(&mut self.rng.clone(), units, None, Some(current)).first().copied()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.position.is_some_and(|p| p + 1 >= self.order.len())
+    }
+
+    pub fn start_next_pass(&mut self, units: Vec<Vec<u64>>, current: u64) {
+        self.order = shuffled(&mut self.rng, units, None, Some(current));
+        self.position = None;
+    }
+
+    /// `id` is now playing. Going back to an entry already played in this pass rewinds
+    /// to it; anything else is pulled forward, with `following` (the rest of its unit)
+    /// right behind it.
+    pub fn advance_to(&mut self, id: u64, following: &[u64]) {
+        if let Some(i) = self.index_of(id) {
+            if self.position.is_some_and(|p| i <= p) {
+                self.position = Some(i);
+                return;
+            }
+        }
+        let played = self.position.map_or(0, |p| p + 1);
+        let mut moved = vec![id];
+        moved.extend(
+            following
+                .iter()
+                .copied()
+                .filter(|&f| f != id && self.index_of(f).is_none_or(|i| i >= played)),
+        );
+        self.order.retain(|o| !moved.contains(o));
+        let at = played.min(self.order.len());
+        self.order.splice(at..at, moved);
+        self.position = Some(at);
+    }
+
+    /// Play `ids` right after the current entry.
+    pub fn insert_next(&mut self, ids: &[u64]) {
+        let at = self.position.map_or(0, |p| p + 1);
+        self.order.splice(at..at, ids.iter().copied());
+    }
+
+    /// Mix new `units` into the part of the pass that hasn't played yet. A unit is only
+    /// placed where `splits` says two neighbours may be separated.
+    pub fn insert_random(&mut self, units: Vec<Vec<u64>>, splits: impl Fn(u64, u64) -> bool) {
+        for unit in units {
+            let start = self.position.map_or(0, |p| p + 1);
+            let slots: Vec<usize> = (start..=self.order.len())
+                .filter(|&k| k == start || k == self.order.len() || splits(self.order[k - 1], self.order[k]))
+                .collect();
+            let at = slots[self.rng.below(slots.len())];
+            self.order.splice(at..at, unit);
+        }
+    }
+
+    /// Drop an entry. If it was the current one, the entry that followed it takes its
+    /// place and is returned.
+    pub fn remove(&mut self, id: u64) -> Option<u64> {
+        let i = self.index_of(id)?;
+        self.order.remove(i);
+        match self.position {
+            Some(p) if i < p => {
+                self.position = Some(p - 1);
+                None
+            }
+            Some(p) if i == p => {
+                let replacement = self.order.get(i).copied();
+                if replacement.is_none() {
+                    self.position = i.checked_sub(1);
+                }
+                replacement
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn singles(n: u64) -> Vec<Vec<u64>> {
+        (1..=n).map(|id| vec![id]).collect()
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let a = ShuffleOrder::new(42, singles(20), None);
+        let b = ShuffleOrder::new(42, singles(20), None);
+        let c = ShuffleOrder::new(43, singles(20), None);
+        assert_eq!(a.order, b.order);
+        assert_ne!(a.order, c.order);
+    }
+
+    #[test]
+    fn a_pass_plays_everything_once_and_the_next_avoids_a_repeat() {
+        let mut order = ShuffleOrder::new(7, singles(10), Some(3));
+        assert_eq!(order.first(), Some(3));
+        let mut played = vec![3];
+        while let Some(next) = order.next_after(*played.last().unwrap()) {
+            order.advance_to(next, &[]);
+            played.push(next);
+        }
+        assert!(order.is_exhausted());
+        let mut sorted = played.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (1..=10).collect::<Vec<_>>());
+
+        let last = *played.last().unwrap();
+        let peeked = order.peek_next_pass(singles(10), last);
+        order.start_next_pass(singles(10), last);
+        assert_eq!(order.first(), peeked);
+        assert_ne!(order.first(), Some(last));
+    }
+
+    #[test]
+    fn units_stay_together_and_in_order() {
+        let units = vec![vec![1, 2, 3], vec![4], vec![5, 6]];
+        let order = ShuffleOrder::new(1, units, Some(2));
+        // The current unit goes first, from the current entry on.
+        assert_eq!(&order.order[..2], &[2, 3]);
+        let rest: Vec<u64> = order.order[2..].to_vec();
+        assert!(rest == vec![4, 5, 6] || rest == vec![5, 6, 4], "{:?}", rest);
+    }
+
+    #[test]
+    fn jumping_back_rewinds_and_jumping_ahead_pulls_forward() {
+        let mut order = ShuffleOrder::new(5, singles(6), Some(1));
+        let second = order.next_after(1).unwrap();
+        order.advance_to(second, &[]);
+        order.advance_to(1, &[]);
+        assert_eq!(order.next_after(1), Some(second));
+
+        let last = *order.order.last().unwrap();
+        order.advance_to(last, &[]);
+        assert_eq!(order.previous_before(last), Some(1));
+        assert_eq!(order.order.len(), 6);
+    }
+}
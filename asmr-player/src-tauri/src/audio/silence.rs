@@ -0,0 +1,340 @@
+use super::clock::PlaybackClock;
+use super::decoder::{open_source, BoxedSource};
+use super::dsp::LiveSettings;
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// RMS below this counts as silence (-50 dBFS).
+const THRESHOLD: f64 = 0.003_162;
+/// Length of the windows the signal is judged in.
+const WINDOW_SECS: f64 = 0.02;
+/// How far into either end of a track to look.
+const MAX_SCAN_SECS: f64 = 60.0;
+/// Shorter stretches are left alone; they are part of the track.
+const MIN_SILENCE_SECS: f64 = 0.5;
+
+/// Silence at the start and end of a track, as stored on `tracks` by the scanner.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Silence {
+    pub leading_secs: f64,
+    pub trailing_secs: f64,
+}
+
+/// Finds the first and last windows with sound in a stream of interleaved samples.
+struct SilenceScan {
+    window_frames: usize,
+    window_samples: usize,
+    sample_rate: u32,
+    sum: f64,
+    in_window: usize,
+    windows: u64,
+    first_sound: Option<u64>,
+    // End of the last window with sound.
+    sound_end: Option<u64>,
+}
+
+impl SilenceScan {
+    fn new(sample_rate: u32, channels: u16) -> Self {
+        let window_frames = ((sample_rate as f64 * WINDOW_SECS) as usize).max(1);
+        Self {
+            window_frames,
+            window_samples: window_frames * channels.max(1) as usize,
+            sample_rate,
+            sum: 0.0,
+            in_window: 0,
+            windows: 0,
+            first_sound: None,
+            sound_end: None,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.sum += (sample as f64) * (sample as f64);
+        self.in_window += 1;
+        if self.in_window < self.window_samples {
+            return;
+        }
+        if (self.sum / self.in_window as f64).sqrt() > THRESHOLD {
+            self.first_sound.get_or_insert(self.windows);
+            self.sound_end = Some(self.windows + 1);
+        }
+        self.windows += 1;
+        self.sum = 0.0;
+        self.in_window = 0;
+    }
+
+    fn secs(&self, windows: u64) -> f64 {
+        (windows * self.window_frames as u64) as f64 / self.sample_rate.max(1) as f64
+    }
+
+    fn scanned_secs(&self) -> f64 {
+        self.secs(self.windows)
+    }
+}
+
+/// Shorter stretches than `MIN_SILENCE_SECS` count as none.
+fn worth_skipping(secs: f64) -> f64 {
+    if secs >= MIN_SILENCE_SECS {
+        secs
+    } else {
+        0.0
+    }
+}
+
+/// Measures the silence at both ends of a file that is being decoded whole anyway,
+/// such as for a loudness scan, so it isn't decoded a second time.
+pub struct SilenceMeter(SilenceScan);
+
+impl SilenceMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self(SilenceScan::new(sample_rate, channels))
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.0.push(sample);
+    }
+
+    /// What `detect_silence` would find: neither end is taken to be longer than it looks.
+    pub fn finish(&self) -> Silence {
+        let scan = &self.0;
+        let (Some(first), Some(end)) = (scan.first_sound, scan.sound_end) else {
+            // Nothing but silence; leave the track alone.
+            return Silence::default();
+        };
+        Silence {
+            leading_secs: worth_skipping(scan.secs(first).min(MAX_SCAN_SECS)),
+            trailing_secs: worth_skipping((scan.scanned_secs() - scan.secs(end)).min(MAX_SCAN_SECS)),
+        }
+    }
+}
+
+/// Measure the silence at both ends of a file. Only the first and last minute are
+/// decoded, so this is much cheaper than a loudness scan, but still blocking.
+pub fn detect_silence(path: &str) -> Result<Silence, String> {
+    let mut source = open_source(path, 0.0)?;
+    let (rate, channels) = (source.sample_rate(), source.channels());
+    let total = source.total_duration();
+
+    let mut head = SilenceScan::new(rate, channels);
+    let limit = (MAX_SCAN_SECS * rate as f64) as usize * channels as usize;
+    for sample in source.by_ref().take(limit) {
+        head.push(sample);
+        if head.first_sound.is_some() {
+            break;
+        }
+    }
+    let scanned = head.scanned_secs();
+    let leading = head.first_sound.map_or(scanned, |w| head.secs(w));
+
+    // Skip the middle of long tracks; short ones are simply read to the end.
+    let (tail_start, rest, reopened): (f64, BoxedSource, bool) =
+        match total.map(|t| t.as_secs_f64() - MAX_SCAN_SECS) {
+            Some(start) if start > scanned => (start, open_source(path, start as f32)?, true),
+            _ => (scanned, source, false),
+        };
+    let mut tail = SilenceScan::new(rate, channels);
+    for sample in rest {
+        tail.push(sample);
+    }
+    let end = tail_start + tail.scanned_secs();
+    let sound_end = match tail.sound_end {
+        Some(w) => tail_start + tail.secs(w),
+        // The sound is somewhere in the part we skipped.
+        None if reopened => tail_start,
+        None if head.first_sound.is_some() => scanned,
+        // Nothing but silence; leave the track alone.
+        None => return Ok(Silence::default()),
+    };
+
+    Ok(Silence {
+        leading_secs: worth_skipping(leading),
+        trailing_secs: worth_skipping(end - sound_end),
+    })
+}
+
+/// Silence measured for the tracks of these works, by track id.
+pub async fn load_silence(pool: &SqlitePool, work_ids: &[i64]) -> Result<HashMap<i64, Silence>, sqlx::Error> {
+    let mut silence = HashMap::new();
+    for &work_id in work_ids {
+        let rows = sqlx::query(
+            "SELECT id, leading_silence_sec, trailing_silence_sec FROM tracks WHERE work_id = ? AND leading_silence_sec IS NOT NULL"
+        )
+        .bind(work_id)
+        .fetch_all(pool)
+        .await?;
+        for row in rows {
+            silence.insert(
+                row.get(0),
+                Silence {
+                    leading_secs: row.get(1),
+                    trailing_secs: row.get::<Option<f64>, _>(2).unwrap_or(0.0),
+                },
+            );
+        }
+    }
+    Ok(silence)
+}
+
+/// Trailing silence to cut off the track playing through a `TrimSource`.
+pub type TrimControl = LiveSettings<Option<Duration>>;
+
+/// Ends a track that much before the end of the file, and reports the shortened
+/// length so a loop or crossfade after it works from the new end.
+pub struct TrimSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    control: TrimControl,
+    clock: PlaybackClock,
+    version: Option<u64>,
+    trim: Option<Duration>,
+    channels: u16,
+    sample_in_frame: u16,
+}
+
+impl<I> TrimSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, control: TrimControl, clock: PlaybackClock) -> Self {
+        let mut trimmed = Self {
+            input,
+            control,
+            clock,
+            version: None,
+            trim: None,
+            channels: 1,
+            sample_in_frame: 0,
+        };
+        trimmed.update_trim();
+        trimmed
+    }
+
+    fn update_trim(&mut self) {
+        let version = self.control.version();
+        if self.version == Some(version) {
+            return;
+        }
+        if let Some(trim) = self.control.try_read() {
+            self.trim = *trim;
+            self.version = Some(version);
+        }
+    }
+
+    fn end(&self) -> Option<Duration> {
+        Some(self.input.total_duration()?.saturating_sub(self.trim?))
+    }
+}
+
+impl<I> Iterator for TrimSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample_in_frame == 0 {
+            self.update_trim();
+            self.channels = self.input.channels().max(1);
+            if self.end().is_some_and(|end| self.clock.pulled() >= end) {
+                return None;
+            }
+        }
+        let sample = self.input.next()?;
+        self.sample_in_frame = (self.sample_in_frame + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<I> Source for TrimSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.end().or_else(|| self.input.total_duration())
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.sample_in_frame = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::clock::{ClockSource, OutputLatency};
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 8_000;
+
+    #[test]
+    fn finds_where_sound_starts_and_stops() {
+        let mut scan = SilenceScan::new(RATE, 2);
+        // 1.5 s of silence, 2 s of a quiet tone, 3 s of silence with a little noise.
+        let tone = (0..RATE * 2).map(|n| 0.05 * (n as f32 * 0.3).sin());
+        let hiss = (0..RATE * 3).map(|n| if n % 7 == 0 { 0.001 } else { 0.0 });
+        let signal = std::iter::repeat_n(0.0, RATE as usize * 3 / 2).chain(tone).chain(hiss);
+        for s in signal {
+            scan.push(s);
+            scan.push(s);
+        }
+        assert!((scan.secs(scan.first_sound.unwrap()) - 1.5).abs() < 0.021);
+        assert!((scan.secs(scan.sound_end.unwrap()) - 3.5).abs() < 0.021);
+        assert!((scan.scanned_secs() - 6.5).abs() < 0.021);
+    }
+
+    #[test]
+    fn a_whole_pass_measures_both_ends() {
+        let mut meter = SilenceMeter::new(RATE, 1);
+        // 2 s of silence, 1 s of tone, 0.2 s of silence.
+        let tone = (0..RATE).map(|n| 0.05 * (n as f32 * 0.3).sin());
+        let signal = std::iter::repeat_n(0.0, RATE as usize * 2)
+            .chain(tone)
+            .chain(std::iter::repeat_n(0.0, RATE as usize / 5));
+        for s in signal {
+            meter.push(s);
+        }
+        let silence = meter.finish();
+        assert!((silence.leading_secs - 2.0).abs() < 0.021, "{:?}", silence);
+        assert_eq!(silence.trailing_secs, 0.0);
+
+        let mut quiet = SilenceMeter::new(RATE, 1);
+        for _ in 0..RATE * 3 {
+            quiet.push(0.0);
+        }
+        assert_eq!(quiet.finish(), Silence::default());
+    }
+
+    #[test]
+    fn trim_ends_the_track_early() {
+        let clock = PlaybackClock::new(Duration::ZERO, OutputLatency::default());
+        let input = ClockSource::new(
+            SamplesBuffer::new(1, RATE, vec![0.5; RATE as usize * 3]),
+            Duration::ZERO,
+            clock.clone(),
+        );
+        let trimmed = TrimSource::new(input, LiveSettings::new(Some(Duration::from_secs(1))), clock);
+        assert_eq!(trimmed.total_duration(), Some(Duration::from_secs(2)));
+        let played = trimmed.count();
+        assert!((played as i64 - RATE as i64 * 2).abs() <= 1, "{}", played);
+    }
+}
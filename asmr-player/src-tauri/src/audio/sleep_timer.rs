@@ -0,0 +1,224 @@
+use super::queue::PlayQueue;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// How the frontend asks for a sleep timer, e.g. `{ "kind": "duration", "seconds": 1800 }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SleepTimerMode {
+    Duration { seconds: f64 },
+    EndOfTrack,
+    EndOfWork,
+}
+
+/// What the timer is waiting for. Track and work targets follow whatever is
+/// current, so skipping around the queue doesn't strand the timer.
+#[derive(Debug, Clone)]
+enum Target {
+    Deadline(Instant),
+    TrackEnd,
+    /// The last consecutive queue entry of the current work, or the point where
+    /// repeat starts the work over.
+    WorkEnd,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SleepTimerStatus {
+    pub mode: SleepTimerMode,
+    pub remaining_secs: Option<f64>,
+    pub fade_secs: f64,
+    pub fading: bool,
+}
+
+/// What the tick task does on one tick.
+#[derive(Debug, PartialEq)]
+pub enum Tick {
+    /// Keep playing at this volume.
+    Fade(f32),
+    /// The deadline has passed: stop playback.
+    Expired,
+}
+
+#[derive(Debug, Clone)]
+pub struct SleepTimer {
+    /// Distinguishes this timer from earlier ones so their tick tasks can exit.
+    pub id: u64,
+    pub mode: SleepTimerMode,
+    target: Target,
+    pub fade: Duration,
+}
+
+impl SleepTimer {
+    pub fn new(id: u64, mode: SleepTimerMode, fade_secs: f64) -> Self {
+        let target = match mode {
+            SleepTimerMode::Duration { seconds } => {
+                Target::Deadline(Instant::now() + Duration::from_secs_f64(seconds.max(0.0)))
+            }
+            SleepTimerMode::EndOfTrack => Target::TrackEnd,
+            SleepTimerMode::EndOfWork => Target::WorkEnd,
+        };
+        Self {
+            id,
+            mode,
+            target,
+            fade: Duration::from_secs_f64(fade_secs.max(0.0)),
+        }
+    }
+
+    /// True for the wall-clock timer, which fires from the tick task rather than
+    /// at a track boundary.
+    pub fn is_deadline(&self) -> bool {
+        matches!(self.target, Target::Deadline(_))
+    }
+
+    /// Whether playback must stop once the entry at `index` finishes instead of moving
+    /// on to whatever the queue plays next. Used both to hold back the gapless preload
+    /// and to fire at the boundary.
+    pub fn stops_after(&self, queue: &PlayQueue, index: usize) -> bool {
+        match self.target {
+            Target::Deadline(_) => false,
+            Target::TrackEnd => true,
+            // Ad-hoc tracks have no work, so for them "end of work" is the end of the track.
+            Target::WorkEnd => {
+                let Some(current) = queue.entry(index) else {
+                    return true;
+                };
+                let next = queue.successor_of(index);
+                current.track.work_id.is_none()
+                    || next.is_none_or(|n| {
+                        n <= index || queue.entry(n).map(|e| e.track.work_id) != Some(current.track.work_id)
+                    })
+            }
+        }
+    }
+
+    /// Seconds until the timer fires, given the position in the current entry.
+    /// Track durations come from the queue, so this is an estimate for later entries.
+    /// They are track time, which passes `speed` times as fast as the wall clock.
+    pub fn remaining(
+        &self,
+        queue: &PlayQueue,
+        position_secs: f64,
+        current_duration: Option<f64>,
+        speed: f64,
+    ) -> Option<f64> {
+        match self.target {
+            Target::Deadline(deadline) => {
+                Some(deadline.saturating_duration_since(Instant::now()).as_secs_f64())
+            }
+            Target::TrackEnd | Target::WorkEnd => {
+                let mut i = queue.current_index()?;
+                let current = queue.current()?;
+                let duration = current_duration.unwrap_or(current.track.duration);
+                let mut remaining = (duration - position_secs).max(0.0);
+
+                // Work ends fire before the index wraps, so this walks forward at most once.
+                for _ in 0..queue.len() {
+                    if self.stops_after(queue, i) {
+                        break;
+                    }
+                    match queue.successor_of(i).and_then(|n| Some((n, queue.entry(n)?))) {
+                        Some((n, entry)) => {
+                            remaining += entry.track.duration;
+                            i = n;
+                        }
+                        None => break,
+                    }
+                }
+                Some(remaining / speed)
+            }
+        }
+    }
+
+    /// Volume multiplier for the fade-out window.
+    pub fn gain(&self, remaining_secs: f64) -> f32 {
+        let fade = self.fade.as_secs_f64();
+        if fade <= 0.0 || remaining_secs >= fade {
+            1.0
+        } else {
+            (remaining_secs / fade).clamp(0.0, 1.0) as f32
+        }
+    }
+
+    /// The timer a tick task started for `id` keeps driving, unless it was cancelled
+    /// or replaced since.
+    pub fn still_running(active: Option<&SleepTimer>, id: u64) -> Option<&SleepTimer> {
+        active.filter(|t| t.id == id)
+    }
+
+    /// What to do with `remaining_secs` left. Track and work timers never expire here;
+    /// they fire when the sink runs dry at the boundary.
+    pub fn tick(&self, remaining_secs: Option<f64>) -> Tick {
+        match remaining_secs {
+            Some(r) if self.is_deadline() && r <= 0.0 => Tick::Expired,
+            Some(r) => Tick::Fade(self.gain(r)),
+            None => Tick::Fade(1.0),
+        }
+    }
+
+    pub fn status(&self, remaining_secs: Option<f64>) -> SleepTimerStatus {
+        SleepTimerStatus {
+            mode: self.mode.clone(),
+            remaining_secs,
+            fade_secs: self.fade.as_secs_f64(),
+            fading: remaining_secs.is_some_and(|r| self.gain(r) < 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::queue::tests::track;
+    use super::*;
+
+    /// Two tracks of work 1 followed by one of work 2, playing the first.
+    fn queue() -> PlayQueue {
+        let mut queue = PlayQueue::default();
+        queue.replace(vec![track(11, Some(1)), track(12, Some(1)), track(21, Some(2))], 0);
+        queue
+    }
+
+    #[test]
+    fn deadline_fades_out_then_expires() {
+        let timer = SleepTimer::new(1, SleepTimerMode::Duration { seconds: 0.0 }, 30.0);
+        assert_eq!(timer.tick(Some(45.0)), Tick::Fade(1.0));
+        assert_eq!(timer.tick(Some(15.0)), Tick::Fade(0.5));
+        assert!(timer.status(Some(15.0)).fading);
+        assert!(!timer.status(Some(45.0)).fading);
+
+        let remaining = timer.remaining(&queue(), 0.0, None, 1.0);
+        assert_eq!(remaining, Some(0.0));
+        assert_eq!(timer.tick(remaining), Tick::Expired);
+
+        let abrupt = SleepTimer::new(1, SleepTimerMode::Duration { seconds: 60.0 }, 0.0);
+        assert_eq!(abrupt.tick(Some(0.5)), Tick::Fade(1.0));
+    }
+
+    #[test]
+    fn track_and_work_timers_stop_at_the_boundary() {
+        let queue = queue();
+        let track_end = SleepTimer::new(1, SleepTimerMode::EndOfTrack, 10.0);
+        assert!(track_end.stops_after(&queue, 0));
+        assert_eq!(track_end.remaining(&queue, 50.0, None, 1.0), Some(10.0));
+        assert_eq!(track_end.tick(Some(0.0)), Tick::Fade(0.0));
+
+        let work_end = SleepTimer::new(1, SleepTimerMode::EndOfWork, 10.0);
+        assert!(!work_end.stops_after(&queue, 0));
+        assert!(work_end.stops_after(&queue, 1));
+        assert_eq!(work_end.remaining(&queue, 30.0, None, 2.0), Some(45.0));
+    }
+
+    #[test]
+    fn cancelled_and_replaced_timers_stop_ticking() {
+        let first = SleepTimer::new(1, SleepTimerMode::Duration { seconds: 10.0 }, 30.0);
+        assert!(SleepTimer::still_running(Some(&first), 1).is_some());
+        assert!(SleepTimer::still_running(None, 1).is_none());
+
+        // Extending replaces the timer; the old tick task exits and the new one starts unfaded.
+        let extended = SleepTimer::new(2, SleepTimerMode::Duration { seconds: 600.0 }, 30.0);
+        assert!(SleepTimer::still_running(Some(&extended), 1).is_none());
+        let remaining = extended.remaining(&queue(), 0.0, None, 1.0);
+        assert!(remaining.is_some_and(|r| r > 590.0));
+        assert_eq!(extended.tick(remaining), Tick::Fade(1.0));
+    }
+}
@@ -0,0 +1,327 @@
+use super::dsp::LiveSettings;
+use serde::{Deserialize, Serialize};
+use spectrum_analyzer::scaling::divide_by_N;
+use spectrum_analyzer::windows::hann_window;
+use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const MIN_BANDS: usize = 8;
+pub const MAX_BANDS: usize = 256;
+const MIN_FFT_SIZE: usize = 256;
+const MAX_FFT_SIZE: usize = 8192;
+/// Frequency range the bands are spread over, logarithmically.
+const MIN_FREQ: f32 = 20.0;
+const MAX_FREQ: f32 = 20_000.0;
+/// Level shown as an empty band; 0 dBFS is a full one.
+const FLOOR_DB: f32 = -70.0;
+
+/// What the analyzer listens to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpectrumChannels {
+    /// Left and right mixed down.
+    #[default]
+    Mid,
+    Left,
+    Right,
+    /// Left and right analyzed separately, for binaural works.
+    Split,
+}
+
+/// Stored as JSON under `audio.spectrum`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpectrumSettings {
+    pub channels: SpectrumChannels,
+    /// Number of bands between 20 Hz and 20 kHz.
+    pub bands: usize,
+    /// Samples per FFT, a power of two; more resolves low frequencies better but reacts slower.
+    pub fft_size: usize,
+    /// How much of the previous frame a falling band keeps, 0 (none) to 0.99.
+    pub smoothing: f32,
+    /// How long a peak marker stays put before it falls.
+    pub peak_hold_secs: f32,
+    /// How fast peak markers fall afterwards, in full bands per second.
+    pub peak_falloff: f32,
+    /// Upper limit on spectrum events per second.
+    pub max_fps: f32,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        Self {
+            channels: SpectrumChannels::Mid,
+            bands: 64,
+            fft_size: 2048,
+            smoothing: 0.6,
+            peak_hold_secs: 0.5,
+            peak_falloff: 1.0,
+            max_fps: 30.0,
+        }
+    }
+}
+
+impl SpectrumSettings {
+    pub fn clamped(self) -> Self {
+        let finite = |v: f32, default: f32| if v.is_finite() { v } else { default };
+        Self {
+            channels: self.channels,
+            bands: self.bands.clamp(MIN_BANDS, MAX_BANDS),
+            fft_size: self.fft_size.clamp(MIN_FFT_SIZE, MAX_FFT_SIZE).next_power_of_two(),
+            smoothing: finite(self.smoothing, 0.0).clamp(0.0, 0.99),
+            peak_hold_secs: finite(self.peak_hold_secs, 0.0).clamp(0.0, 5.0),
+            peak_falloff: finite(self.peak_falloff, 1.0).clamp(0.01, 10.0),
+            max_fps: finite(self.max_fps, 30.0).clamp(1.0, 120.0),
+        }
+    }
+}
+
+/// Band levels of one channel, 0 to 1.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelSpectrum {
+    pub bands: Vec<f32>,
+    pub peaks: Vec<f32>,
+}
+
+/// Payload of `spectrum-frame`: one channel, or left then right when split.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpectrumFrame {
+    pub channels: Vec<ChannelSpectrum>,
+}
+
+impl SpectrumFrame {
+    /// Bands of all channels averaged, for `spectrum-update`.
+    pub fn combined(&self) -> Vec<f32> {
+        let count = self.channels.len().max(1) as f32;
+        let mut bands = vec![0.0; self.channels.first().map_or(0, |c| c.bands.len())];
+        for channel in &self.channels {
+            for (sum, level) in bands.iter_mut().zip(&channel.bands) {
+                *sum += level / count;
+            }
+        }
+        bands
+    }
+}
+
+/// Smoothed bands and peak markers of one channel across frames.
+#[derive(Default)]
+struct BandState {
+    bands: Vec<f32>,
+    peaks: Vec<f32>,
+    // Hold time left on each peak.
+    held: Vec<f32>,
+}
+
+impl BandState {
+    fn update(&mut self, levels: Vec<f32>, settings: &SpectrumSettings, elapsed: f32) -> ChannelSpectrum {
+        if self.bands.len() != levels.len() {
+            self.bands = vec![0.0; levels.len()];
+            self.peaks = vec![0.0; levels.len()];
+            self.held = vec![0.0; levels.len()];
+        }
+        for (i, level) in levels.into_iter().enumerate() {
+            // Rise at once, fall smoothly.
+            let band = &mut self.bands[i];
+            *band = if level >= *band { level } else { *band * settings.smoothing + level * (1.0 - settings.smoothing) };
+
+            if *band >= self.peaks[i] {
+                self.peaks[i] = *band;
+                self.held[i] = settings.peak_hold_secs;
+            } else if self.held[i] > 0.0 {
+                self.held[i] -= elapsed;
+            } else {
+                self.peaks[i] = (self.peaks[i] - settings.peak_falloff * elapsed).max(*band);
+            }
+        }
+        ChannelSpectrum {
+            bands: self.bands.clone(),
+            peaks: self.peaks.clone(),
+        }
+    }
+}
+
+/// Turns the samples of one track, as tapped off the sink, into spectrum frames.
+pub struct SpectrumAnalyzer {
+    control: LiveSettings<SpectrumSettings>,
+    version: Option<u64>,
+    settings: SpectrumSettings,
+    sample_rate: u32,
+    channels: usize,
+    // The latest `fft_size` frames, interleaved.
+    history: VecDeque<f32>,
+    states: Vec<BandState>,
+    last_frame: Option<Instant>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(control: LiveSettings<SpectrumSettings>, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            control,
+            version: None,
+            settings: SpectrumSettings::default(),
+            sample_rate,
+            channels: channels.max(1) as usize,
+            history: VecDeque::new(),
+            states: Vec::new(),
+            last_frame: None,
+        }
+    }
+
+    /// Add interleaved samples; returns a frame when one is due.
+    pub fn push(&mut self, samples: &[f32], now: Instant) -> Option<SpectrumFrame> {
+        let version = self.control.version();
+        if self.version != Some(version) {
+            self.settings = self.control.get();
+            self.version = Some(version);
+            self.states.clear();
+        }
+
+        self.history.extend(samples);
+        let window = self.settings.fft_size * self.channels;
+        if self.history.len() > window {
+            self.history.drain(..self.history.len() - window);
+        }
+        if self.history.len() < window {
+            return None;
+        }
+
+        let interval = Duration::from_secs_f32(1.0 / self.settings.max_fps);
+        let elapsed = match self.last_frame {
+            Some(last) if now.duration_since(last) < interval => return None,
+            Some(last) => now.duration_since(last).as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_frame = Some(now);
+
+        let inputs: Vec<Vec<f32>> = match self.settings.channels {
+            SpectrumChannels::Mid => vec![self.mid()],
+            SpectrumChannels::Left => vec![self.channel(0)],
+            SpectrumChannels::Right => vec![self.channel(1)],
+            SpectrumChannels::Split => vec![self.channel(0), self.channel(1)],
+        };
+        self.states.resize_with(inputs.len(), BandState::default);
+        let channels = inputs
+            .iter()
+            .zip(self.states.iter_mut())
+            .map(|(signal, state)| {
+                let levels = band_levels(signal, self.sample_rate, self.settings.bands);
+                state.update(levels, &self.settings, elapsed)
+            })
+            .collect();
+        Some(SpectrumFrame { channels })
+    }
+
+    /// One channel of the history; mono stands in for both sides.
+    fn channel(&self, channel: usize) -> Vec<f32> {
+        let channel = channel.min(self.channels - 1);
+        self.history.iter().skip(channel).step_by(self.channels).copied().collect()
+    }
+
+    fn mid(&self) -> Vec<f32> {
+        let frames = self.history.len() / self.channels;
+        let mut mid = vec![0.0; frames];
+        for (i, sample) in self.history.iter().enumerate() {
+            mid[i / self.channels] += sample / self.channels as f32;
+        }
+        mid
+    }
+}
+
+/// Level of each log-spaced band, 0 to 1 on a dB scale. Bands too narrow to hold an
+/// FFT bin take the bin nearest their centre.
+fn band_levels(signal: &[f32], sample_rate: u32, bands: usize) -> Vec<f32> {
+    let windowed = hann_window(signal);
+    let Ok(spectrum) = samples_fft_to_spectrum(&windowed, sample_rate, FrequencyLimit::All, Some(&divide_by_N)) else {
+        return vec![0.0; bands];
+    };
+    let bins: Vec<(f32, f32)> = spectrum.data().iter().map(|(f, v)| (f.val(), v.val())).collect();
+
+    let top = MAX_FREQ.min(sample_rate as f32 / 2.0);
+    let ratio = (top / MIN_FREQ).powf(1.0 / bands as f32);
+    (0..bands)
+        .map(|band| {
+            let low = MIN_FREQ * ratio.powi(band as i32);
+            let high = low * ratio;
+            let in_band = bins
+                .iter()
+                .filter(|(f, _)| *f >= low && *f < high)
+                .map(|&(_, v)| v)
+                .reduce(f32::max);
+            let magnitude = in_band.unwrap_or_else(|| {
+                let centre = (low * high).sqrt();
+                bins.iter()
+                    .min_by(|a, b| (a.0 - centre).abs().total_cmp(&(b.0 - centre).abs()))
+                    .map_or(0.0, |&(_, v)| v)
+            });
+            // Undo the one-sided spectrum's halving and the Hann window's coherent gain,
+            // so a full-scale sine reads 0 dB.
+            let db = 20.0 * (magnitude * 4.0).max(1e-10).log10();
+            ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn sine(freq: f32, frames: usize) -> impl Iterator<Item = f32> {
+        (0..frames).map(move |n| (std::f32::consts::TAU * freq * n as f32 / RATE as f32).sin())
+    }
+
+    fn loudest(bands: &[f32]) -> usize {
+        (0..bands.len()).max_by(|&a, &b| bands[a].total_cmp(&bands[b])).unwrap()
+    }
+
+    #[test]
+    fn a_sine_lands_in_its_log_band() {
+        // 30 bands over three decades, 10 per decade from 20 Hz: band 17 spans 1.0-1.26 kHz.
+        let signal: Vec<f32> = sine(1_125.0, 4096).collect();
+        let levels = band_levels(&signal, RATE, 30);
+        assert_eq!(loudest(&levels), 17, "{:?}", levels);
+        assert!(levels[loudest(&levels)] > 0.95);
+        assert!(levels[5] < 0.3, "{}", levels[5]);
+    }
+
+    #[test]
+    fn split_analyzes_each_side() {
+        let settings = SpectrumSettings {
+            channels: SpectrumChannels::Split,
+            bands: 30,
+            fft_size: 4096,
+            ..SpectrumSettings::default()
+        };
+        let mut analyzer = SpectrumAnalyzer::new(LiveSettings::new(settings), RATE, 2);
+        let samples: Vec<f32> = sine(100.0, 4096)
+            .zip(sine(5_000.0, 4096))
+            .flat_map(|(l, r)| [l, r])
+            .collect();
+        let now = Instant::now();
+        let frame = analyzer.push(&samples, now).unwrap();
+        assert_eq!(frame.channels.len(), 2);
+        assert!(loudest(&frame.channels[0].bands) < loudest(&frame.channels[1].bands));
+        // Capped frame rate: nothing new this soon.
+        assert!(analyzer.push(&samples[..64], now + Duration::from_millis(5)).is_none());
+    }
+
+    #[test]
+    fn bands_fall_smoothly_and_peaks_hold() {
+        let settings = SpectrumSettings {
+            smoothing: 0.5,
+            peak_hold_secs: 0.5,
+            peak_falloff: 1.0,
+            ..SpectrumSettings::default()
+        };
+        let mut state = BandState::default();
+        state.update(vec![1.0], &settings, 0.0);
+        let after = state.update(vec![0.0], &settings, 0.1);
+        assert_eq!(after.bands, vec![0.5]);
+        assert_eq!(after.peaks, vec![1.0]);
+        state.update(vec![0.0], &settings, 0.5);
+        let falling = state.update(vec![0.0], &settings, 0.25);
+        assert!((falling.peaks[0] - 0.75).abs() < 1e-6, "{:?}", falling.peaks);
+    }
+}
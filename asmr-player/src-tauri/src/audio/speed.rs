@@ -0,0 +1,498 @@
+use super::clock::MediaRate;
+use super::dsp::LiveSettings;
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::time::Duration;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+
+/// Frames produced per refill when resampling or passing through.
+const CHUNK_FRAMES: usize = 256;
+/// Stretch window; long enough to hold a couple of pitch periods of a low voice.
+const WINDOW_SECS: f64 = 0.04;
+/// How far a stretch segment may move from its nominal position to line up with the last one.
+const SEARCH_SECS: f64 = 0.012;
+/// Step of the coarse similarity search, in frames.
+const COARSE_STEP: usize = 4;
+
+/// Stored as JSON under `audio.speed:{work_id}` in `app_settings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedSettings {
+    pub speed: f32,
+    /// Time-stretch instead of resampling, so voices keep their pitch.
+    pub preserve_pitch: bool,
+}
+
+impl Default for SpeedSettings {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            preserve_pitch: true,
+        }
+    }
+}
+
+pub type SpeedControl = LiveSettings<SpeedSettings>;
+
+/// Overlap-add state of the pitch-preserving stretch (WSOLA).
+struct Stretch {
+    /// Nominal start of the next segment, in buffered frames.
+    analysis: f64,
+    /// Where the previous segment would have carried on, i.e. the audio the next one
+    /// should line up with. None until the first segment has been played.
+    continuation: Option<usize>,
+    /// Windowed second half of the previous segment, still to be overlapped.
+    overlap: Vec<f32>,
+}
+
+enum Mode {
+    /// Buffered frames from `cursor` on, then the input as is.
+    Direct { cursor: usize },
+    /// Linear interpolation at a fractional read position; pitch follows speed.
+    Resample { position: f64 },
+    Stretch(Stretch),
+}
+
+impl Mode {
+    fn for_settings(settings: &SpeedSettings, start: usize) -> Self {
+        if settings.speed == 1.0 {
+            Mode::Direct { cursor: start }
+        } else if settings.preserve_pitch {
+            Mode::Stretch(Stretch {
+                analysis: start as f64,
+                continuation: None,
+                overlap: Vec::new(),
+            })
+        } else {
+            Mode::Resample {
+                position: start as f64,
+            }
+        }
+    }
+
+    fn same_kind(&self, other: &Mode) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// First buffered frame that hasn't been played yet, for handing over to another mode.
+    fn resume_at(&self) -> usize {
+        match self {
+            Mode::Direct { cursor } => *cursor,
+            Mode::Resample { position } => position.ceil() as usize,
+            Mode::Stretch(stretch) => stretch
+                .continuation
+                .unwrap_or(stretch.analysis.round() as usize),
+        }
+    }
+}
+
+/// Changes playback speed between `MIN_SPEED` and `MAX_SPEED`. Sits directly on the
+/// decoder and reports the media time per output frame through `MediaRate`, so the
+/// `ClockSource` after it keeps counting track time.
+pub struct SpeedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    control: SpeedControl,
+    rate: MediaRate,
+    version: Option<u64>,
+    settings: SpeedSettings,
+    mode: Mode,
+    channels: u16,
+    sample_rate: u32,
+    window: Vec<f32>,
+    search: usize,
+    /// Interleaved input frames the current mode may still read.
+    buffer: Vec<f32>,
+    ended: bool,
+    out: Vec<f32>,
+    out_pos: usize,
+    scratch: Vec<f32>,
+}
+
+impl<I> SpeedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, control: SpeedControl, rate: MediaRate) -> Self {
+        let mut source = Self {
+            input,
+            control,
+            rate,
+            version: None,
+            settings: SpeedSettings::default(),
+            mode: Mode::Direct { cursor: 0 },
+            channels: 1,
+            sample_rate: 0,
+            window: Vec::new(),
+            search: 0,
+            buffer: Vec::new(),
+            ended: false,
+            out: Vec::new(),
+            out_pos: 0,
+            scratch: Vec::new(),
+        };
+        source.read_format();
+        source
+    }
+
+    fn read_format(&mut self) {
+        self.channels = self.input.channels().max(1);
+        let sample_rate = self.input.sample_rate();
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            let hop = ((sample_rate as f64 * WINDOW_SECS / 2.0) as usize).max(COARSE_STEP);
+            // Periodic Hann: halves one hop apart sum to one.
+            self.window = (0..hop * 2)
+                .map(|k| 0.5 - 0.5 * (PI * k as f32 / hop as f32).cos())
+                .collect();
+            self.search = (sample_rate as f64 * SEARCH_SECS) as usize;
+        }
+    }
+
+    fn hop(&self) -> usize {
+        self.window.len() / 2
+    }
+
+    fn frames(&self) -> usize {
+        self.buffer.len() / self.channels as usize
+    }
+
+    /// Pull input until `frames` frames are buffered or the input runs out.
+    fn fill(&mut self, frames: usize) {
+        let channels = self.channels as usize;
+        while !self.ended && self.frames() < frames {
+            for _ in 0..channels {
+                match self.input.next() {
+                    Some(sample) => self.buffer.push(sample),
+                    None => {
+                        // A partial frame at the very end is dropped.
+                        let whole = self.frames() * channels;
+                        self.buffer.truncate(whole);
+                        self.ended = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget the first `frames` buffered frames.
+    fn discard(&mut self, frames: usize) {
+        let frames = frames.min(self.frames());
+        if frames == 0 {
+            return;
+        }
+        self.buffer.drain(..frames * self.channels as usize);
+        match self.mode {
+            Mode::Direct { ref mut cursor } => *cursor -= frames,
+            Mode::Resample { ref mut position } => *position -= frames as f64,
+            Mode::Stretch(ref mut stretch) => {
+                stretch.analysis -= frames as f64;
+                stretch.continuation = stretch.continuation.map(|c| c - frames);
+            }
+        }
+    }
+
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        self.buffer
+            .get(frame * self.channels as usize + channel)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Follow the control; a change of mode picks up where the old one left off.
+    fn update_settings(&mut self) {
+        let version = self.control.version();
+        if self.version == Some(version) {
+            return;
+        }
+        let Some(settings) = self.control.try_read() else {
+            return;
+        };
+        self.settings = settings.clone();
+        drop(settings);
+        self.settings.speed = self.settings.speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.version = Some(version);
+
+        let wanted = Mode::for_settings(&self.settings, 0);
+        if !self.mode.same_kind(&wanted) {
+            let start = self.mode.resume_at();
+            self.mode = Mode::for_settings(&self.settings, start);
+        }
+    }
+
+    /// Produce the next block of output. False once the input is exhausted.
+    fn refill(&mut self) -> bool {
+        self.out.clear();
+        self.out_pos = 0;
+        if self.buffer.is_empty() && matches!(self.mode, Mode::Direct { .. }) {
+            // Nothing buffered, so the format may safely follow the input.
+            self.read_format();
+        }
+        self.update_settings();
+
+        match self.mode {
+            Mode::Direct { .. } => self.refill_direct(),
+            Mode::Resample { .. } => self.refill_resampled(),
+            Mode::Stretch(_) => self.refill_stretched(),
+        }
+        !self.out.is_empty()
+    }
+
+    fn refill_direct(&mut self) {
+        let Mode::Direct { cursor } = self.mode else {
+            return;
+        };
+        self.rate.set(1.0);
+        self.fill(cursor + CHUNK_FRAMES);
+        let channels = self.channels as usize;
+        let end = self.frames().min(cursor + CHUNK_FRAMES);
+        if end > cursor {
+            self.out.extend_from_slice(&self.buffer[cursor * channels..end * channels]);
+        }
+        self.mode = Mode::Direct { cursor: end };
+        self.discard(end);
+    }
+
+    fn refill_resampled(&mut self) {
+        let Mode::Resample { mut position } = self.mode else {
+            return;
+        };
+        let speed = self.settings.speed as f64;
+        self.rate.set(self.settings.speed);
+        let channels = self.channels as usize;
+
+        for _ in 0..CHUNK_FRAMES {
+            let index = position.floor() as usize;
+            self.fill(index + 2);
+            if index >= self.frames() {
+                break;
+            }
+            let frac = (position - index as f64) as f32;
+            let next = if index + 1 < self.frames() { index + 1 } else { index };
+            for channel in 0..channels {
+                let a = self.sample(index, channel);
+                let b = self.sample(next, channel);
+                self.out.push(a + (b - a) * frac);
+            }
+            position += speed;
+        }
+        self.mode = Mode::Resample { position };
+        self.discard(position.floor() as usize);
+    }
+
+    fn refill_stretched(&mut self) {
+        let Mode::Stretch(ref mut stretch) = self.mode else {
+            return;
+        };
+        let mut stretch = std::mem::replace(
+            stretch,
+            Stretch {
+                analysis: 0.0,
+                continuation: None,
+                overlap: Vec::new(),
+            },
+        );
+        self.rate.set(self.settings.speed);
+        let hop = self.hop();
+        let channels = self.channels as usize;
+        let nominal = stretch.analysis.round().max(0.0) as usize;
+
+        self.fill(nominal + self.search + hop * 2);
+        if nominal >= self.frames() && self.ended {
+            // Play out the tail of the last segment and stop.
+            self.out.append(&mut stretch.overlap);
+        } else {
+            let start = match stretch.continuation {
+                Some(target) => self.best_match(nominal, target),
+                None => nominal,
+            };
+            let first = stretch.continuation.is_none();
+            for k in 0..hop {
+                // The very first segment starts at full level instead of fading in.
+                let w = if first { 1.0 } else { self.window[k] };
+                for channel in 0..channels {
+                    let overlap = stretch.overlap.get(k * channels + channel).copied().unwrap_or(0.0);
+                    self.out.push(overlap + w * self.sample(start + k, channel));
+                }
+            }
+            stretch.overlap.clear();
+            for k in hop..hop * 2 {
+                for channel in 0..channels {
+                    stretch.overlap.push(self.window[k] * self.sample(start + k, channel));
+                }
+            }
+            stretch.continuation = Some(start + hop);
+            stretch.analysis += hop as f64 * self.settings.speed as f64;
+        }
+
+        let keep_from = (stretch.analysis as usize)
+            .saturating_sub(self.search)
+            .min(stretch.continuation.unwrap_or(usize::MAX));
+        self.mode = Mode::Stretch(stretch);
+        self.discard(keep_from);
+    }
+
+    /// Start of the segment near `nominal` that best continues the audio at `target`.
+    fn best_match(&mut self, nominal: usize, target: usize) -> usize {
+        let hop = self.hop();
+        let low = nominal.saturating_sub(self.search);
+        let high = nominal + self.search;
+        let from = low.min(target);
+        let to = high.max(target) + hop;
+
+        // Channels summed, over everything the search can touch.
+        let mut mono = std::mem::take(&mut self.scratch);
+        mono.clear();
+        mono.extend((from..to).map(|f| (0..self.channels as usize).map(|c| self.sample(f, c)).sum::<f32>()));
+
+        let score = |start: usize, step: usize| -> f32 {
+            let (mut dot, mut energy) = (0.0f32, 1e-9f32);
+            for k in (0..hop).step_by(step) {
+                let x = mono[start - from + k];
+                dot += x * mono[target - from + k];
+                energy += x * x;
+            }
+            dot / energy.sqrt()
+        };
+        let best_in = |range: std::ops::RangeInclusive<usize>, step: usize| {
+            range
+                .step_by(step)
+                .map(|start| (start, score(start, step)))
+                .fold((nominal, f32::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+                .0
+        };
+
+        let coarse = best_in(low..=high, COARSE_STEP);
+        let fine = best_in(coarse.saturating_sub(COARSE_STEP - 1).max(low)..=(coarse + COARSE_STEP - 1).min(high), 1);
+        self.scratch = mono;
+        fine
+    }
+}
+
+impl<I> Iterator for SpeedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.out_pos >= self.out.len() && !self.refill() {
+            return None;
+        }
+        let sample = self.out[self.out_pos];
+        self.out_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<I> Source for SpeedSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Resampled or stretched output has no span boundaries to report; the format is
+        // only re-read while passing through with nothing buffered.
+        match self.mode {
+            Mode::Direct { .. } if self.buffer.is_empty() => {
+                let pending = self.out.len() - self.out_pos;
+                self.input.current_frame_len().map(|n| n + pending)
+            }
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.buffer.clear();
+        self.out.clear();
+        self.out_pos = 0;
+        self.ended = false;
+        self.mode = Mode::for_settings(&self.settings, 0);
+        self.read_format();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 48_000;
+
+    fn sine(seconds: f32) -> Vec<f32> {
+        (0..(RATE as f32 * seconds) as usize)
+            .map(|n| (2.0 * PI * 440.0 * n as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    fn speed_source(speed: f32, preserve_pitch: bool) -> (SpeedSource<SamplesBuffer<f32>>, MediaRate) {
+        let rate = MediaRate::default();
+        let control = LiveSettings::new(SpeedSettings { speed, preserve_pitch });
+        let source = SpeedSource::new(SamplesBuffer::new(1, RATE, sine(2.0)), control, rate.clone());
+        (source, rate)
+    }
+
+    /// Dominant frequency from zero crossings.
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings = samples.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0).count();
+        crossings as f32 * RATE as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn normal_speed_passes_samples_through() {
+        let (source, rate) = speed_source(1.0, true);
+        let played: Vec<f32> = source.collect();
+        assert_eq!(played, sine(2.0));
+        assert_eq!(rate.get(), 1.0);
+    }
+
+    #[test]
+    fn resampling_shortens_and_raises_pitch() {
+        let (source, rate) = speed_source(2.0, false);
+        let played: Vec<f32> = source.collect();
+        assert!((played.len() as i64 - RATE as i64).abs() < 8, "{}", played.len());
+        assert!((frequency(&played) - 880.0).abs() < 10.0, "{}", frequency(&played));
+        assert_eq!(rate.get(), 2.0);
+    }
+
+    #[test]
+    fn stretching_keeps_pitch() {
+        for speed in [0.5, 1.5] {
+            let (source, rate) = speed_source(speed, true);
+            let played: Vec<f32> = source.collect();
+            let expected = 2.0 * RATE as f32 / speed;
+            assert!((played.len() as f32 - expected).abs() < RATE as f32 * 0.05, "{} {}", speed, played.len());
+            assert!((frequency(&played) - 440.0).abs() < 10.0, "{} {}", speed, frequency(&played));
+            assert_eq!(rate.get(), speed);
+        }
+    }
+
+    #[test]
+    fn seeking_restarts_at_the_target() {
+        let (mut source, _) = speed_source(1.5, true);
+        source.by_ref().take(RATE as usize).count();
+        source.try_seek(Duration::from_secs(1)).unwrap();
+        let rest = source.count() as f32;
+        assert!((rest - RATE as f32 / 1.5).abs() < RATE as f32 * 0.05, "{}", rest);
+    }
+}
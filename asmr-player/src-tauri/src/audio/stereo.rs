@@ -0,0 +1,238 @@
+use super::dsp::{FrameProcessor, LiveSettings};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// Bauer stereo-to-binaural crossfeed, as in bs2b: the opposite channel is low-passed
+/// and mixed in, and the direct signal gets a matching high shelf.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossfeedSettings {
+    pub enabled: bool,
+    /// Low-pass cutoff of the crossfed signal (bs2b default: 700 Hz).
+    pub cutoff_hz: f64,
+    /// How much quieter the crossfed signal is at low frequencies (bs2b default: 4.5 dB).
+    pub feed_db: f64,
+}
+
+/// Stored as JSON under `audio.stereo` in `app_settings`. Only applies to stereo sources.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StereoSettings {
+    /// -1.0 is left only, 1.0 right only.
+    pub balance: f32,
+    pub swap_channels: bool,
+    pub mono: bool,
+    pub crossfeed: CrossfeedSettings,
+}
+
+impl Default for StereoSettings {
+    fn default() -> Self {
+        Self {
+            balance: 0.0,
+            swap_channels: false,
+            mono: false,
+            crossfeed: CrossfeedSettings {
+                enabled: false,
+                cutoff_hz: 700.0,
+                feed_db: 4.5,
+            },
+        }
+    }
+}
+
+impl StereoSettings {
+    fn is_neutral(&self) -> bool {
+        self.balance == 0.0 && !self.swap_channels && !self.mono && !self.crossfeed.enabled
+    }
+}
+
+/// First-order filters of the bs2b crossfeed, for one sample rate.
+struct Crossfeed {
+    a0_lo: f64,
+    b1_lo: f64,
+    a0_hi: f64,
+    a1_hi: f64,
+    b1_hi: f64,
+    gain: f64,
+    lo: [f64; 2],
+    hi: [f64; 2],
+    last: [f64; 2],
+}
+
+impl Crossfeed {
+    fn new(settings: &CrossfeedSettings, sample_rate: u32) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let fc_lo = settings.cutoff_hz.clamp(300.0, 2000.0);
+        let level = settings.feed_db.clamp(1.0, 15.0);
+
+        let gb_lo = level * -5.0 / 6.0 - 3.0;
+        let gb_hi = level / 6.0 - 3.0;
+        let g_lo = 10f64.powf(gb_lo / 20.0);
+        let g_hi = 1.0 - 10f64.powf(gb_hi / 20.0);
+        let fc_hi = fc_lo * 2f64.powf((gb_lo - 20.0 * g_hi.log10()) / 12.0);
+
+        let x_lo = (-2.0 * PI * fc_lo / rate).exp();
+        let x_hi = (-2.0 * PI * fc_hi / rate).exp();
+        Self {
+            a0_lo: g_lo * (1.0 - x_lo),
+            b1_lo: x_lo,
+            a0_hi: 1.0 - g_hi * (1.0 - x_hi),
+            a1_hi: -x_hi,
+            b1_hi: x_hi,
+            gain: 1.0 / (1.0 - g_hi + g_lo),
+            lo: [0.0; 2],
+            hi: [0.0; 2],
+            last: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let input = [left as f64, right as f64];
+        for (ch, &x) in input.iter().enumerate() {
+            self.lo[ch] = self.a0_lo * x + self.b1_lo * self.lo[ch];
+            self.hi[ch] = self.a0_hi * x + self.a1_hi * self.last[ch] + self.b1_hi * self.hi[ch];
+        }
+        self.last = input;
+        (
+            ((self.hi[0] + self.lo[1]) * self.gain) as f32,
+            ((self.hi[1] + self.lo[0]) * self.gain) as f32,
+        )
+    }
+
+    fn reset(&mut self) {
+        self.lo = [0.0; 2];
+        self.hi = [0.0; 2];
+        self.last = [0.0; 2];
+    }
+}
+
+/// Balance, channel swap, mono downmix and crossfeed for two-channel sources.
+pub struct StereoStage {
+    control: LiveSettings<StereoSettings>,
+    version: Option<u64>,
+    sample_rate: u32,
+    settings: StereoSettings,
+    crossfeed: Option<Crossfeed>,
+}
+
+impl StereoStage {
+    pub fn new(control: LiveSettings<StereoSettings>) -> Self {
+        Self {
+            control,
+            version: None,
+            sample_rate: 0,
+            settings: StereoSettings::default(),
+            crossfeed: None,
+        }
+    }
+}
+
+impl FrameProcessor for StereoStage {
+    fn prepare(&mut self, sample_rate: u32, _channels: u16) {
+        let version = self.control.version();
+        if self.version == Some(version) && self.sample_rate == sample_rate {
+            return;
+        }
+        let Some(settings) = self.control.try_read() else {
+            return;
+        };
+        let previous = std::mem::replace(&mut self.settings, settings.clone());
+        drop(settings);
+
+        // Keep the filter state across edits that don't touch the crossfeed itself.
+        let rebuild = self.sample_rate != sample_rate
+            || previous.crossfeed.cutoff_hz != self.settings.crossfeed.cutoff_hz
+            || previous.crossfeed.feed_db != self.settings.crossfeed.feed_db;
+        self.crossfeed = match (self.settings.crossfeed.enabled, self.crossfeed.take()) {
+            (false, _) => None,
+            (true, Some(existing)) if !rebuild => Some(existing),
+            (true, _) => Some(Crossfeed::new(&self.settings.crossfeed, sample_rate)),
+        };
+        self.sample_rate = sample_rate;
+        self.version = Some(version);
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let [left, right] = frame else {
+            return;
+        };
+        let s = &self.settings;
+        if s.is_neutral() {
+            return;
+        }
+
+        let (mut l, mut r) = if s.swap_channels { (*right, *left) } else { (*left, *right) };
+        if s.mono {
+            let mid = (l + r) * 0.5;
+            (l, r) = (mid, mid);
+        } else if let Some(ref mut crossfeed) = self.crossfeed {
+            (l, r) = crossfeed.process(l, r);
+        }
+
+        let balance = s.balance.clamp(-1.0, 1.0);
+        *left = l * (1.0 - balance).min(1.0);
+        *right = r * (1.0 + balance).min(1.0);
+    }
+
+    fn reset(&mut self) {
+        if let Some(ref mut crossfeed) = self.crossfeed {
+            crossfeed.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(settings: StereoSettings) -> StereoStage {
+        let mut stage = StereoStage::new(LiveSettings::new(settings));
+        stage.prepare(48_000, 2);
+        stage
+    }
+
+    fn run(stage: &mut StereoStage, left: f32, right: f32) -> [f32; 2] {
+        let mut frame = [left, right];
+        stage.process(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn swap_mono_and_balance() {
+        let mut swap = stage(StereoSettings {
+            swap_channels: true,
+            ..Default::default()
+        });
+        assert_eq!(run(&mut swap, 1.0, 0.0), [0.0, 1.0]);
+
+        let mut mono = stage(StereoSettings {
+            mono: true,
+            ..Default::default()
+        });
+        assert_eq!(run(&mut mono, 1.0, 0.0), [0.5, 0.5]);
+
+        let mut right_only = stage(StereoSettings {
+            balance: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(run(&mut right_only, 0.8, 0.8), [0.0, 0.8]);
+    }
+
+    #[test]
+    fn crossfeed_leaks_low_frequencies_and_keeps_dc_level() {
+        let mut crossfeed = stage(StereoSettings {
+            crossfeed: CrossfeedSettings {
+                enabled: true,
+                cutoff_hz: 700.0,
+                feed_db: 4.5,
+            },
+            ..Default::default()
+        });
+        // A left-only DC signal settles with both channels summing back to the input.
+        let mut out = [0.0; 2];
+        for _ in 0..48_000 {
+            out = run(&mut crossfeed, 1.0, 0.0);
+        }
+        assert!(out[1] > 0.3 && out[1] < out[0], "{:?}", out);
+        assert!((out[0] + out[1] - 1.0).abs() < 0.01, "{:?}", out);
+    }
+}
@@ -0,0 +1,236 @@
+use super::clock::PlaybackClock;
+use super::dsp::LiveSettings;
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Longest crossfade or gap the settings accept, in seconds.
+pub const MAX_TRANSITION_SECS: f32 = 30.0;
+
+/// What happens where one work ends and another begins. Tracks of the same work
+/// always follow each other gaplessly. Stored as JSON under `audio.transition`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitionSettings {
+    /// Overlap the end of one work with the start of the next; 0 is off.
+    /// Takes precedence over `gap_secs`.
+    pub crossfade_secs: f32,
+    /// Silence between works; 0 is off.
+    pub gap_secs: f32,
+}
+
+/// How one queue entry hands over to the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handover {
+    Gapless,
+    Crossfade(Duration),
+    Gap(Duration),
+}
+
+impl TransitionSettings {
+    pub fn clamped(self) -> Self {
+        let clamp = |secs: f32| if secs.is_finite() { secs.clamp(0.0, MAX_TRANSITION_SECS) } else { 0.0 };
+        Self {
+            crossfade_secs: clamp(self.crossfade_secs),
+            gap_secs: clamp(self.gap_secs),
+        }
+    }
+
+    pub fn handover(&self, same_work: bool) -> Handover {
+        if same_work {
+            Handover::Gapless
+        } else if self.crossfade_secs > 0.0 {
+            Handover::Crossfade(Duration::from_secs_f32(self.crossfade_secs))
+        } else if self.gap_secs > 0.0 {
+            Handover::Gap(Duration::from_secs_f32(self.gap_secs))
+        } else {
+            Handover::Gapless
+        }
+    }
+}
+
+/// Volume ramps of one source.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fades {
+    /// Ramp up over this long from where the source starts playing.
+    pub fade_in: Option<Duration>,
+    /// Ramp down over this long before the end of the track. Only set once the
+    /// next entry is known to crossfade in.
+    pub fade_out: Option<Duration>,
+}
+
+pub type FadeControl = LiveSettings<Fades>;
+
+/// Applies `Fades` and reports when the fade-out starts, which is the cue to start
+/// the next track. Reads the track position from the clock, like `LoopSource`.
+pub struct FadeSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    input: I,
+    control: FadeControl,
+    clock: PlaybackClock,
+    version: Option<u64>,
+    fades: Fades,
+    on_fade_out: Option<Box<dyn FnOnce() + Send>>,
+    channels: u16,
+    sample_in_frame: u16,
+    gain: f32,
+    played_frames: u64,
+}
+
+impl<I> FadeSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    pub fn new(input: I, control: FadeControl, clock: PlaybackClock, on_fade_out: Box<dyn FnOnce() + Send>) -> Self {
+        Self {
+            input,
+            control,
+            clock,
+            version: None,
+            fades: Fades::default(),
+            on_fade_out: Some(on_fade_out),
+            channels: 1,
+            sample_in_frame: 0,
+            gain: 1.0,
+            played_frames: 0,
+        }
+    }
+
+    fn update_fades(&mut self) {
+        let version = self.control.version();
+        if self.version == Some(version) {
+            return;
+        }
+        if let Some(fades) = self.control.try_read() {
+            self.fades = *fades;
+            self.version = Some(version);
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.update_fades();
+        self.channels = self.input.channels().max(1);
+        self.gain = 1.0;
+
+        if let Some(fade_in) = self.fades.fade_in {
+            let elapsed = self.played_frames as f32 / self.input.sample_rate().max(1) as f32;
+            self.gain *= (elapsed / fade_in.as_secs_f32()).min(1.0);
+        }
+        if let (Some(fade_out), Some(total)) = (self.fades.fade_out, self.input.total_duration()) {
+            // Track time left, in wall-clock seconds.
+            let left = total.saturating_sub(self.clock.pulled()).as_secs_f32() / self.clock.speed().max(0.01);
+            if left <= fade_out.as_secs_f32() {
+                if let Some(notify) = self.on_fade_out.take() {
+                    notify();
+                }
+                self.gain *= left / fade_out.as_secs_f32();
+            }
+        }
+        self.played_frames += 1;
+    }
+}
+
+impl<I> Iterator for FadeSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample_in_frame == 0 {
+            self.start_frame();
+        }
+        let sample = self.input.next()?;
+        self.sample_in_frame = (self.sample_in_frame + 1) % self.channels;
+        Some(sample * self.gain)
+    }
+}
+
+impl<I> Source for FadeSource<I>
+where
+    I: Source<Item = f32> + Send,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.sample_in_frame = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::clock::{ClockSource, OutputLatency};
+    use rodio::buffer::SamplesBuffer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const RATE: u32 = 8_000;
+
+    fn faded(fades: Fades, cue: Arc<AtomicUsize>) -> FadeSource<ClockSource<SamplesBuffer<f32>>> {
+        let clock = PlaybackClock::new(Duration::ZERO, OutputLatency::default());
+        let input = ClockSource::new(
+            SamplesBuffer::new(1, RATE, vec![1.0; RATE as usize * 4]),
+            Duration::ZERO,
+            clock.clone(),
+        );
+        let counter = cue.clone();
+        FadeSource::new(
+            input,
+            LiveSettings::new(fades),
+            clock,
+            Box::new(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }),
+        )
+    }
+
+    #[test]
+    fn fades_in_from_silence() {
+        let fades = Fades {
+            fade_in: Some(Duration::from_secs(1)),
+            fade_out: None,
+        };
+        let out: Vec<f32> = faded(fades, Arc::default()).collect();
+        assert_eq!(out[0], 0.0);
+        assert!((out[RATE as usize / 2] - 0.5).abs() < 0.01);
+        assert_eq!(out[RATE as usize * 2], 1.0);
+    }
+
+    #[test]
+    fn fade_out_ends_the_track_silent_and_cues_once() {
+        let cue = Arc::new(AtomicUsize::new(0));
+        let fades = Fades {
+            fade_in: None,
+            fade_out: Some(Duration::from_secs(1)),
+        };
+        let mut source = faded(fades, cue.clone());
+        let before: Vec<f32> = source.by_ref().take(RATE as usize * 3 - 8).collect();
+        assert!(before.iter().all(|&s| s == 1.0));
+        assert_eq!(cue.load(Ordering::Relaxed), 0);
+
+        let tail: Vec<f32> = source.collect();
+        assert_eq!(cue.load(Ordering::Relaxed), 1);
+        assert!((tail[RATE as usize / 2] - 0.5).abs() < 0.01, "{}", tail[RATE as usize / 2]);
+        assert!(*tail.last().unwrap() < 0.01);
+    }
+}
@@ -0,0 +1,198 @@
+use super::decoder::open_source;
+use crate::archive;
+use rodio::Source;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Resolution the summary is computed and cached at; requests are downsampled from it.
+pub const STORED_BUCKETS: usize = 4096;
+/// Decoded audio is first reduced to blocks this long, whatever the track's length.
+const BLOCK_SECS: f64 = 0.01;
+
+/// Peak and RMS level of each slice of a track, linear 0 to 1, for drawing a scrubber.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Waveform {
+    pub peaks: Vec<f32>,
+    pub rms: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+    peak: f32,
+    sum_squares: f64,
+    samples: u64,
+}
+
+impl Block {
+    fn add(&mut self, other: &Block) {
+        self.peak = self.peak.max(other.peak);
+        self.sum_squares += other.sum_squares;
+        self.samples += other.samples;
+    }
+
+    fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.sum_squares / self.samples as f64).sqrt() as f32
+    }
+}
+
+impl Waveform {
+    /// Merge `blocks` into `buckets` slices of (nearly) equal length.
+    fn from_blocks(blocks: &[Block], buckets: usize) -> Self {
+        let buckets = buckets.min(blocks.len());
+        let mut merged = vec![Block::default(); buckets];
+        for (i, block) in blocks.iter().enumerate() {
+            merged[i * buckets / blocks.len()].add(block);
+        }
+        Self {
+            peaks: merged.iter().map(|b| b.peak).collect(),
+            rms: merged.iter().map(Block::rms).collect(),
+        }
+    }
+
+    /// The same summary at a lower resolution. Never adds detail it doesn't have.
+    pub fn resample(&self, buckets: usize) -> Self {
+        // Cached buckets cover equal lengths, so they weigh the same.
+        let blocks: Vec<Block> = self
+            .peaks
+            .iter()
+            .zip(&self.rms)
+            .map(|(&peak, &rms)| Block {
+                peak,
+                sum_squares: (rms as f64) * (rms as f64),
+                samples: 1,
+            })
+            .collect();
+        Self::from_blocks(&blocks, buckets.max(1))
+    }
+
+    /// Peak and RMS of each bucket as little-endian u16s.
+    fn to_bytes(&self) -> Vec<u8> {
+        let quantize = |v: f32| ((v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16).to_le_bytes();
+        self.peaks
+            .iter()
+            .zip(&self.rms)
+            .flat_map(|(&p, &r)| quantize(p).into_iter().chain(quantize(r)))
+            .collect()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let value = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32;
+        let (peaks, rms) = bytes.chunks_exact(4).map(|c| (value(&c[..2]), value(&c[2..]))).unzip();
+        Self { peaks, rms }
+    }
+}
+
+/// Decode a whole file into a `STORED_BUCKETS` summary. Blocking.
+pub fn analyze_waveform(path: &str) -> Result<Waveform, String> {
+    let source = open_source(path, 0.0)?;
+    let frames_per_block = ((source.sample_rate() as f64 * BLOCK_SECS) as u64).max(1);
+    let samples_per_block = frames_per_block * source.channels().max(1) as u64;
+
+    let mut blocks = Vec::new();
+    let mut block = Block::default();
+    for sample in source {
+        block.peak = block.peak.max(sample.abs());
+        block.sum_squares += (sample as f64) * (sample as f64);
+        block.samples += 1;
+        if block.samples == samples_per_block {
+            blocks.push(block);
+            block = Block::default();
+        }
+    }
+    if block.samples > 0 {
+        blocks.push(block);
+    }
+    Ok(Waveform::from_blocks(&blocks, STORED_BUCKETS))
+}
+
+/// Modification time of a file in seconds, for telling whether a cached summary is stale.
+/// An entry inside an archive goes by the archive's.
+pub fn file_mtime(path: &str) -> Result<i64, String> {
+    let file = archive::split_entry_path(path).map_or(Path::new(path), |(archive, _)| archive);
+    let modified = file
+        .metadata()
+        .and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0))
+}
+
+/// The cached summary of `path`, if it was made from the file as it is now.
+pub async fn load_waveform(pool: &SqlitePool, path: &str, mtime: i64) -> Result<Option<Waveform>, sqlx::Error> {
+    let data: Option<Vec<u8>> = sqlx::query_scalar("SELECT data FROM track_waveforms WHERE path = ? AND mtime = ?")
+        .bind(path)
+        .bind(mtime)
+        .fetch_optional(pool)
+        .await?;
+    Ok(data.map(|d| Waveform::from_bytes(&d)))
+}
+
+pub async fn store_waveform(pool: &SqlitePool, path: &str, mtime: i64, waveform: &Waveform) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO track_waveforms (path, mtime, data) VALUES (?, ?, ?)
+         ON CONFLICT(path) DO UPDATE SET mtime = excluded.mtime, data = excluded.data"
+    )
+    .bind(path)
+    .bind(mtime)
+    .bind(waveform.to_bytes())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(level: f32) -> Block {
+        Block {
+            peak: level,
+            sum_squares: (level * level) as f64 * 100.0,
+            samples: 100,
+        }
+    }
+
+    #[test]
+    fn buckets_keep_the_loudest_peak_and_average_power() {
+        let blocks: Vec<Block> = [0.1, 0.5, 0.2, 0.2, 0.0, 0.0].into_iter().map(block).collect();
+        let waveform = Waveform::from_blocks(&blocks, 3);
+        assert_eq!(waveform.peaks, vec![0.5, 0.2, 0.0]);
+        assert!((waveform.rms[0] - 0.13f32.sqrt()).abs() < 1e-6);
+        assert!((waveform.rms[1] - 0.2).abs() < 1e-6);
+
+        // Asking for more detail than there is gives what there is.
+        assert_eq!(waveform.resample(10).peaks.len(), 3);
+        assert_eq!(waveform.resample(1).peaks, vec![0.5]);
+    }
+
+    #[test]
+    fn survives_the_round_trip_through_the_cache() {
+        let waveform = Waveform {
+            peaks: vec![0.0, 0.25, 1.0],
+            rms: vec![0.0, 0.1, 0.7],
+        };
+        let restored = Waveform::from_bytes(&waveform.to_bytes());
+        for (a, b) in waveform.peaks.iter().chain(&waveform.rms).zip(restored.peaks.iter().chain(&restored.rms)) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn archive_entries_go_by_the_archive() {
+        let archive = std::env::temp_dir().join(format!("waveform-test-{}.zip", std::process::id()));
+        std::fs::write(&archive, b"").unwrap();
+        let archive_path = archive.to_string_lossy().to_string();
+        let entry = format!("{}!/mp3/01.mp3", archive_path);
+        assert_eq!(file_mtime(&entry), file_mtime(&archive_path));
+        assert!(file_mtime(&entry).is_ok());
+        std::fs::remove_file(&archive).unwrap();
+        assert!(file_mtime(&entry).is_err());
+    }
+}
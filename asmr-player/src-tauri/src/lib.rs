@@ -1,6 +1,14 @@
+mod archive;
 mod audio;
+#[cfg(target_os = "linux")]
+mod mpris;
 mod scraper;
+mod roots;
+mod scan_job;
 mod scanner;
+#[cfg(test)]
+mod test_db;
+mod watcher;
 
 use sqlx::sqlite::SqlitePoolOptions;
 use std::sync::Mutex;
@@ -647,24 +655,9 @@ async fn save_playback_progress(
     track_id: i64,
     position_sec: f64
 ) -> Result<(), String> {
-    sqlx::query(
-        r#"
-        INSERT INTO track_progress (work_id, track_id, position_sec, updated_at)
-        VALUES (?, ?, ?, CURRENT_TIMESTAMP)
-        ON CONFLICT(work_id) DO UPDATE SET
-            track_id = excluded.track_id,
-            position_sec = excluded.position_sec,
-            updated_at = CURRENT_TIMESTAMP
-        "#
-    )
-    .bind(work_id)
-    .bind(track_id)
-    .bind(position_sec)
-    .execute(pool.inner())
-    .await
-    .map_err(|e| e.to_string())?;
-    
-    Ok(())
+    audio::save_progress(pool.inner(), work_id, track_id, position_sec)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -965,7 +958,11 @@ async fn delete_work(pool: tauri::State<'_, sqlx::SqlitePool>, work_id: i64, del
     // Delete files if requested
     if delete_files {
         let dir_path = std::path::Path::new(&work.dir_path);
-        if dir_path.exists() {
+        // Works packed in an archive are the archive itself.
+        if dir_path.is_file() {
+            std::fs::remove_file(dir_path)
+                .map_err(|e| format!("Failed to delete files: {}", e))?;
+        } else if dir_path.exists() {
             std::fs::remove_dir_all(dir_path)
                 .map_err(|e| format!("Failed to delete files: {}", e))?;
         }
@@ -984,6 +981,12 @@ pub fn run() {
         .setup(|app| {
             // Setup Audio State
             app.manage(Mutex::new(audio::AudioState::new()));
+            audio::spawn_queue_worker(app.handle().clone());
+            audio::spawn_output_watcher(app.handle().clone());
+            audio::spawn_progress_writer(app.handle().clone());
+            #[cfg(target_os = "linux")]
+            mpris::start(app.handle().clone());
+            watcher::start(app.handle().clone());
 
             let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
@@ -1012,6 +1015,7 @@ pub fn run() {
                     .await
                     .expect("Failed to run migrations");
 
+                audio::load_settings(&app_handle, &pool).await;
                 app_handle.manage(pool);
             });
 
@@ -1049,12 +1053,76 @@ pub fn run() {
             delete_work,
             scanner::scan_library,
             scanner::cleanup_orphaned_works,
+            roots::list_library_roots,
+            roots::add_library_root,
+            roots::remove_library_root,
+            roots::set_library_root_enabled,
+            roots::rescan_library_root,
+            roots::rescan_library,
+            scan_job::cancel_scan,
+            scan_job::get_scan_progress,
+            scan_job::list_scan_summaries,
             audio::play_track,
             audio::pause_track,
             audio::resume_track,
             audio::seek_track,
-            audio::set_volume
+            audio::get_playback_position,
+            audio::get_playback_state,
+            audio::set_volume,
+            audio::get_queue,
+            audio::queue_replace,
+            audio::queue_enqueue,
+            audio::queue_insert_next,
+            audio::queue_remove,
+            audio::queue_move,
+            audio::queue_jump,
+            audio::queue_next,
+            audio::queue_previous,
+            audio::queue_clear,
+            audio::set_sleep_timer,
+            audio::cancel_sleep_timer,
+            audio::get_sleep_timer,
+            audio::get_normalization,
+            audio::set_normalization,
+            audio::get_dsp_presets,
+            audio::get_dsp_settings,
+            audio::set_dsp_settings,
+            audio::clear_work_dsp_settings,
+            audio::get_stereo_settings,
+            audio::set_stereo_settings,
+            audio::get_transition_settings,
+            audio::set_transition_settings,
+            audio::get_track_waveform,
+            audio::get_spectrum_settings,
+            audio::set_spectrum_settings,
+            audio::get_skip_silence,
+            audio::set_skip_silence,
+            audio::get_playback_speed,
+            audio::set_playback_speed,
+            audio::set_ab_loop,
+            audio::clear_ab_loop,
+            audio::get_ab_loop,
+            audio::list_bookmarks,
+            audio::create_bookmark,
+            audio::delete_bookmark,
+            audio::jump_to_bookmark,
+            audio::loop_bookmark,
+            audio::get_playback_mode,
+            audio::set_repeat_mode,
+            audio::set_shuffle_mode,
+            audio::list_output_devices,
+            audio::get_output_device,
+            audio::set_output_device,
+            audio::start_render,
+            audio::stop_render,
+            audio::get_render_status,
+            audio::export_work
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                audio::flush_progress_blocking(app_handle);
+            }
+        });
 }
@@ -0,0 +1,482 @@
+// MPRIS2 server, so the desktop's media keys, lock screen and `playerctl` can see and
+// drive the player. Linux only; without a session bus the app just goes without.
+
+use crate::audio::{self, MediaStatus, MediaTrack, PlaybackState};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Listener, Manager};
+use tokio::sync::mpsc;
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{connection, fdo, interface};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.asmr_player";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// Picks up what no event announces, like volume changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A position this far from where playback should have got to is a seek.
+const SEEK_TOLERANCE_SECS: f64 = 1.0;
+/// Events after which the desktop's picture of the player may be out of date.
+const NUDGE_EVENTS: [&str; 5] = [
+    "playback-state-changed",
+    "track-changed",
+    "queue-changed",
+    "playback-mode-changed",
+    "playback-position",
+];
+
+fn micros(secs: f64) -> i64 {
+    (secs * 1_000_000.0) as i64
+}
+
+fn track_object_path(entry_id: u64) -> ObjectPath<'static> {
+    ObjectPath::from_string_unchecked(format!("/org/asmr_player/entry/{}", entry_id))
+}
+
+/// `file://` URI of a local path, percent-encoding everything but unreserved characters.
+fn file_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Local path of a `file://` URI.
+fn path_from_uri(uri: &str) -> Option<String> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Who made a work, for the artist fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Credits {
+    circles: Vec<String>,
+    voice_actors: Vec<String>,
+}
+
+/// What the desktop was last told about the player.
+#[derive(Debug, Clone, PartialEq)]
+struct Published {
+    state: PlaybackState,
+    track: Option<MediaTrack>,
+    credits: Credits,
+    volume: f64,
+    speed: f64,
+    can_go_next: bool,
+    can_go_previous: bool,
+}
+
+impl Published {
+    fn new(status: &MediaStatus, credits: Credits) -> Self {
+        Self {
+            state: status.state,
+            track: status.track.clone(),
+            credits,
+            volume: status.volume as f64,
+            speed: status.speed,
+            can_go_next: status.can_go_next,
+            can_go_previous: status.can_go_previous,
+        }
+    }
+}
+
+struct Root {
+    app: AppHandle,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {
+        if let Some(window) = self.app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+
+    fn quit(&self) {
+        self.app.exit(0);
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "ASMR Player".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        ["audio/mpeg", "audio/wav", "audio/flac", "audio/mp4", "audio/ogg"]
+            .map(String::from)
+            .to_vec()
+    }
+}
+
+struct Player {
+    app: AppHandle,
+    published: Published,
+}
+
+impl Player {
+    fn seek_to(&self, seconds: f64) -> fdo::Result<()> {
+        audio::seek_track(self.app.clone(), self.app.state(), seconds as f32).map_err(fdo::Error::Failed)
+    }
+
+    fn has_track(&self) -> bool {
+        self.published.track.is_some()
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) -> fdo::Result<()> {
+        audio::queue_next(self.app.clone(), self.app.state()).map_err(fdo::Error::Failed)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        audio::queue_previous(self.app.clone(), self.app.state()).map_err(fdo::Error::Failed)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        audio::pause_track(self.app.state()).map_err(fdo::Error::Failed)
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        match audio::media_status(&self.app).state {
+            PlaybackState::Playing | PlaybackState::Buffering => self.pause(),
+            PlaybackState::Paused | PlaybackState::Stopped => self.play(),
+        }
+    }
+
+    fn stop(&self) {
+        audio::media_stop(&self.app);
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        audio::media_play(&self.app).map_err(fdo::Error::Failed)
+    }
+
+    /// Relative seek in microseconds; past the end moves on to the next track.
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let status = audio::media_status(&self.app);
+        let Some(track) = status.track else {
+            return Ok(());
+        };
+        let target = status.position + offset as f64 / 1_000_000.0;
+        if track.duration.is_some_and(|d| target >= d) {
+            return self.next();
+        }
+        self.seek_to(target.max(0.0))
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let Some(track) = audio::media_status(&self.app).track else {
+            return Ok(());
+        };
+        let seconds = position as f64 / 1_000_000.0;
+        // Stale requests for a track that has since changed are ignored, as the spec asks.
+        if track_id != track_object_path(track.entry_id) || position < 0 || track.duration.is_some_and(|d| seconds > d) {
+            return Ok(());
+        }
+        self.seek_to(seconds)
+    }
+
+    async fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        let path = path_from_uri(&uri).ok_or_else(|| fdo::Error::NotSupported(format!("Can't open {}", uri)))?;
+        audio::play_track(self.app.clone(), self.app.state(), path)
+            .await
+            .map_err(fdo::Error::Failed)
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        match self.published.state {
+            // Loading counts as playing; it only lasts until the audio thread gets there.
+            PlaybackState::Playing | PlaybackState::Buffering => "Playing",
+            PlaybackState::Paused => "Paused",
+            PlaybackState::Stopped => "Stopped",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        self.published.speed
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        let mut metadata = HashMap::new();
+        let Some(ref track) = self.published.track else {
+            metadata.insert(
+                "mpris:trackid".to_string(),
+                Value::from(ObjectPath::from_string_unchecked(NO_TRACK.to_string())),
+            );
+            return metadata;
+        };
+        let credits = &self.published.credits;
+        metadata.insert("mpris:trackid".to_string(), Value::from(track_object_path(track.entry_id)));
+        metadata.insert("xesam:title".to_string(), Value::from(track.title.clone()));
+        metadata.insert("xesam:url".to_string(), Value::from(file_uri(&track.path)));
+        if let Some(duration) = track.duration {
+            metadata.insert("mpris:length".to_string(), Value::from(micros(duration)));
+        }
+        if let Some(ref work_title) = track.work_title {
+            metadata.insert("xesam:album".to_string(), Value::from(work_title.clone()));
+        }
+        if let Some(ref cover) = track.cover_path {
+            metadata.insert("mpris:artUrl".to_string(), Value::from(file_uri(cover)));
+        }
+        if !credits.voice_actors.is_empty() {
+            metadata.insert("xesam:artist".to_string(), Value::from(credits.voice_actors.clone()));
+        }
+        if !credits.circles.is_empty() {
+            metadata.insert("xesam:albumArtist".to_string(), Value::from(credits.circles.clone()));
+        }
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.published.volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) -> fdo::Result<()> {
+        let volume = volume.clamp(0.0, 1.0);
+        audio::set_volume(self.app.state(), volume as f32).map_err(fdo::Error::Failed)?;
+        self.published.volume = volume;
+        Ok(())
+    }
+
+    /// Read live; MPRIS clients extrapolate it from `Rate` and the `Seeked` signal.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        micros(audio::media_status(&self.app).position)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.published.can_go_next
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.published.can_go_previous
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.has_track()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.has_track()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.has_track()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+}
+
+/// Register on the session bus and keep the published state in step with `AudioState`.
+pub fn start(app: AppHandle) {
+    let (nudge_tx, nudges) = mpsc::unbounded_channel();
+    for event in NUDGE_EVENTS {
+        let nudge_tx = nudge_tx.clone();
+        app.listen_any(event, move |_| {
+            let _ = nudge_tx.send(());
+        });
+    }
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve(app, nudges).await {
+            eprintln!("[MPRIS] Media controls unavailable: {}", e);
+        }
+    });
+}
+
+async fn serve(app: AppHandle, mut nudges: mpsc::UnboundedReceiver<()>) -> zbus::Result<()> {
+    let status = audio::media_status(&app);
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root { app: app.clone() })?
+        .serve_at(
+            OBJECT_PATH,
+            Player {
+                app: app.clone(),
+                published: Published::new(&status, Credits::default()),
+            },
+        )?
+        .build()
+        .await?;
+    println!("[MPRIS] Registered as {}", BUS_NAME);
+    let player = connection.object_server().interface::<_, Player>(OBJECT_PATH).await?;
+
+    let mut credits: HashMap<i64, Credits> = HashMap::new();
+    // Entry, position, when, and how fast it was moving, to tell seeks from playback.
+    let mut last_position: Option<(u64, f64, Instant, f64)> = None;
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            nudge = nudges.recv() => {
+                if nudge.is_none() {
+                    return Ok(());
+                }
+            }
+            _ = poll.tick() => {}
+        }
+        while nudges.try_recv().is_ok() {}
+
+        let status = audio::media_status(&app);
+        let work_credits = match status.track.as_ref().and_then(|t| t.work_id) {
+            Some(work_id) => match credits.get(&work_id) {
+                Some(known) => known.clone(),
+                None => {
+                    let loaded = load_credits(&app, work_id).await;
+                    if let Some(ref loaded) = loaded {
+                        credits.insert(work_id, loaded.clone());
+                    }
+                    loaded.unwrap_or_default()
+                }
+            },
+            None => Credits::default(),
+        };
+        publish(&player, Published::new(&status, work_credits)).await?;
+
+        let now = Instant::now();
+        if let (Some(track), Some((entry_id, position, at, rate))) = (&status.track, last_position) {
+            let expected = position + now.duration_since(at).as_secs_f64() * rate;
+            if track.entry_id == entry_id && (status.position - expected).abs() > SEEK_TOLERANCE_SECS {
+                Player::seeked(player.signal_emitter(), micros(status.position)).await?;
+            }
+        }
+        let moving = status.state == PlaybackState::Playing;
+        last_position = status
+            .track
+            .as_ref()
+            .map(|t| (t.entry_id, status.position, now, if moving { status.speed } else { 0.0 }));
+    }
+}
+
+/// Tell the desktop about whatever changed since it was last told.
+async fn publish(player: &InterfaceRef<Player>, next: Published) -> zbus::Result<()> {
+    let mut iface = player.get_mut().await;
+    if iface.published == next {
+        return Ok(());
+    }
+    let previous = std::mem::replace(&mut iface.published, next);
+    let now = &iface.published;
+    let emitter = player.signal_emitter();
+    if previous.state != now.state {
+        iface.playback_status_changed(emitter).await?;
+    }
+    if previous.track != now.track || previous.credits != now.credits {
+        iface.metadata_changed(emitter).await?;
+    }
+    if previous.track.is_some() != now.track.is_some() {
+        iface.can_play_changed(emitter).await?;
+        iface.can_pause_changed(emitter).await?;
+        iface.can_seek_changed(emitter).await?;
+    }
+    if previous.volume != now.volume {
+        iface.volume_changed(emitter).await?;
+    }
+    if previous.speed != now.speed {
+        iface.rate_changed(emitter).await?;
+    }
+    if previous.can_go_next != now.can_go_next {
+        iface.can_go_next_changed(emitter).await?;
+    }
+    if previous.can_go_previous != now.can_go_previous {
+        iface.can_go_previous_changed(emitter).await?;
+    }
+    Ok(())
+}
+
+/// Circles and voice actors of a work; None if the database isn't ready yet.
+async fn load_credits(app: &AppHandle, work_id: i64) -> Option<Credits> {
+    let pool = app.try_state::<SqlitePool>()?;
+    let names = |sql: &'static str| {
+        sqlx::query_scalar::<_, String>(sql)
+            .bind(work_id)
+            .fetch_all(pool.inner())
+    };
+    let circles = names(
+        "SELECT c.name FROM circles c JOIN work_circles wc ON c.id = wc.circle_id WHERE wc.work_id = ? ORDER BY c.name",
+    )
+    .await;
+    let voice_actors = names(
+        "SELECT v.name FROM voice_actors v JOIN work_voice_actors wv ON v.id = wv.voice_actor_id WHERE wv.work_id = ? ORDER BY v.name",
+    )
+    .await;
+    match (circles, voice_actors) {
+        (Ok(circles), Ok(voice_actors)) => Some(Credits { circles, voice_actors }),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("[MPRIS] Failed to load credits of work {}: {}", work_id, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uris_round_trip() {
+        let path = "/home/me/ASMR/RJ01234567 耳かき/01 100% chill #1.flac";
+        let uri = file_uri(path);
+        assert!(uri.starts_with("file:///home/me/ASMR/RJ01234567%20%E8%80%B3"));
+        assert!(!uri.contains(' ') && !uri.contains('#'));
+        assert_eq!(path_from_uri(&uri).as_deref(), Some(path));
+        assert_eq!(path_from_uri("http://example.com/a.mp3"), None);
+        assert_eq!(path_from_uri("file:///broken%2"), None);
+    }
+}
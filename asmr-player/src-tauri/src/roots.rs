@@ -0,0 +1,325 @@
+use crate::scan_job::ScanJob;
+use crate::scanner::{self, ScanOutcome, SCAN_LOCK};
+use crate::watcher;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::path::Path;
+use std::sync::Arc;
+use tauri::AppHandle;
+
+/// A folder that is part of the library, with how its last scan went.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct LibraryRoot {
+    pub id: i64,
+    pub path: String,
+    pub enabled: bool,
+    pub last_scanned_at: Option<String>,
+    pub work_count: i64,
+    pub error_count: i64,
+    pub last_error: Option<String>,
+}
+
+const ROOT_COLUMNS: &str = "id, path, enabled, last_scanned_at, work_count, error_count, last_error";
+
+pub async fn list_roots(pool: &SqlitePool) -> Result<Vec<LibraryRoot>, sqlx::Error> {
+    sqlx::query_as(&format!("SELECT {} FROM library_roots ORDER BY path", ROOT_COLUMNS))
+        .fetch_all(pool)
+        .await
+}
+
+/// Roots the scanner and watcher should look at.
+pub async fn enabled_roots(pool: &SqlitePool) -> Result<Vec<LibraryRoot>, sqlx::Error> {
+    sqlx::query_as(&format!("SELECT {} FROM library_roots WHERE enabled = 1 ORDER BY path", ROOT_COLUMNS))
+        .fetch_all(pool)
+        .await
+}
+
+async fn get_root(pool: &SqlitePool, id: i64) -> Result<LibraryRoot, String> {
+    sqlx::query_as(&format!("SELECT {} FROM library_roots WHERE id = ?", ROOT_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Library folder {} not found", id))
+}
+
+/// The root for `path`, added if it isn't one yet.
+pub async fn ensure_root(pool: &SqlitePool, path: &str) -> Result<LibraryRoot, sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO library_roots (path) VALUES (?)")
+        .bind(path)
+        .execute(pool)
+        .await?;
+    watcher::roots_changed();
+    sqlx::query_as(&format!("SELECT {} FROM library_roots WHERE path = ?", ROOT_COLUMNS))
+        .bind(path)
+        .fetch_one(pool)
+        .await
+}
+
+/// Scan a root and record how it went. Callers hold `SCAN_LOCK`.
+pub async fn scan_root(
+    job: &Arc<ScanJob>,
+    pool: &SqlitePool,
+    root: &LibraryRoot,
+    hash_files: bool,
+) -> Result<ScanOutcome, String> {
+    job.start_root(&root.path);
+    let path = Path::new(&root.path);
+    let outcome = if path.is_dir() {
+        scanner::scan_tree(job, pool, path, false, hash_files).await
+    } else {
+        Err(format!("Directory does not exist: {}", root.path))
+    };
+    let (error_count, last_error) = match outcome {
+        Ok(ref outcome) => (outcome.errors.len() as i64, outcome.errors.last().cloned()),
+        Err(ref e) => (1, Some(e.clone())),
+    };
+    sqlx::query(
+        "UPDATE library_roots SET last_scanned_at = CURRENT_TIMESTAMP, error_count = ?, last_error = ?,
+         work_count = (SELECT COUNT(*) FROM works WHERE root_id = library_roots.id)
+         WHERE id = ?"
+    )
+    .bind(error_count)
+    .bind(last_error)
+    .bind(root.id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    outcome
+}
+
+/// Bring every root's work count up to date after works were added or removed.
+pub async fn count_works(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE library_roots SET work_count = (SELECT COUNT(*) FROM works WHERE root_id = library_roots.id)")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Put a work under the innermost root its folder is in, so a work under nested roots
+/// stays with the inner one whichever of them was scanned.
+pub async fn assign_root(pool: &SqlitePool, work_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE works SET root_id = (
+            SELECT r.id FROM library_roots r
+            WHERE works.dir_path = r.path
+               OR substr(works.dir_path, 1, length(rtrim(r.path, '/\')) + 1)
+                  IN (rtrim(r.path, '/\') || '/', rtrim(r.path, '/\') || '\')
+            ORDER BY length(r.path) DESC
+            LIMIT 1
+        )
+        WHERE id = ?
+        "#
+    )
+    .bind(work_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Forget a root and the works found in it. Callers hold `SCAN_LOCK`.
+pub async fn remove_root(pool: &SqlitePool, id: i64) -> Result<usize, sqlx::Error> {
+    let work_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM works WHERE root_id = ?")
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+    for &work_id in &work_ids {
+        scanner::delete_work_records(pool, work_id).await;
+    }
+    sqlx::query("DELETE FROM library_roots WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    watcher::roots_changed();
+    Ok(work_ids.len())
+}
+
+pub async fn set_enabled(pool: &SqlitePool, id: i64, enabled: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE library_roots SET enabled = ? WHERE id = ?")
+        .bind(enabled)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    watcher::roots_changed();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_library_roots(pool: tauri::State<'_, SqlitePool>) -> Result<Vec<LibraryRoot>, String> {
+    list_roots(pool.inner()).await.map_err(|e| e.to_string())
+}
+
+/// Add a folder to the library and scan it.
+#[tauri::command]
+pub async fn add_library_root(
+    app: AppHandle,
+    pool: tauri::State<'_, SqlitePool>,
+    path: String,
+) -> Result<LibraryRoot, String> {
+    let pool = pool.inner();
+    if !Path::new(&path).is_dir() {
+        return Err("Directory does not exist".to_string());
+    }
+    let root = ensure_root(pool, &path).await.map_err(|e| e.to_string())?;
+    let _scanning = SCAN_LOCK.lock().await;
+    let job = ScanJob::begin(&app);
+    let outcome = scan_root(&job, pool, &root, false).await;
+    job.finish(pool, outcome.as_ref().err().map(String::as_str)).await;
+    outcome?;
+    get_root(pool, root.id).await
+}
+
+/// Take a folder out of the library along with the works found in it. The files stay.
+#[tauri::command]
+pub async fn remove_library_root(pool: tauri::State<'_, SqlitePool>, id: i64) -> Result<usize, String> {
+    let _scanning = SCAN_LOCK.lock().await;
+    let removed = remove_root(pool.inner(), id).await.map_err(|e| e.to_string())?;
+    println!("Removed library folder {} and {} works", id, removed);
+    Ok(removed)
+}
+
+/// A disabled root is skipped by rescans and the watcher; its works stay as they are.
+#[tauri::command]
+pub async fn set_library_root_enabled(
+    pool: tauri::State<'_, SqlitePool>,
+    id: i64,
+    enabled: bool,
+) -> Result<LibraryRoot, String> {
+    let pool = pool.inner();
+    set_enabled(pool, id, enabled).await.map_err(|e| e.to_string())?;
+    get_root(pool, id).await
+}
+
+#[tauri::command]
+pub async fn rescan_library_root(
+    app: AppHandle,
+    pool: tauri::State<'_, SqlitePool>,
+    id: i64,
+    hash_files: Option<bool>,
+) -> Result<LibraryRoot, String> {
+    let pool = pool.inner();
+    let root = get_root(pool, id).await?;
+    if !root.enabled {
+        return Err(format!("Library folder {} is disabled", root.path));
+    }
+    let _scanning = SCAN_LOCK.lock().await;
+    let job = ScanJob::begin(&app);
+    let outcome = scan_root(&job, pool, &root, hash_files.unwrap_or(false)).await;
+    job.finish(pool, outcome.as_ref().err().map(String::as_str)).await;
+    outcome?;
+    get_root(pool, id).await
+}
+
+/// Rescan every enabled root, all as one scan. A root that fails doesn't stop the
+/// others; its error is kept with its statistics and the scan's summary.
+#[tauri::command]
+pub async fn rescan_library(
+    app: AppHandle,
+    pool: tauri::State<'_, SqlitePool>,
+    hash_files: Option<bool>,
+) -> Result<Vec<LibraryRoot>, String> {
+    let pool = pool.inner();
+    let _scanning = SCAN_LOCK.lock().await;
+    let job = ScanJob::begin(&app);
+    let roots = match enabled_roots(pool).await {
+        Ok(roots) => roots,
+        Err(e) => {
+            job.finish(pool, Some(&e.to_string())).await;
+            return Err(e.to_string());
+        }
+    };
+    for root in roots {
+        if job.cancelled() {
+            break;
+        }
+        if let Err(e) = scan_root(&job, pool, &root, hash_files.unwrap_or(false)).await {
+            job.record_error(format!("Failed to scan {}: {}", root.path, e));
+        }
+    }
+    job.finish(pool, None).await;
+    list_roots(pool).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_db::library;
+
+    async fn add_work(pool: &SqlitePool, dir: &str) -> i64 {
+        let id = sqlx::query_scalar("INSERT INTO works (title, dir_path) VALUES ('Work', ?) RETURNING id")
+            .bind(dir)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assign_root(pool, id).await.unwrap();
+        id
+    }
+
+    async fn root_of(pool: &SqlitePool, work_id: i64) -> Option<i64> {
+        sqlx::query_scalar("SELECT root_id FROM works WHERE id = ?")
+            .bind(work_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn roots_are_added_once_and_disabled_ones_are_left_out() {
+        let pool = library().await;
+        let lib = ensure_root(&pool, "/lib").await.unwrap();
+        assert_eq!(ensure_root(&pool, "/lib").await.unwrap().id, lib.id);
+        let other = ensure_root(&pool, "/other").await.unwrap();
+        assert!(lib.enabled && other.enabled);
+
+        set_enabled(&pool, other.id, false).await.unwrap();
+        let enabled: Vec<_> = enabled_roots(&pool).await.unwrap().into_iter().map(|r| r.path).collect();
+        assert_eq!(enabled, ["/lib"]);
+        assert_eq!(list_roots(&pool).await.unwrap().len(), 2);
+
+        set_enabled(&pool, other.id, true).await.unwrap();
+        assert_eq!(enabled_roots(&pool).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn removing_a_root_takes_its_works_along() {
+        let pool = library().await;
+        let lib = ensure_root(&pool, "/lib").await.unwrap();
+        let other = ensure_root(&pool, "/other").await.unwrap();
+        add_work(&pool, "/lib/RJ01").await;
+        add_work(&pool, "/lib/RJ02.zip").await;
+        let kept = add_work(&pool, "/other/RJ03").await;
+
+        assert_eq!(remove_root(&pool, lib.id).await.unwrap(), 2);
+        let works: Vec<i64> = sqlx::query_scalar("SELECT id FROM works").fetch_all(&pool).await.unwrap();
+        assert_eq!(works, [kept]);
+        let roots: Vec<_> = list_roots(&pool).await.unwrap().into_iter().map(|r| r.id).collect();
+        assert_eq!(roots, [other.id]);
+    }
+
+    #[tokio::test]
+    async fn works_go_under_the_innermost_root() {
+        let pool = library().await;
+        let lib = ensure_root(&pool, "/lib").await.unwrap();
+        let voice = ensure_root(&pool, "/lib/voice/").await.unwrap();
+
+        let outer = add_work(&pool, "/lib/RJ01").await;
+        let inner = add_work(&pool, "/lib/voice/RJ02").await;
+        // A root that looks like a prefix of another folder doesn't hold it.
+        let beside = add_work(&pool, "/lib/voices/RJ03").await;
+        let elsewhere = add_work(&pool, "/library/RJ04").await;
+        let windows = add_work(&pool, "/lib/voice\\RJ05").await;
+        assert_eq!(root_of(&pool, outer).await, Some(lib.id));
+        assert_eq!(root_of(&pool, inner).await, Some(voice.id));
+        assert_eq!(root_of(&pool, beside).await, Some(lib.id));
+        assert_eq!(root_of(&pool, elsewhere).await, None);
+        assert_eq!(root_of(&pool, windows).await, Some(voice.id));
+
+        // Scanning the outer root again doesn't take the work from the inner one.
+        assign_root(&pool, inner).await.unwrap();
+        assert_eq!(root_of(&pool, inner).await, Some(voice.id));
+        count_works(&pool).await.unwrap();
+        let counts: Vec<_> = list_roots(&pool).await.unwrap().into_iter().map(|r| (r.id, r.work_count)).collect();
+        assert_eq!(counts, [(lib.id, 2), (voice.id, 2)]);
+    }
+}
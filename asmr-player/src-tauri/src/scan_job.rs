@@ -0,0 +1,314 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+/// Progress events are sent at most this often, phase changes aside.
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+/// Errors kept with a stored summary; the count covers the rest.
+const MAX_STORED_ERRORS: usize = 50;
+
+/// The scan the user can watch and cancel; the watcher's small rescans aren't one.
+static CURRENT: Mutex<Option<Arc<ScanJob>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPhase {
+    /// Walking the folders for works.
+    Discovering,
+    /// Reading the tracks of the works found.
+    Scanning,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+impl ScanPhase {
+    fn as_str(self) -> &'static str {
+        match self {
+            ScanPhase::Discovering => "discovering",
+            ScanPhase::Scanning => "scanning",
+            ScanPhase::Finished => "finished",
+            ScanPhase::Cancelled => "cancelled",
+            ScanPhase::Failed => "failed",
+        }
+    }
+}
+
+/// Payload of `scan-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProgress {
+    pub phase: ScanPhase,
+    pub root: Option<String>,
+    pub current_path: Option<String>,
+    /// Works found so far, and how many of them have been scanned.
+    pub works_found: usize,
+    pub works_done: usize,
+    pub tracks_found: usize,
+    pub files_failed: usize,
+    pub elapsed_secs: f64,
+    /// Until every work found so far is scanned; None until there is a rate to go by.
+    pub eta_secs: Option<f64>,
+}
+
+/// A finished scan as stored in `scan_summaries`, and the payload of `scan-finished`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ScanSummary {
+    pub id: i64,
+    pub status: String,
+    pub roots: String,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub works_found: i64,
+    pub tracks_found: i64,
+    pub files_failed: i64,
+    pub error_count: i64,
+    /// JSON array of the first errors.
+    pub errors: String,
+}
+
+struct JobState {
+    progress: ScanProgress,
+    roots: Vec<String>,
+    errors: Vec<String>,
+    error_count: usize,
+    // When works started being scanned, for the ETA.
+    scanning_since: Option<Instant>,
+    last_emit: Option<Instant>,
+}
+
+/// One run of the scanner, shared by its workers.
+pub struct ScanJob {
+    app: Option<AppHandle>,
+    cancel: AtomicBool,
+    started: Instant,
+    state: Mutex<JobState>,
+}
+
+impl ScanJob {
+    fn new(app: Option<AppHandle>) -> Self {
+        Self {
+            app,
+            cancel: AtomicBool::new(false),
+            started: Instant::now(),
+            state: Mutex::new(JobState {
+                progress: ScanProgress {
+                    phase: ScanPhase::Discovering,
+                    root: None,
+                    current_path: None,
+                    works_found: 0,
+                    works_done: 0,
+                    tracks_found: 0,
+                    files_failed: 0,
+                    elapsed_secs: 0.0,
+                    eta_secs: None,
+                },
+                roots: Vec::new(),
+                errors: Vec::new(),
+                error_count: 0,
+                scanning_since: None,
+                last_emit: None,
+            }),
+        }
+    }
+
+    /// Start the scan the frontend sees. Callers hold `SCAN_LOCK`, so there is only one.
+    pub fn begin(app: &AppHandle) -> Arc<Self> {
+        let job = Arc::new(Self::new(Some(app.clone())));
+        if let Ok(mut current) = CURRENT.lock() {
+            *current = Some(job.clone());
+        }
+        job
+    }
+
+    /// A job nobody watches, for scans the app starts by itself.
+    pub fn detached() -> Arc<Self> {
+        Arc::new(Self::new(None))
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Acquire)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, JobState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Change the progress and let the frontend know, at most every `EMIT_INTERVAL`
+    /// unless the phase changed.
+    pub fn update(&self, change: impl FnOnce(&mut ScanProgress)) {
+        let mut state = self.state();
+        let phase = state.progress.phase;
+        change(&mut state.progress);
+        let now = Instant::now();
+        if state.progress.phase == ScanPhase::Scanning && state.scanning_since.is_none() {
+            state.scanning_since = Some(now);
+        }
+
+        let scanning_since = state.scanning_since;
+        let progress = &mut state.progress;
+        progress.elapsed_secs = self.started.elapsed().as_secs_f64();
+        let remaining = progress.works_found.saturating_sub(progress.works_done);
+        progress.eta_secs = match scanning_since {
+            Some(since) if progress.works_done > 0 => {
+                let per_work = now.duration_since(since).as_secs_f64() / progress.works_done as f64;
+                Some(per_work * remaining as f64)
+            }
+            _ => None,
+        };
+
+        let due = state.last_emit.is_none_or(|t| now.duration_since(t) >= EMIT_INTERVAL);
+        if due || state.progress.phase != phase {
+            state.last_emit = Some(now);
+            if let Some(ref app) = self.app {
+                let _ = app.emit("scan-progress", &state.progress);
+            }
+        }
+    }
+
+    pub fn start_root(&self, root: &str) {
+        self.state().roots.push(root.to_string());
+        self.update(|p| {
+            p.phase = ScanPhase::Discovering;
+            p.root = Some(root.to_string());
+            p.current_path = None;
+        });
+    }
+
+    pub fn record_error(&self, error: String) {
+        eprintln!("{}", error);
+        let mut state = self.state();
+        state.error_count += 1;
+        if state.errors.len() < MAX_STORED_ERRORS {
+            state.errors.push(error);
+        }
+    }
+
+    /// Close the job, store its summary and tell the frontend. `failure` is what
+    /// stopped it early, if anything did.
+    pub async fn finish(&self, pool: &SqlitePool, failure: Option<&str>) -> Option<ScanSummary> {
+        if let Some(e) = failure {
+            self.record_error(e.to_string());
+        }
+        let phase = match failure {
+            Some(_) => ScanPhase::Failed,
+            None if self.cancelled() => ScanPhase::Cancelled,
+            None => ScanPhase::Finished,
+        };
+        self.update(|p| {
+            p.phase = phase;
+            p.current_path = None;
+        });
+        if let Ok(mut current) = CURRENT.lock() {
+            if current.as_ref().is_some_and(|j| std::ptr::eq(j.as_ref(), self)) {
+                *current = None;
+            }
+        }
+
+        let (progress, roots, errors, error_count) = {
+            let state = self.state();
+            (state.progress.clone(), state.roots.clone(), state.errors.clone(), state.error_count)
+        };
+        let stored = sqlx::query_as::<_, ScanSummary>(
+            "INSERT INTO scan_summaries (status, roots, started_at, duration_secs, works_found, tracks_found, files_failed, error_count, errors)
+             VALUES (?, ?, datetime('now', ?), ?, ?, ?, ?, ?, ?)
+             RETURNING id, status, roots, started_at, duration_secs, works_found, tracks_found, files_failed, error_count, errors"
+        )
+        .bind(phase.as_str())
+        .bind(serde_json::to_string(&roots).unwrap_or_default())
+        .bind(format!("-{:.0} seconds", progress.elapsed_secs))
+        .bind(progress.elapsed_secs)
+        .bind(progress.works_found as i64)
+        .bind(progress.tracks_found as i64)
+        .bind(progress.files_failed as i64)
+        .bind(error_count as i64)
+        .bind(serde_json::to_string(&errors).unwrap_or_default())
+        .fetch_one(pool)
+        .await;
+
+        match stored {
+            Ok(summary) => {
+                println!(
+                    "Scan {}: {} works, {} tracks, {} failed in {:.1}s",
+                    summary.status, summary.works_found, summary.tracks_found, summary.files_failed, summary.duration_secs
+                );
+                if let Some(ref app) = self.app {
+                    let _ = app.emit("scan-finished", &summary);
+                }
+                Some(summary)
+            }
+            Err(e) => {
+                eprintln!("Failed to store scan summary: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Stop the running scan. Works being scanned are finished; nothing new is started.
+/// Returns false if nothing was scanning.
+#[tauri::command]
+pub fn cancel_scan() -> Result<bool, String> {
+    let current = CURRENT.lock().map_err(|e| e.to_string())?;
+    match current.as_ref() {
+        Some(job) => {
+            job.cancel.store(true, Ordering::Release);
+            println!("Cancelling scan");
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[tauri::command]
+pub fn get_scan_progress() -> Result<Option<ScanProgress>, String> {
+    let current = CURRENT.lock().map_err(|e| e.to_string())?;
+    Ok(current.as_ref().map(|job| job.state().progress.clone()))
+}
+
+/// Stored summaries of past scans, newest first.
+#[tauri::command]
+pub async fn list_scan_summaries(
+    pool: tauri::State<'_, SqlitePool>,
+    limit: Option<i64>,
+) -> Result<Vec<ScanSummary>, String> {
+    sqlx::query_as(
+        "SELECT id, status, roots, started_at, duration_secs, works_found, tracks_found, files_failed, error_count, errors
+         FROM scan_summaries ORDER BY id DESC LIMIT ?"
+    )
+    .bind(limit.unwrap_or(20))
+    .fetch_all(pool.inner())
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_follows_the_rate_works_are_scanned_at() {
+        let job = ScanJob::detached();
+        job.update(|p| p.works_found = 10);
+        assert_eq!(job.state().progress.eta_secs, None);
+
+        job.update(|p| p.phase = ScanPhase::Scanning);
+        job.state().scanning_since = Some(Instant::now() - Duration::from_secs(4));
+        job.update(|p| p.works_done = 2);
+        let eta = job.state().progress.eta_secs.unwrap();
+        assert!((eta - 16.0).abs() < 0.5, "{}", eta);
+    }
+
+    #[test]
+    fn only_the_first_errors_are_kept() {
+        let job = ScanJob::detached();
+        for i in 0..MAX_STORED_ERRORS + 5 {
+            job.record_error(format!("error {}", i));
+        }
+        let state = job.state();
+        assert_eq!(state.error_count, MAX_STORED_ERRORS + 5);
+        assert_eq!(state.errors.len(), MAX_STORED_ERRORS);
+    }
+}
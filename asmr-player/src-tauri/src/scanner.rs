@@ -1,25 +1,116 @@
+use crate::archive;
+use crate::audio::{analyze_loudness, analyze_loudness_and_silence, detect_silence, Loudness, Silence};
+use crate::roots;
+use crate::scan_job::{ScanJob, ScanPhase};
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, BufReader};
 use std::path::{Path, PathBuf};
-use tauri::{AppHandle, Emitter};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tauri::AppHandle;
+use tokio::task::JoinSet;
 use walkdir::WalkDir;
-use lofty::{read_from_path, prelude::*};
+use lofty::{file::TaggedFile, probe::Probe, read_from_path, prelude::*};
 
+/// Held by whatever is writing works and tracks, so a manual scan and the watcher
+/// never work on the same folder at once.
+pub static SCAN_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Scan `root_path` for works, adding it to the library roots if it isn't one yet.
+/// Files that haven't changed since the last scan are not read again, and tracks keep
+/// their ids. With `hash_files`, new and changed files are hashed too, so a renamed
+/// file keeps its track and a touched one isn't re-probed.
 #[tauri::command]
 pub async fn scan_library(
     app: AppHandle,
     root_path: String,
+    hash_files: Option<bool>,
     pool: tauri::State<'_, SqlitePool>,
 ) -> Result<usize, String> {
     let pool = pool.inner();
-    let root = Path::new(&root_path);
-    if !root.exists() {
+    if !Path::new(&root_path).exists() {
         return Err("Directory does not exist".to_string());
     }
+    let root = roots::ensure_root(pool, &root_path).await.map_err(|e| e.to_string())?;
+    let _scanning = SCAN_LOCK.lock().await;
+    let job = ScanJob::begin(&app);
+    let outcome = roots::scan_root(&job, pool, &root, hash_files.unwrap_or(false)).await;
+    job.finish(pool, outcome.as_ref().err().map(String::as_str)).await;
+    Ok(outcome?.works)
+}
 
-    let rj_regex = Regex::new(r"(RJ|BJ)\d{6,8}").unwrap();
+/// What a walk over a folder found.
+#[derive(Debug, Default)]
+pub struct ScanOutcome {
+    pub works: usize,
+    pub errors: Vec<String>,
+}
+
+/// A folder or archive that looks like a work.
+struct WorkCandidate {
+    path: PathBuf,
+    rj_code: Option<String>,
+    title: String,
+}
+
+/// Works scanned at once; each probes its files one after the other.
+fn worker_count() -> usize {
+    std::thread::available_parallelism().map_or(2, |n| n.get()).clamp(1, 8)
+}
+
+/// Register every work under `root`, and `root` itself if `include_root` and it looks
+/// like one. Each goes under the innermost library root it is in. Callers hold `SCAN_LOCK`.
+pub async fn scan_tree(
+    job: &Arc<ScanJob>,
+    pool: &SqlitePool,
+    root: &Path,
+    include_root: bool,
+    hash_files: bool,
+) -> Result<ScanOutcome, String> {
+    let (candidates, mut errors) = {
+        let (job, root) = (job.clone(), root.to_path_buf());
+        tokio::task::spawn_blocking(move || find_works(&job, &root, include_root))
+            .await
+            .map_err(|e| e.to_string())?
+    };
+    job.update(|p| p.phase = ScanPhase::Scanning);
+
+    let workers = worker_count();
+    let mut candidates = candidates.into_iter();
+    let mut tasks = JoinSet::new();
     let mut count = 0;
+    loop {
+        while tasks.len() < workers && !job.cancelled() {
+            let Some(candidate) = candidates.next() else {
+                break;
+            };
+            tasks.spawn(register_work(job.clone(), pool.clone(), candidate, hash_files));
+        }
+        let Some(result) = tasks.join_next().await else {
+            break;
+        };
+        match result {
+            Ok(Ok(())) => count += 1,
+            Ok(Err(e)) => errors.push(e),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    for e in &errors {
+        job.record_error(e.clone());
+    }
+    Ok(ScanOutcome { works: count, errors })
+}
+
+/// Walk `root` for works without touching the database. Blocking.
+fn find_works(job: &ScanJob, root: &Path, include_root: bool) -> (Vec<WorkCandidate>, Vec<String>) {
+    let rj_regex = Regex::new(r"(RJ|BJ)\d{6,8}").unwrap();
+    let mut candidates = Vec::new();
+    let mut errors = Vec::new();
 
     // Use WalkDir with max_depth(1) to iterate over immediate subdirectories of the root
     // If the user selects a folder "MyLibrary" containing "RJ123456", "RJ654321", "ASMR_Folder"
@@ -50,22 +141,29 @@ pub async fn scan_library(
     //    Simple approach: Just register every folder that has audio files or RJ code.
 
     let mut it = WalkDir::new(root).into_iter();
-    
+
     loop {
+        if job.cancelled() {
+            break;
+        }
         let entry = match it.next() {
             None => break,
-            Some(Err(_)) => continue,
+            Some(Err(e)) => {
+                errors.push(e.to_string());
+                continue;
+            }
             Some(Ok(e)) => e,
         };
 
         let path = entry.path();
-        
+
         // Skip root directory itself
-        if path == root {
+        if path == root && !include_root {
             continue;
         }
 
         if path.is_dir() {
+            job.update(|p| p.current_path = Some(path.to_string_lossy().to_string()));
             let dir_name = path.file_name().unwrap_or_default().to_string_lossy();
             let mut is_work = false;
             let mut rj_code: Option<String> = None;
@@ -82,69 +180,117 @@ pub async fn scan_library(
             }
 
             if is_work {
-                let path_str = path.to_string_lossy().to_string();
-                
-                // Priority 1: Try to extract from audio file metadata (embedded cover)
-                let mut cover_path = extract_embedded_cover(path);
-                
-                // Priority 2: Fallback to image files in folder
-                if cover_path.is_none() {
-                    cover_path = find_cover_image(path);
-                }
+                candidates.push(WorkCandidate {
+                    path: path.to_path_buf(),
+                    rj_code,
+                    title,
+                });
+                job.update(|p| p.works_found += 1);
 
-                let existing_id: Option<i64> = sqlx::query("SELECT id FROM works WHERE dir_path = ?")
-                    .bind(&path_str)
-                    .fetch_optional(pool)
-                    .await
-                    .ok()
-                    .flatten()
-                    .map(|row| {
-                        use sqlx::Row;
-                        row.get(0)
-                    });
-
-                let work_id: Option<i64> = if let Some(eid) = existing_id {
-                    Some(eid)
-                } else {
-                    sqlx::query(
-                        r#"
-                        INSERT INTO works (rj_code, title, dir_path, cover_path) 
-                        VALUES (?, ?, ?, ?)
-                        RETURNING id
-                        "#
-                    )
-                    .bind(rj_code)
-                    .bind(title)
-                    .bind(path_str)
-                    .bind(cover_path)
-                    .fetch_optional(pool)
-                    .await
-                    .map_err(|e| e.to_string())?
-                    .map(|row| {
-                        use sqlx::Row;
-                        row.get(0)
-                    })
-                };
-
-                if let Some(wid) = work_id {
-                    count += 1;
-                    // Scan for tracks in this directory
-                    let _ = sqlx::query("DELETE FROM tracks WHERE work_id = ?")
-                        .bind(wid)
-                        .execute(pool)
-                        .await;
-
-                    scan_tracks(wid, path, pool).await.ok();
-                    app.emit("scan-progress", count).ok();
-                }
-                
                 // IMPORTANT: Do not scan subdirectories of a Work
                 it.skip_current_dir();
             }
+        } else if archive::is_archive(path) {
+            // Only archives named after a work; anything else is likely not one.
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let Some(caps) = rj_regex.captures(&name) else {
+                continue;
+            };
+            let title = archive::stem(path);
+            if path.with_file_name(&title).is_dir() {
+                println!("Skipping {}: already extracted next to it", path.display());
+                continue;
+            }
+            if archive::extractor_for(path).is_none() {
+                println!("Skipping {}: this kind of archive can't be read yet", path.display());
+                continue;
+            }
+            candidates.push(WorkCandidate {
+                path: path.to_path_buf(),
+                rj_code: Some(caps[0].to_string()),
+                title,
+            });
+            job.update(|p| p.works_found += 1);
         }
     }
 
-    Ok(count)
+    (candidates, errors)
+}
+
+/// Add a work found on disk, or look up the one already there, and scan its tracks.
+async fn register_work(
+    job: Arc<ScanJob>,
+    pool: SqlitePool,
+    candidate: WorkCandidate,
+    hash_files: bool,
+) -> Result<(), String> {
+    let WorkCandidate { path, rj_code, title } = candidate;
+    let path_str = path.to_string_lossy().to_string();
+    job.update(|p| p.current_path = Some(path_str.clone()));
+
+    let cover_path = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || {
+            if archive::is_archive(&path) {
+                return extract_archive_cover(&path);
+            }
+            // Priority 1: Try to extract from audio file metadata (embedded cover)
+            // Priority 2: Fallback to image files in folder
+            extract_embedded_cover(&path).or_else(|| find_cover_image(&path))
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    };
+
+    let existing_id: Option<i64> = sqlx::query_scalar("SELECT id FROM works WHERE dir_path = ?")
+        .bind(&path_str)
+        .fetch_optional(&pool)
+        .await
+        .ok()
+        .flatten();
+
+    let work_id: i64 = if let Some(eid) = existing_id {
+        eid
+    } else {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO works (rj_code, title, dir_path, cover_path) 
+            VALUES (?, ?, ?, ?)
+            RETURNING id
+            "#
+        )
+        .bind(rj_code)
+        .bind(title)
+        .bind(&path_str)
+        .bind(cover_path)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| format!("Failed to add work {}: {}", path_str, e))?
+    };
+    // Works scanned before roots were recorded, or found again from an outer root.
+    roots::assign_root(&pool, work_id)
+        .await
+        .map_err(|e| format!("Failed to assign work {} to its library folder: {}", path_str, e))?;
+
+    let scanned = rescan_work(&job, &pool, work_id, &path, hash_files).await;
+    job.update(|p| p.works_done += 1);
+    scanned
+}
+
+/// Bring the tracks of a work that is (again) on disk up to date.
+pub async fn rescan_work(job: &ScanJob, pool: &SqlitePool, work_id: i64, path: &Path, hash_files: bool) -> Result<(), String> {
+    sqlx::query("UPDATE works SET missing = 0 WHERE id = ? AND missing = 1")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to clear missing flag of work {}: {}", work_id, e))?;
+    let files = work_files(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    match scan_tracks(job, work_id, files, pool, hash_files).await {
+        Ok(changes) if changes.any() => println!("Rescanned {}: {:?}", path.display(), changes),
+        Ok(_) => {}
+        Err(e) => return Err(format!("Failed to scan tracks of {}: {}", path.display(), e)),
+    }
+    Ok(())
 }
 
 /// Cleanup works whose folders no longer exist on disk
@@ -153,6 +299,7 @@ pub async fn cleanup_orphaned_works(
     pool: tauri::State<'_, SqlitePool>,
 ) -> Result<u32, String> {
     let pool = pool.inner();
+    let _scanning = SCAN_LOCK.lock().await;
     
     // Get all works with their directory paths
     let works: Vec<(i64, String)> = sqlx::query_as("SELECT id, dir_path FROM works")
@@ -167,50 +314,7 @@ pub async fn cleanup_orphaned_works(
         
         // If the directory no longer exists, remove the work from DB
         if !path.exists() {
-            // Delete related records first (foreign key constraints)
-            sqlx::query("DELETE FROM tracks WHERE work_id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            sqlx::query("DELETE FROM work_voice_actors WHERE work_id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            sqlx::query("DELETE FROM work_circles WHERE work_id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            sqlx::query("DELETE FROM work_tags WHERE work_id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            sqlx::query("DELETE FROM favorites WHERE work_id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            sqlx::query("DELETE FROM playlist_tracks WHERE track_id IN (SELECT id FROM tracks WHERE work_id = ?)")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
-            // Finally delete the work
-            sqlx::query("DELETE FROM works WHERE id = ?")
-                .bind(work_id)
-                .execute(pool)
-                .await
-                .ok();
-            
+            delete_work_records(pool, work_id).await;
             removed_count += 1;
             eprintln!("Removed orphaned work: {} ({})", work_id, dir_path);
         }
@@ -219,6 +323,53 @@ pub async fn cleanup_orphaned_works(
     Ok(removed_count)
 }
 
+/// Remove a work and everything that hangs off it from the database.
+pub async fn delete_work_records(pool: &SqlitePool, work_id: i64) {
+    // Delete related records first (foreign key constraints)
+    sqlx::query("DELETE FROM playlist_tracks WHERE track_id IN (SELECT id FROM tracks WHERE work_id = ?)")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query("DELETE FROM tracks WHERE work_id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query("DELETE FROM work_voice_actors WHERE work_id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query("DELETE FROM work_circles WHERE work_id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query("DELETE FROM work_tags WHERE work_id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query("DELETE FROM favorites WHERE work_id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+
+    // Finally delete the work
+    sqlx::query("DELETE FROM works WHERE id = ?")
+        .bind(work_id)
+        .execute(pool)
+        .await
+        .ok();
+}
+
 fn contains_audio_files(path: &Path) -> bool {
     if let Ok(entries) = fs::read_dir(path) {
         for entry in entries.flatten() {
@@ -325,51 +476,477 @@ fn extract_embedded_cover(dir_path: &Path) -> Option<String> {
     None
 }
 
-async fn scan_tracks(work_id: i64, path: &Path, pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    for entry in WalkDir::new(path)
+/// Copy the best image in an archive out next to it, as `<archive>.cover.<ext>`, since
+/// covers are shown from plain files.
+fn extract_archive_cover(archive_path: &Path) -> Option<String> {
+    let priority_names = ["cover", "folder", "front", "main", "jacket"];
+    let images: Vec<_> = archive::list(archive_path)
+        .ok()?
+        .into_iter()
+        .filter_map(|entry| {
+            let name = Path::new(&entry.name);
+            let ext = name.extension()?.to_string_lossy().to_lowercase();
+            let stem = name.file_stem()?.to_string_lossy().to_lowercase();
+            ["jpg", "jpeg", "png", "webp", "bmp"].contains(&ext.as_str()).then_some((entry, stem, ext))
+        })
+        .collect();
+    let (entry, _, ext) = images
+        .iter()
+        .find(|(_, stem, _)| priority_names.contains(&stem.as_str()))
+        .or_else(|| images.iter().max_by_key(|(entry, _, _)| entry.size))?;
+
+    let save_path = PathBuf::from(format!("{}.cover.{}", archive_path.display(), ext));
+    if !save_path.is_file() {
+        let mut reader = archive::open(archive_path, &entry.name).ok()?;
+        let copied = fs::File::create(&save_path).and_then(|mut file| io::copy(&mut reader, &mut file));
+        if let Err(e) = copied {
+            eprintln!("Failed to extract cover from {:?}: {}", archive_path, e);
+            let _ = fs::remove_file(&save_path);
+            return None;
+        }
+        println!("Extracted cover to: {:?}", save_path);
+    }
+    Some(save_path.to_string_lossy().to_string())
+}
+
+/// A track row as the last scan left it.
+struct KnownTrack {
+    id: i64,
+    size: Option<i64>,
+    mtime: Option<i64>,
+    hash: Option<String>,
+    loudness: Option<Loudness>,
+    silence: Option<Silence>,
+}
+
+impl KnownTrack {
+    /// Whether the file on disk is still the one that was probed.
+    fn matches(&self, fingerprint: &Fingerprint) -> bool {
+        if self.size != Some(fingerprint.size) {
+            return false;
+        }
+        // A touched or copied file has a new mtime but the same content.
+        self.mtime == Some(fingerprint.mtime)
+            || (self.hash.is_some() && self.hash == fingerprint.hash)
+    }
+}
+
+/// Size and modification time of a file, and its content hash when the scan hashes.
+#[derive(Debug, Clone, PartialEq)]
+struct Fingerprint {
+    size: i64,
+    mtime: i64,
+    hash: Option<String>,
+}
+
+impl Fingerprint {
+    fn read(p: &Path) -> io::Result<Self> {
+        let meta = p.metadata()?;
+        let mtime = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        Ok(Self {
+            size: meta.len() as i64,
+            mtime,
+            hash: None,
+        })
+    }
+
+    /// Hash the file on a blocking thread. A file that can't be read stays unhashed, and
+    /// one already hashed (archive entries come with their CRC) is left as it is.
+    async fn hash(&mut self, p: &Path) {
+        if self.hash.is_some() {
+            return;
+        }
+        let path = p.to_path_buf();
+        match tokio::task::spawn_blocking(move || content_hash(&path)).await {
+            Ok(Ok(hash)) => self.hash = Some(hash),
+            Ok(Err(e)) => eprintln!("Hashing failed for {:?}: {}", p, e),
+            Err(e) => eprintln!("Hashing panicked for {:?}: {}", p, e),
+        }
+    }
+}
+
+/// Fingerprint of an archive entry. The archive's checksum stands in for the hash; it
+/// can't be mistaken for one of a plain file.
+fn entry_fingerprint(entry: &archive::ArchiveEntry) -> Fingerprint {
+    Fingerprint {
+        size: entry.size as i64,
+        mtime: entry.mtime,
+        hash: entry.crc32.map(|crc| format!("crc32:{:08x}", crc)),
+    }
+}
+
+pub fn is_audio_file(p: &Path) -> bool {
+    p.extension().is_some_and(|ext| {
+        let ext_str = ext.to_string_lossy().to_lowercase();
+        ["mp3", "wav", "flac", "m4a", "mp4", "ogg"].contains(&ext_str.as_str())
+    })
+}
+
+/// The audio files of a work with their fingerprints: the files under its folder, or
+/// the entries of its archive.
+fn work_files(path: &Path) -> io::Result<Vec<(PathBuf, io::Result<Fingerprint>)>> {
+    if archive::is_archive(path) {
+        return Ok(archive::list(path)?
+            .iter()
+            .map(|entry| (archive::entry_path(path, &entry.name), Ok(entry_fingerprint(entry))))
+            .filter(|(p, _)| is_audio_file(p))
+            .collect());
+    }
+    Ok(WalkDir::new(path)
         .into_iter()
         .filter_map(|e| e.ok())
-    {
-        let p = entry.path();
-        if p.is_file() {
-            if let Some(ext) = p.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if ["mp3", "wav", "flac", "m4a", "mp4", "ogg"].contains(&ext_str.as_str()) {
-                    let title = p
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string();
-                    let path_str = p.to_string_lossy().to_string();
-
-                    // Extract Duration using Lofty
-                    let duration_sec = match read_from_path(p) {
-                        Ok(tagged_file) => tagged_file.properties().duration().as_secs() as i64,
-                        Err(e) => {
-                            eprintln!("Lofty Error on {}: {}", title, e);
-                            0
-                        }
-                    };
+        .map(|entry| entry.into_path())
+        .filter(|p| p.is_file() && is_audio_file(p))
+        .map(|p| {
+            let fingerprint = Fingerprint::read(&p);
+            (p, fingerprint)
+        })
+        .collect())
+}
 
-                    // Log duration for debugging
-                    if duration_sec > 0 {
-                        println!("Scanned {}: {}s", title, duration_sec);
-                    } else {
-                        println!("Warning: Could not determine duration for {}", title);
-                    }
+fn content_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-                    sqlx::query(
-                        "INSERT INTO tracks (work_id, title, path, duration_sec) VALUES (?, ?, ?, ?)"
-                    )
-                    .bind(work_id)
-                    .bind(title)
-                    .bind(path_str)
-                    .bind(duration_sec)
-                    .execute(pool)
-                    .await?;
-                }
+/// What a rescan did to the tracks of one work.
+#[derive(Debug, Default)]
+struct TrackChanges {
+    added: usize,
+    updated: usize,
+    moved: usize,
+    removed: usize,
+}
+
+impl TrackChanges {
+    fn any(&self) -> bool {
+        self.added + self.updated + self.moved + self.removed > 0
+    }
+}
+
+async fn load_known_tracks(work_id: i64, pool: &SqlitePool) -> Result<HashMap<String, KnownTrack>, sqlx::Error> {
+    use sqlx::Row;
+    let rows = sqlx::query(
+        "SELECT id, path, file_size, file_mtime, content_hash, loudness_lufs, true_peak, leading_silence_sec, trailing_silence_sec
+         FROM tracks WHERE work_id = ?"
+    )
+    .bind(work_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let true_peak: Option<f64> = row.get(6);
+            let trailing: Option<f64> = row.get(8);
+            let known = KnownTrack {
+                id: row.get(0),
+                size: row.get(2),
+                mtime: row.get(3),
+                hash: row.get(4),
+                loudness: row
+                    .get::<Option<f64>, _>(5)
+                    .map(|integrated_lufs| Loudness { integrated_lufs, true_peak: true_peak.unwrap_or(0.0) }),
+                silence: row
+                    .get::<Option<f64>, _>(7)
+                    .map(|leading_secs| Silence { leading_secs, trailing_secs: trailing.unwrap_or(0.0) }),
+            };
+            (row.get(1), known)
+        })
+        .collect())
+}
+
+/// Measure whatever of loudness and silence isn't known yet on a blocking thread. A
+/// track that needs both is decoded once for the two of them.
+async fn measure_track(
+    p: &Path,
+    loudness: Option<Loudness>,
+    silence: Option<Silence>,
+) -> (Option<Loudness>, Option<Silence>) {
+    if loudness.is_some() && silence.is_some() {
+        return (loudness, silence);
+    }
+    let path_str = p.to_string_lossy().to_string();
+    let measured = tokio::task::spawn_blocking(move || match (loudness, silence) {
+        (None, None) => analyze_loudness_and_silence(&path_str).map(|(l, s)| (l, Some(s))),
+        (None, known) => analyze_loudness(&path_str).map(|l| (l, known)),
+        (known, _) => detect_silence(&path_str).map(|s| (known, Some(s))),
+    })
+    .await;
+    match measured {
+        Ok(Ok(measured)) => measured,
+        Ok(Err(e)) => {
+            eprintln!("Track analysis failed for {:?}: {}", p, e);
+            (loudness, silence)
+        }
+        Err(e) => {
+            eprintln!("Track analysis panicked for {:?}: {}", p, e);
+            (loudness, silence)
+        }
+    }
+}
+
+/// Read the tags of a file, or of an entry streamed out of its archive.
+fn read_tags(p: &Path) -> lofty::error::Result<TaggedFile> {
+    let path_str = p.to_string_lossy();
+    if archive::split_entry_path(&path_str).is_none() {
+        return read_from_path(p);
+    }
+    let entry = archive::open_entry(&path_str)?;
+    Probe::new(BufReader::new(entry)).guess_file_type()?.read()
+}
+
+/// Everything the scanner reads out of an audio file.
+struct ProbedTrack {
+    title: String,
+    duration_sec: i64,
+    loudness: Option<Loudness>,
+    silence: Option<Silence>,
+}
+
+/// Read the tags and duration of a file and measure it. `measured` carries loudness and
+/// silence that are known to still hold, since measuring decodes the whole track.
+async fn probe_track(job: &ScanJob, p: &Path, measured: Option<&KnownTrack>) -> ProbedTrack {
+    let title = p
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // Extract Duration (and any ReplayGain tags) using Lofty
+    let path = p.to_path_buf();
+    let tags = tokio::task::spawn_blocking(move || {
+        read_tags(&path).map(|tagged_file| {
+            let replaygain = tagged_file.tags().iter().find_map(|tag| {
+                let gain = tag.get_string(&ItemKey::ReplayGainTrackGain)?;
+                Loudness::from_replaygain(gain, tag.get_string(&ItemKey::ReplayGainTrackPeak))
+            });
+            (tagged_file.properties().duration().as_secs() as i64, replaygain)
+        })
+    })
+    .await;
+    let (duration_sec, tagged_loudness) = match tags {
+        Ok(Ok(tags)) => tags,
+        Ok(Err(e)) => {
+            eprintln!("Lofty Error on {}: {}", title, e);
+            job.update(|progress| progress.files_failed += 1);
+            (0, None)
+        }
+        Err(e) => {
+            eprintln!("Lofty panicked on {}: {}", title, e);
+            job.update(|progress| progress.files_failed += 1);
+            (0, None)
+        }
+    };
+
+    // Earlier measurements first, then ReplayGain tags; only what is still missing is decoded.
+    let (loudness, silence) = measure_track(
+        p,
+        measured.and_then(|k| k.loudness).or(tagged_loudness),
+        measured.and_then(|k| k.silence),
+    )
+    .await;
+
+    // Log duration for debugging
+    if duration_sec > 0 {
+        println!("Scanned {}: {}s", title, duration_sec);
+    } else {
+        println!("Warning: Could not determine duration for {}", title);
+    }
+
+    ProbedTrack { title, duration_sec, loudness, silence }
+}
+
+/// Bring the tracks of a work in line with its folder. Rows are updated in place so
+/// playlists, history and resume positions that point at them survive.
+async fn scan_tracks(
+    job: &ScanJob,
+    work_id: i64,
+    files: Vec<(PathBuf, io::Result<Fingerprint>)>,
+    pool: &SqlitePool,
+    hash_files: bool,
+) -> Result<TrackChanges, sqlx::Error> {
+    let mut known = load_known_tracks(work_id, pool).await?;
+    let mut changes = TrackChanges::default();
+    // Files without a row of their own, looked at once the gone rows are known.
+    let mut new_files = Vec::new();
+
+    for (p, fingerprint) in files {
+        // Stop before anything is removed; the rest is picked up by the next scan.
+        if job.cancelled() {
+            return Ok(changes);
+        }
+        let p = p.as_path();
+        let path_str = p.to_string_lossy().to_string();
+        job.update(|progress| {
+            progress.tracks_found += 1;
+            progress.current_path = Some(path_str.clone());
+        });
+        let mut fingerprint = match fingerprint {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                eprintln!("Could not stat {:?}: {}", p, e);
+                job.update(|progress| progress.files_failed += 1);
+                // Leave the row alone rather than drop a track that may come back.
+                known.remove(&path_str);
+                continue;
             }
+        };
+
+        let Some(track) = known.remove(&path_str) else {
+            new_files.push((p.to_path_buf(), fingerprint));
+            continue;
+        };
+        if track.matches(&fingerprint) {
+            continue;
+        }
+        if hash_files && track.size == Some(fingerprint.size) && track.hash.is_some() {
+            fingerprint.hash(p).await;
+            if track.matches(&fingerprint) {
+                update_fingerprint(pool, track.id, &fingerprint).await?;
+                continue;
+            }
+        }
+        // Rows from before fingerprints were recorded were measured from this same file.
+        let measured = track.size.is_none().then_some(&track);
+        let probed = probe_track(job, p, measured).await;
+        if hash_files && fingerprint.hash.is_none() {
+            fingerprint.hash(p).await;
         }
+        update_track(pool, track.id, &probed, &fingerprint).await?;
+        changes.updated += 1;
     }
+
+    for (p, mut fingerprint) in new_files {
+        if job.cancelled() {
+            return Ok(changes);
+        }
+        let path_str = p.to_string_lossy().to_string();
+        if hash_files {
+            fingerprint.hash(&p).await;
+        }
+        // A gone file with the same content is this one, renamed or moved within the work.
+        let moved = fingerprint.hash.as_ref().and_then(|hash| {
+            known
+                .iter()
+                .find(|(_, k)| k.hash.as_ref() == Some(hash) && k.size == Some(fingerprint.size))
+                .map(|(old_path, _)| old_path.clone())
+        });
+        if let Some(track) = moved.and_then(|old_path| known.remove(&old_path)) {
+            let title = p.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            sqlx::query("UPDATE tracks SET title = ?, path = ? WHERE id = ?")
+                .bind(title)
+                .bind(&path_str)
+                .bind(track.id)
+                .execute(pool)
+                .await?;
+            update_fingerprint(pool, track.id, &fingerprint).await?;
+            changes.moved += 1;
+            continue;
+        }
+
+        let probed = probe_track(job, &p, None).await;
+        sqlx::query(
+            "INSERT INTO tracks (work_id, title, path, duration_sec, loudness_lufs, true_peak, leading_silence_sec, trailing_silence_sec, file_size, file_mtime, content_hash)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(work_id)
+        .bind(probed.title)
+        .bind(path_str)
+        .bind(probed.duration_sec)
+        .bind(probed.loudness.map(|l| l.integrated_lufs))
+        .bind(probed.loudness.map(|l| l.true_peak))
+        .bind(probed.silence.map(|s| s.leading_secs))
+        .bind(probed.silence.map(|s| s.trailing_secs))
+        .bind(fingerprint.size)
+        .bind(fingerprint.mtime)
+        .bind(fingerprint.hash)
+        .execute(pool)
+        .await?;
+        changes.added += 1;
+    }
+
+    // Whatever is left is gone from disk.
+    for track in known.values() {
+        sqlx::query("DELETE FROM playlist_tracks WHERE track_id = ?")
+            .bind(track.id)
+            .execute(pool)
+            .await?;
+        sqlx::query("DELETE FROM tracks WHERE id = ?")
+            .bind(track.id)
+            .execute(pool)
+            .await?;
+        changes.removed += 1;
+    }
+    Ok(changes)
+}
+
+async fn update_track(
+    pool: &SqlitePool,
+    id: i64,
+    probed: &ProbedTrack,
+    fingerprint: &Fingerprint,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE tracks SET title = ?, duration_sec = ?, loudness_lufs = ?, true_peak = ?, leading_silence_sec = ?, trailing_silence_sec = ?
+         WHERE id = ?"
+    )
+    .bind(&probed.title)
+    .bind(probed.duration_sec)
+    .bind(probed.loudness.map(|l| l.integrated_lufs))
+    .bind(probed.loudness.map(|l| l.true_peak))
+    .bind(probed.silence.map(|s| s.leading_secs))
+    .bind(probed.silence.map(|s| s.trailing_secs))
+    .bind(id)
+    .execute(pool)
+    .await?;
+    update_fingerprint(pool, id, fingerprint).await
+}
+
+async fn update_fingerprint(pool: &SqlitePool, id: i64, fingerprint: &Fingerprint) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE tracks SET file_size = ?, file_mtime = ?, content_hash = ? WHERE id = ?")
+        .bind(fingerprint.size)
+        .bind(fingerprint.mtime)
+        .bind(fingerprint.hash.as_deref())
+        .bind(id)
+        .execute(pool)
+        .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(size: i64, mtime: i64, hash: Option<&str>) -> KnownTrack {
+        KnownTrack {
+            id: 1,
+            size: Some(size),
+            mtime: Some(mtime),
+            hash: hash.map(str::to_string),
+            loudness: None,
+            silence: None,
+        }
+    }
+
+    fn fingerprint(size: i64, mtime: i64, hash: Option<&str>) -> Fingerprint {
+        Fingerprint { size, mtime, hash: hash.map(str::to_string) }
+    }
+
+    #[test]
+    fn unchanged_files_are_recognised() {
+        assert!(known(100, 5, None).matches(&fingerprint(100, 5, None)));
+        assert!(!known(100, 5, None).matches(&fingerprint(100, 6, None)));
+        assert!(!known(100, 5, None).matches(&fingerprint(101, 5, None)));
+        // Touched, but the content is the same.
+        assert!(known(100, 5, Some("ab")).matches(&fingerprint(100, 6, Some("ab"))));
+        assert!(!known(100, 5, Some("ab")).matches(&fingerprint(100, 6, Some("cd"))));
+        // Rows from before fingerprints always count as changed.
+        let legacy = KnownTrack { size: None, mtime: None, ..known(0, 0, None) };
+        assert!(!legacy.matches(&fingerprint(100, 5, None)));
+    }
+}
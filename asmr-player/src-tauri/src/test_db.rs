@@ -0,0 +1,11 @@
+//! A database for tests: in memory, with every migration applied.
+
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+
+/// An empty library. One connection, as each in-memory connection is a database of its own.
+pub async fn library() -> SqlitePool {
+    let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    pool
+}
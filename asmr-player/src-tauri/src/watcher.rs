@@ -0,0 +1,399 @@
+use crate::archive;
+use crate::roots::{self, LibraryRoot};
+use crate::scan_job::ScanJob;
+use crate::scanner::{self, SCAN_LOCK};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{mpsc, Notify};
+
+/// How long a file has to be left alone before its events are passed on.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long the library has to be quiet before changes are acted on, so a work that
+/// is still being copied in is only scanned when it is complete.
+const SETTLE: Duration = Duration::from_secs(5);
+
+/// How often roots that couldn't be watched, such as a drive that isn't plugged in,
+/// are tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Raised when library roots are added, removed, enabled or disabled.
+static ROOTS_CHANGED: Notify = Notify::const_new();
+
+/// Payload of the `library-changed` event.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LibraryChanged {
+    /// Works whose tracks were rescanned.
+    pub updated: Vec<i64>,
+    /// Works whose folder disappeared.
+    pub missing: Vec<i64>,
+    /// Works found in new folders, new or already known.
+    pub found: usize,
+}
+
+/// Paths that changed under the library roots while it settled. A path is only in one
+/// of the sets, the one for what last happened to it.
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    /// New folders and archives.
+    created: BTreeSet<PathBuf>,
+    /// Audio files that were added or written to.
+    modified: BTreeSet<PathBuf>,
+    removed: BTreeSet<PathBuf>,
+    /// Events were dropped, so every root has to be looked at again.
+    rescan_roots: bool,
+}
+
+impl Changes {
+    fn add(&mut self, result: DebounceEventResult) {
+        match result {
+            Ok(events) => events.iter().for_each(|event| self.record(event)),
+            Err(errors) => errors.iter().for_each(|e| eprintln!("[Watcher] {}", e)),
+        }
+    }
+
+    fn record(&mut self, event: &DebouncedEvent) {
+        if event.need_rescan() {
+            self.rescan_roots = true;
+            return;
+        }
+        match (event.kind, event.paths.as_slice()) {
+            (EventKind::Create(_), paths) | (EventKind::Modify(ModifyKind::Name(RenameMode::To)), paths) => {
+                paths.iter().for_each(|p| self.appeared(p));
+            }
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) => {
+                self.gone(from);
+                self.appeared(to);
+            }
+            (EventKind::Remove(_), paths) | (EventKind::Modify(ModifyKind::Name(RenameMode::From)), paths) => {
+                paths.iter().for_each(|p| self.gone(p));
+            }
+            // The platform couldn't tell which side of the rename this is.
+            (EventKind::Modify(ModifyKind::Name(_)), paths) => {
+                for path in paths {
+                    if path.exists() {
+                        self.appeared(path);
+                    } else {
+                        self.gone(path);
+                    }
+                }
+            }
+            (EventKind::Modify(_), paths) => paths.iter().for_each(|p| self.appeared(p)),
+            _ => {}
+        }
+    }
+
+    /// Only folders, archives and audio files count; anything else, such as a cover
+    /// the scanner extracts, doesn't change what a work holds.
+    fn appeared(&mut self, path: &Path) {
+        let set = if path.is_dir() || archive::is_archive(path) {
+            &mut self.created
+        } else if scanner::is_audio_file(path) {
+            &mut self.modified
+        } else {
+            return;
+        };
+        self.removed.remove(path);
+        set.insert(path.to_path_buf());
+    }
+
+    fn gone(&mut self, path: &Path) {
+        self.created.remove(path);
+        self.modified.remove(path);
+        self.removed.insert(path.to_path_buf());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.modified.is_empty() && self.removed.is_empty() && !self.rescan_roots
+    }
+}
+
+/// What to do about a set of changes.
+#[derive(Debug, Default, PartialEq)]
+struct Plan {
+    rescan: BTreeMap<i64, PathBuf>,
+    missing: BTreeSet<i64>,
+    // Folders outside any work to look for works in, none inside another.
+    discover: Vec<PathBuf>,
+}
+
+impl Plan {
+    /// Works never nest, so a path belongs to at most one of `works`.
+    fn new(changes: &Changes, works: &[(i64, PathBuf)], roots: &[PathBuf]) -> Self {
+        let work_of = |path: &Path| works.iter().find(|(_, dir)| path.starts_with(dir));
+        let mut plan = Plan::default();
+        if changes.rescan_roots {
+            plan.discover.extend(roots.iter().cloned());
+        }
+
+        for path in &changes.created {
+            match work_of(path) {
+                Some((id, dir)) => {
+                    plan.rescan.insert(*id, dir.clone());
+                }
+                None => plan.discover.push(path.clone()),
+            }
+        }
+        for path in &changes.modified {
+            match work_of(path) {
+                // Also catches a track overwritten in place.
+                Some((id, dir)) => {
+                    plan.rescan.insert(*id, dir.clone());
+                }
+                // A track dropped into a folder that isn't a work yet. Loose tracks
+                // right in a root don't make a work.
+                None => {
+                    if let Some(parent) = path.parent().filter(|p| !roots.iter().any(|r| r == p)) {
+                        plan.discover.push(parent.to_path_buf());
+                    }
+                }
+            }
+        }
+        for path in &changes.removed {
+            match work_of(path) {
+                Some((id, dir)) if dir != path => {
+                    plan.rescan.insert(*id, dir.clone());
+                }
+                // The work itself, or a folder above works that was moved or removed
+                // with them; only the outermost path is reported then.
+                _ => plan
+                    .missing
+                    .extend(works.iter().filter(|(_, dir)| dir.starts_with(path)).map(|(id, _)| *id)),
+            }
+        }
+        for id in &plan.missing {
+            plan.rescan.remove(id);
+        }
+        // Subfolders are walked with the folder they are in.
+        plan.discover.sort();
+        plan.discover.dedup_by(|path, kept| path.starts_with(kept));
+        plan
+    }
+}
+
+/// Tell the watcher the library roots changed, so it watches the enabled ones.
+pub fn roots_changed() {
+    ROOTS_CHANGED.notify_one();
+}
+
+/// Roots to watch: the enabled ones, leaving out those inside another, which are
+/// watched with it.
+fn outermost(roots: &[LibraryRoot]) -> Vec<PathBuf> {
+    let paths: Vec<PathBuf> = roots.iter().map(|r| PathBuf::from(&r.path)).collect();
+    paths
+        .iter()
+        .filter(|p| !paths.iter().any(|other| other != *p && p.starts_with(other)))
+        .cloned()
+        .collect()
+}
+
+/// Watch the enabled roots, and stop watching those that were removed or disabled.
+/// `watched` ends up holding the roots that are being watched.
+async fn watch_roots(
+    debouncer: &mut Debouncer<RecommendedWatcher, RecommendedCache>,
+    pool: &SqlitePool,
+    watched: &mut Vec<PathBuf>,
+) {
+    let roots = match roots::enabled_roots(pool).await {
+        Ok(roots) => outermost(&roots),
+        Err(e) => {
+            eprintln!("[Watcher] Failed to load library folders: {}", e);
+            return;
+        }
+    };
+    for path in watched.iter().filter(|p| !roots.contains(p)) {
+        if let Err(e) = debouncer.unwatch(path) {
+            eprintln!("[Watcher] Failed to stop watching {}: {}", path.display(), e);
+        }
+    }
+    watched.retain(|p| roots.contains(p));
+    for path in roots {
+        if watched.contains(&path) {
+            continue;
+        }
+        match debouncer.watch(&path, RecursiveMode::Recursive) {
+            Ok(()) => watched.push(path),
+            Err(e) => eprintln!("[Watcher] Can't watch {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Watch the library folders and keep the database in step with them.
+pub fn start(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        // The pool is managed once migrations finish.
+        let pool = loop {
+            if let Some(pool) = app.try_state::<SqlitePool>() {
+                break pool.inner().clone();
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        };
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let mut debouncer = match new_debouncer(DEBOUNCE, None, move |result: DebounceEventResult| {
+            let _ = tx.send(result);
+        }) {
+            Ok(debouncer) => debouncer,
+            Err(e) => {
+                eprintln!("[Watcher] Can't watch the library: {}", e);
+                return;
+            }
+        };
+        let mut watched = Vec::new();
+        watch_roots(&mut debouncer, &pool, &mut watched).await;
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+
+        loop {
+            let first = tokio::select! {
+                _ = ROOTS_CHANGED.notified() => {
+                    watch_roots(&mut debouncer, &pool, &mut watched).await;
+                    continue;
+                }
+                _ = retry.tick() => {
+                    watch_roots(&mut debouncer, &pool, &mut watched).await;
+                    continue;
+                }
+                result = events.recv() => match result {
+                    Some(result) => result,
+                    None => return,
+                },
+            };
+            let mut changes = Changes::default();
+            changes.add(first);
+            while let Ok(Some(result)) = tokio::time::timeout(SETTLE, events.recv()).await {
+                changes.add(result);
+            }
+            if changes.is_empty() {
+                continue;
+            }
+
+            match apply(&pool, &changes).await {
+                Ok(changed) => {
+                    println!("[Watcher] Library changed: {:?}", changed);
+                    let _ = app.emit("library-changed", changed);
+                }
+                Err(e) => eprintln!("[Watcher] Failed to update the library: {}", e),
+            }
+        }
+    });
+}
+
+async fn apply(pool: &SqlitePool, changes: &Changes) -> Result<LibraryChanged, String> {
+    let _scanning = SCAN_LOCK.lock().await;
+    let works: Vec<(i64, String)> = sqlx::query_as("SELECT id, dir_path FROM works")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let works: Vec<(i64, PathBuf)> = works.into_iter().map(|(id, dir)| (id, PathBuf::from(dir))).collect();
+    let roots: Vec<PathBuf> = roots::enabled_roots(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| PathBuf::from(r.path))
+        .collect();
+    let plan = Plan::new(changes, &works, &roots);
+
+    let job = ScanJob::detached();
+    let mut changed = LibraryChanged::default();
+    for &id in &plan.missing {
+        sqlx::query("UPDATE works SET missing = 1 WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        changed.missing.push(id);
+    }
+    for (&id, dir) in &plan.rescan {
+        if let Err(e) = scanner::rescan_work(&job, pool, id, dir, false).await {
+            eprintln!("[Watcher] {}", e);
+        }
+        changed.updated.push(id);
+    }
+    for dir in &plan.discover {
+        // A root is looked through, while any other folder may be a work itself.
+        let include_root = !roots.contains(dir);
+        let outcome = scanner::scan_tree(&job, pool, dir, include_root, false).await?;
+        changed.found += outcome.works;
+    }
+    roots::count_works(pool).await.map_err(|e| e.to_string())?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> BTreeSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn changes_map_onto_works() {
+        let works = vec![
+            (1, PathBuf::from("/lib/RJ01")),
+            (2, PathBuf::from("/lib/RJ02")),
+            (5, PathBuf::from("/lib/old/RJ05")),
+            (6, PathBuf::from("/lib/old/2023/RJ06.zip")),
+            (7, PathBuf::from("/lib/older/RJ07")),
+        ];
+        let changes = Changes {
+            // A new work, copied in with its subfolders.
+            created: paths(&["/lib/RJ03", "/lib/RJ03/wav"]),
+            // A track overwritten in place, and one dropped into a plain folder.
+            modified: paths(&["/lib/RJ01/mp3/01.mp3", "/lib/misc/RJ04/01.mp3", "/lib/loose.mp3"]),
+            // A work, and a folder of works moved elsewhere in one go.
+            removed: paths(&["/lib/RJ02", "/lib/old"]),
+            rescan_roots: false,
+        };
+        let plan = Plan::new(&changes, &works, &[PathBuf::from("/lib")]);
+        assert_eq!(plan.rescan.keys().copied().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(plan.missing, BTreeSet::from([2, 5, 6]));
+        assert_eq!(plan.discover, vec![PathBuf::from("/lib/RJ03"), PathBuf::from("/lib/misc/RJ04")]);
+    }
+
+    #[test]
+    fn a_removed_subfolder_rescans_its_work() {
+        let works = vec![(1, PathBuf::from("/lib/RJ01"))];
+        let changes = Changes { removed: paths(&["/lib/RJ01/bonus"]), ..Changes::default() };
+        let plan = Plan::new(&changes, &works, &[PathBuf::from("/lib")]);
+        assert_eq!(plan.rescan.keys().copied().collect::<Vec<_>>(), vec![1]);
+        assert!(plan.missing.is_empty() && plan.discover.is_empty());
+    }
+
+    #[test]
+    fn dropped_events_look_through_every_root() {
+        let changes = Changes { created: paths(&["/lib/a/RJ05"]), rescan_roots: true, ..Changes::default() };
+        let plan = Plan::new(&changes, &[], &[PathBuf::from("/lib/a"), PathBuf::from("/other")]);
+        assert_eq!(plan.discover, vec![PathBuf::from("/lib/a"), PathBuf::from("/other")]);
+    }
+
+    #[test]
+    fn the_last_event_for_a_path_wins() {
+        let dir = std::env::temp_dir().join(format!("watcher-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let track = dir.join("01.mp3");
+        std::fs::write(&track, b"").unwrap();
+        let event = |kind, path: &Path| DebouncedEvent::new(notify::Event::new(kind).add_path(path.to_path_buf()), std::time::Instant::now());
+
+        let mut changes = Changes::default();
+        changes.record(&event(EventKind::Remove(notify::event::RemoveKind::File), &track));
+        // Saved by writing a new file over the old one.
+        changes.record(&event(EventKind::Create(notify::event::CreateKind::File), &track));
+        changes.record(&event(EventKind::Create(notify::event::CreateKind::Folder), &dir));
+        changes.record(&event(EventKind::Modify(ModifyKind::Any), &dir.join("notes.txt")));
+        assert_eq!(changes.modified, BTreeSet::from([track.clone()]));
+        assert_eq!(changes.created, BTreeSet::from([dir.clone()]));
+        assert!(changes.removed.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        changes.record(&event(EventKind::Remove(notify::event::RemoveKind::Folder), &dir));
+        assert!(changes.created.is_empty());
+        assert_eq!(changes.removed, BTreeSet::from([dir]));
+    }
+}
@@ -1,180 +1,42 @@
 import { Play, Pause, SkipBack, SkipForward, Repeat, Repeat1, Shuffle, Volume2, Moon } from 'lucide-react';
-import { usePlayerStore } from '../hooks/usePlayerStore';
+import { syncPlayerStore, usePlayerStore } from '../hooks/usePlayerStore';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
-import { useEffect, useRef, useState, useCallback } from 'react';
+import { useEffect, useRef, useState } from 'react';
 import { convertFileSrc } from '@tauri-apps/api/core';
 
-// Audio playback modes
-type PlaybackMode = 'rust' | 'web' | null;
+type SleepTimerMode =
+    | { kind: 'duration'; seconds: number }
+    | { kind: 'end_of_track' }
+    | { kind: 'end_of_work' };
+
+interface SleepTimerStatus {
+    mode: SleepTimerMode;
+    remaining_secs: number | null;
+}
 
 export function PlayerBar() {
     const {
-        isPlaying, currentTrack, setIsPlaying, playNext, playPrev,
-        shuffle, repeatMode, toggleShuffle, cycleRepeatMode
+        isPlaying, currentTrack, currentTime, duration, volume, togglePlay, seek, setVolume,
+        playNext, playPrev, shuffle, repeatMode, toggleShuffle, cycleRepeatMode
     } = usePlayerStore();
     const canvasRef = useRef<HTMLCanvasElement>(null);
-    const audioRef = useRef<HTMLAudioElement | null>(null);
-    const audioContextRef = useRef<AudioContext | null>(null);
-    const analyserRef = useRef<AnalyserNode | null>(null);
-    const sourceNodeRef = useRef<MediaElementAudioSourceNode | null>(null);
 
-    const [volume, setVolume] = useState(1.0);
-    const [currentTime, setCurrentTime] = useState(0);
-    const [duration, setDuration] = useState(0);
-    const [isSeeking, setIsSeeking] = useState(false);
-    const [playbackMode, setPlaybackMode] = useState<PlaybackMode>(null);
+    // Where the seek bar is being dragged to, until it is let go
+    const [seekTarget, setSeekTarget] = useState<number | null>(null);
 
     // Sleep Timer State
-    const [sleepTimerSeconds, setSleepTimerSeconds] = useState<number | null>(null);
+    const [sleepTimer, setSleepTimerStatus] = useState<SleepTimerStatus | null>(null);
     const [showSleepMenu, setShowSleepMenu] = useState(false);
 
-    // Initialize Web Audio Context
-    const initAudioContext = useCallback(() => {
-        if (!audioRef.current) return;
-
-        if (!audioContextRef.current) {
-            const AudioContextClass = window.AudioContext || (window as any).webkitAudioContext;
-            audioContextRef.current = new AudioContextClass();
-        }
-
-        const ctx = audioContextRef.current;
-
-        // Create source node only once
-        if (!sourceNodeRef.current) {
-            sourceNodeRef.current = ctx.createMediaElementSource(audioRef.current);
-            analyserRef.current = ctx.createAnalyser();
-            analyserRef.current.fftSize = 256; // 128 bins
-
-            // Connect: Source -> Analyser -> Destination
-            sourceNodeRef.current.connect(analyserRef.current);
-            analyserRef.current.connect(ctx.destination);
-        }
-
-        // Resume context if suspended (policy)
-        if (ctx.state === 'suspended') {
-            ctx.resume();
-        }
-    }, []);
-
-    // Initialize HTML Audio element
-    useEffect(() => {
-        if (!audioRef.current) {
-            audioRef.current = new Audio();
-            // Allow crossOrigin for Web Audio if needed (for local files usually not needed but good practice)
-            audioRef.current.crossOrigin = "anonymous";
-
-            audioRef.current.addEventListener('timeupdate', () => {
-                if (audioRef.current && !isSeeking) {
-                    setCurrentTime(audioRef.current.currentTime);
-                }
-            });
-            audioRef.current.addEventListener('loadedmetadata', () => {
-                if (audioRef.current) {
-                    setDuration(audioRef.current.duration);
-                }
-            });
-            audioRef.current.addEventListener('ended', () => {
-                playNext();
-            });
-            audioRef.current.addEventListener('error', (e) => {
-                console.error('[Web Audio] Playback error:', e);
-            });
-
-            // Initialize AudioContext when audio element is ready
-            // Note: browser policy requires user interaction before resume, but we can setup
-            initAudioContext();
-        }
-        return () => {
-            if (audioRef.current) {
-                audioRef.current.pause();
-                audioRef.current = null;
-            }
-            if (audioContextRef.current) {
-                audioContextRef.current.close();
-                audioContextRef.current = null;
-            }
-        };
-    }, [initAudioContext, playNext]);
-
-    // Sync volume to web audio
-    useEffect(() => {
-        if (audioRef.current) {
-            audioRef.current.volume = volume;
-        }
-    }, [volume]);
-
-    const stopAllPlayback = useCallback(async () => {
-        // Stop Rust backend
-        try {
-            await invoke('pause_track');
-        } catch (e) { /* ignore */ }
-        // Stop Web Audio
-        if (audioRef.current) {
-            audioRef.current.pause();
-        }
-    }, []);
-
-    const playWithWebAudio = useCallback((path: string) => {
-        if (!audioRef.current) {
-            console.error('[Web Audio] audioRef is null!');
-            return;
-        }
-
-        // Ensure context is running
-        if (audioContextRef.current && audioContextRef.current.state === 'suspended') {
-            audioContextRef.current.resume();
-        }
-
-        const src = convertFileSrc(path);
-        console.log('[Web Audio] Playing:', path);
-        // console.log('[Web Audio] Converted URL:', src); 
-        audioRef.current.src = src;
-        audioRef.current.volume = volume;
-        audioRef.current.play()
-            .then(() => {
-                console.log('[Web Audio] Playback started successfully');
-                setPlaybackMode('web');
-                setIsPlaying(true);
-            })
-            .catch((e) => {
-                console.error('[Web Audio] Failed to play:', e);
-            });
-    }, [volume, setIsPlaying]);
-
-    const togglePlay = async () => {
-        if (isPlaying) {
-            if (playbackMode === 'rust') {
-                await invoke('pause_track');
-            } else if (playbackMode === 'web' && audioRef.current) {
-                audioRef.current.pause();
-            }
-            setIsPlaying(false);
-        } else {
-            if (playbackMode === 'rust') {
-                await invoke('resume_track');
-            } else if (playbackMode === 'web' && audioRef.current) {
-                if (audioContextRef.current && audioContextRef.current.state === 'suspended') {
-                    audioContextRef.current.resume();
-                }
-                audioRef.current.play();
-            }
-            setIsPlaying(true);
-        }
-    };
+    // The backend owns the queue; follow it
+    useEffect(() => syncPlayerStore(), []);
 
     const handleVolumeChange = async (e: React.ChangeEvent<HTMLInputElement>) => {
-        const v = parseFloat(e.target.value);
-        setVolume(v);
-        if (playbackMode === 'rust') {
-            await invoke('set_volume', { volume: v });
-        }
-        if (audioRef.current) {
-            audioRef.current.volume = v;
-        }
+        await setVolume(parseFloat(e.target.value));
     };
 
-    // Visualizer logic (Unified)
+    // Visualizer logic
     useEffect(() => {
         const canvas = canvasRef.current;
         if (!canvas) return;
@@ -184,13 +46,8 @@ export function PlayerBar() {
         let animationId: number;
         let spectrumData: number[] = new Array(100).fill(0);
 
-        // Web Audio Data Buffer
-        let webAudioDataArray: Uint8Array;
-
         const unlisten = listen<number[]>('spectrum-update', (event) => {
-            if (playbackMode === 'rust') {
-                spectrumData = event.payload;
-            }
+            spectrumData = event.payload;
         });
 
         const resize = () => {
@@ -206,35 +63,7 @@ export function PlayerBar() {
             if (!ctx) return;
             ctx.clearRect(0, 0, canvas.width, canvas.height);
 
-            let bars = spectrumData.length;
-            let currentData = spectrumData;
-
-            // Fetch Web Audio Data if in web mode
-            if (playbackMode === 'web' && analyserRef.current) {
-                const analyser = analyserRef.current;
-                const bufferLength = analyser.frequencyBinCount;
-                if (!webAudioDataArray || webAudioDataArray.length !== bufferLength) {
-                    webAudioDataArray = new Uint8Array(bufferLength);
-                }
-                analyser.getByteFrequencyData(webAudioDataArray as any);
-
-                // Normalize 0-255 to 0.0-1.0 like Rust backend
-                // Also take a subset (lower frequencies are usually more interesting)
-                // Or just use the whole thing but downsample?
-                // Let's just create a float array matching the size we want (e.g. 64 bars)
-                const showBars = 64;
-                const step = Math.floor(bufferLength / showBars);
-
-                const webSpectrum: number[] = [];
-                for (let i = 0; i < showBars; i++) {
-                    // Simple downsampling or averaging
-                    const val = webAudioDataArray[i * step];
-                    webSpectrum.push(val / 255.0);
-                }
-                currentData = webSpectrum;
-                bars = showBars;
-            }
-
+            const bars = spectrumData.length;
             const w = canvas.width / bars;
             const gradient = ctx.createLinearGradient(0, canvas.height, 0, 0);
             gradient.addColorStop(0, 'rgba(168, 85, 247, 0.0)');
@@ -243,7 +72,7 @@ export function PlayerBar() {
             ctx.fillStyle = gradient;
 
             for (let i = 0; i < bars; i++) {
-                const val = currentData[i];
+                const val = spectrumData[i];
                 const h = val * 0.8 * canvas.height; // Adjusted gain for visual balance
                 ctx.fillRect(i * w, canvas.height - h, w - 1, h);
             }
@@ -256,75 +85,17 @@ export function PlayerBar() {
             cancelAnimationFrame(animationId);
             unlisten.then(f => f());
         }
-    }, [isPlaying, playbackMode]);
-
-    // Timer for Rust backend progress (only when using rust mode)
-    useEffect(() => {
-        let interval: ReturnType<typeof setInterval>;
-        if (isPlaying && !isSeeking && playbackMode === 'rust') {
-            interval = setInterval(() => {
-                setCurrentTime(prev => {
-                    if (duration > 0 && prev >= duration) {
-                        playNext();
-                        return 0;
-                    }
-                    if (prev >= duration) return prev;
-                    return prev + 1;
-                });
-            }, 1000);
-        }
-        return () => clearInterval(interval);
-    }, [isPlaying, duration, isSeeking, playNext, playbackMode]);
-
-    // Play track when currentTrack changes - Hybrid approach
-    useEffect(() => {
-        if (currentTrack && currentTrack.path) {
-            // Reset state
-            setCurrentTime(0);
-            setDuration(currentTrack.duration || 0);
-            setPlaybackMode(null);
-
-            // Stop any previous playback
-            stopAllPlayback().then(() => {
-                // Check file extension - use Web Audio for m4a/mp4 files
-                const ext = currentTrack.path.split('.').pop()?.toLowerCase();
-                const useWebAudio = ['m4a', 'mp4', 'aac'].includes(ext || '');
-
-                if (useWebAudio) {
-                    console.log('[Audio] Using Web Audio for:', ext);
-                    playWithWebAudio(currentTrack.path);
-                } else {
-                    // Try Rust backend for other formats
-                    invoke('play_track', { path: currentTrack.path })
-                        .then(() => {
-                            console.log('[Rust Audio] Playing successfully');
-                            setPlaybackMode('rust');
-                            setIsPlaying(true);
-                            invoke('set_volume', { volume });
-                        })
-                        .catch((err) => {
-                            console.warn('[Rust Audio] Failed, falling back to Web Audio:', err);
-                            // Fallback to Web Audio API
-                            playWithWebAudio(currentTrack.path);
-                        });
-                }
-            });
-        }
-    }, [currentTrack]);
+    }, []);
 
     const handleSeek = (e: React.ChangeEvent<HTMLInputElement>) => {
-        const val = parseFloat(e.target.value);
-        setIsSeeking(true);
-        setCurrentTime(val);
+        setSeekTarget(parseFloat(e.target.value));
     };
 
     const handleSeekCommit = async () => {
-        if (playbackMode === 'rust') {
-            await invoke('seek_track', { seconds: currentTime });
-        } else if (playbackMode === 'web' && audioRef.current) {
-            audioRef.current.currentTime = currentTime;
+        if (seekTarget !== null) {
+            await seek(seekTarget);
         }
-        setTimeout(() => setIsSeeking(false), 200);
+        setSeekTarget(null);
     };
 
     const formatTime = (sec: number) => {
@@ -333,41 +104,43 @@ export function PlayerBar() {
         return `${m.toString().padStart(2, '0')}:${s.toString().padStart(2, '0')} `;
     };
 
-    // Sleep Timer Countdown Effect
+    // The sleep timer runs in the backend, which can stop at the end of a track or work
     useEffect(() => {
-        if (sleepTimerSeconds === null || sleepTimerSeconds <= 0) return;
-
-        const interval = setInterval(() => {
-            setSleepTimerSeconds(prev => {
-                if (prev === null || prev <= 1) {
-                    // Timer finished - stop playback
-                    stopAllPlayback();
-                    setIsPlaying(false);
-                    return null;
-                }
-                return prev - 1;
-            });
-        }, 1000);
-
-        return () => clearInterval(interval);
-    }, [sleepTimerSeconds, stopAllPlayback, setIsPlaying]);
-
-    const setSleepTimer = (minutes: number | null) => {
-        if (minutes === null) {
-            setSleepTimerSeconds(null);
-        } else {
-            setSleepTimerSeconds(minutes * 60);
-        }
+        invoke<SleepTimerStatus | null>('get_sleep_timer').then(setSleepTimerStatus).catch(console.error);
+        const unlisteners = [
+            listen<SleepTimerStatus>('sleep-timer-tick', (event) => setSleepTimerStatus(event.payload)),
+            listen('sleep-timer-fired', () => setSleepTimerStatus(null)),
+        ];
+        return () => {
+            unlisteners.forEach(unlisten => unlisten.then(f => f()));
+        };
+    }, []);
+
+    const setSleepTimer = async (mode: SleepTimerMode | null) => {
         setShowSleepMenu(false);
+        try {
+            if (mode === null) {
+                await invoke('cancel_sleep_timer');
+                setSleepTimerStatus(null);
+            } else {
+                setSleepTimerStatus(await invoke<SleepTimerStatus>('set_sleep_timer', { mode }));
+            }
+        } catch (e) {
+            console.error('Failed to set sleep timer:', e);
+        }
     };
 
-    const formatSleepTime = (sec: number) => {
+    const formatSleepTime = (timer: SleepTimerStatus) => {
+        if (timer.mode.kind === 'end_of_track') return '曲末';
+        if (timer.mode.kind === 'end_of_work') return '作品末';
+        const sec = Math.ceil(timer.remaining_secs ?? 0);
         const m = Math.floor(sec / 60);
         const s = sec % 60;
         return `${m}:${s.toString().padStart(2, '0')}`;
     };
 
-    const progressPercent = duration > 0 ? (currentTime / duration) * 100 : 0;
+    const shownTime = seekTarget ?? currentTime;
+    const progressPercent = duration > 0 ? (shownTime / duration) * 100 : 0;
 
     return (
         <footer className="h-20 bg-white border-t border-card-border flex items-center justify-between px-6 z-50 relative shadow-[0_-4px_20px_rgba(0,0,0,0.08)] shrink-0">
@@ -382,9 +155,6 @@ export function PlayerBar() {
                         <div className="flex flex-col overflow-hidden">
                             <span className="text-sm font-bold text-text-primary truncate cursor-pointer hover:underline decoration-accent">{currentTrack.title}</span>
                             <span className="text-xs text-text-muted truncate mt-0.5">{currentTrack.work_title || "作品名未設定"}</span>
-                            {playbackMode === 'web' && (
-                                <span className="text-[10px] text-accent">🌐 Web Audio</span>
-                            )}
                         </div>
                     </>
                 )}
@@ -393,9 +163,9 @@ export function PlayerBar() {
             <div className="flex flex-col items-center flex-1 min-w-0 px-4 max-w-3xl w-full z-10">
                 <div className="flex items-center gap-6 mb-2">
                     <button
-                        className={`transition ${shuffle ? 'text-accent' : 'text-text-muted hover:text-text-primary'}`}
+                        className={`transition ${shuffle !== 'off' ? 'text-accent' : 'text-text-muted hover:text-text-primary'}`}
                         onClick={toggleShuffle}
-                        title={shuffle ? 'シャッフル: ON' : 'シャッフル: OFF'}
+                        title={`シャッフル: ${shuffle === 'off' ? 'OFF' : shuffle === 'tracks' ? '曲' : '作品'}`}
                     >
                         <Shuffle className="w-4 h-4" />
                     </button>
@@ -410,21 +180,21 @@ export function PlayerBar() {
                     <button
                         className={`transition ${repeatMode !== 'off' ? 'text-accent' : 'text-text-muted hover:text-text-primary'}`}
                         onClick={cycleRepeatMode}
-                        title={`リピート: ${repeatMode === 'off' ? 'OFF' : repeatMode === 'all' ? '全曲' : '1曲'}`}
+                        title={`リピート: ${repeatMode === 'off' ? 'OFF' : repeatMode === 'playlist' ? '全曲' : repeatMode === 'work' ? '作品' : '1曲'}`}
                     >
                         {repeatMode === 'one' ? <Repeat1 className="w-4 h-4" /> : <Repeat className="w-4 h-4" />}
                     </button>
                 </div>
 
                 <div className="w-full flex items-center gap-3 text-xs font-mono text-text-muted">
-                    <span className="min-w-[40px] text-right text-text-primary">{formatTime(currentTime)}</span>
+                    <span className="min-w-[40px] text-right text-text-primary">{formatTime(shownTime)}</span>
 
                     <div className="relative flex-1 h-4 flex items-center group">
                         <input
                             type="range"
                             min="0"
                             max={duration || 1}
-                            value={currentTime}
+                            value={shownTime}
                             onChange={handleSeek}
                             onMouseUp={handleSeekCommit}
                             onTouchEnd={handleSeekCommit}
@@ -450,13 +220,13 @@ export function PlayerBar() {
                 <div className="relative">
                     <button
                         onClick={() => setShowSleepMenu(!showSleepMenu)}
-                        className={`p-2 rounded-full transition-colors ${sleepTimerSeconds ? 'bg-accent text-white' : 'text-text-muted hover:text-accent hover:bg-gray-100'}`}
+                        className={`p-2 rounded-full transition-colors ${sleepTimer ? 'bg-accent text-white' : 'text-text-muted hover:text-accent hover:bg-gray-100'}`}
                         title="スリープタイマー"
                     >
                         <Moon className="w-5 h-5" />
-                        {sleepTimerSeconds && (
+                        {sleepTimer && (
                             <span className="absolute -top-1 -right-1 text-[10px] bg-accent text-white px-1 rounded-full">
-                                {formatSleepTime(sleepTimerSeconds)}
+                                {formatSleepTime(sleepTimer)}
                             </span>
                         )}
                     </button>
@@ -473,13 +243,25 @@ export function PlayerBar() {
                             ].map(opt => (
                                 <button
                                     key={opt.value}
-                                    onClick={() => setSleepTimer(opt.value)}
+                                    onClick={() => setSleepTimer({ kind: 'duration', seconds: opt.value * 60 })}
                                     className="w-full px-3 py-2 text-left text-sm hover:bg-gray-100 transition-colors"
                                 >
                                     {opt.label}
                                 </button>
                             ))}
-                            {sleepTimerSeconds && (
+                            <button
+                                onClick={() => setSleepTimer({ kind: 'end_of_track' })}
+                                className="w-full px-3 py-2 text-left text-sm hover:bg-gray-100 transition-colors border-t border-gray-100"
+                            >
+                                この曲の終わり
+                            </button>
+                            <button
+                                onClick={() => setSleepTimer({ kind: 'end_of_work' })}
+                                className="w-full px-3 py-2 text-left text-sm hover:bg-gray-100 transition-colors"
+                            >
+                                この作品の終わり
+                            </button>
+                            {sleepTimer && (
                                 <button
                                     onClick={() => setSleepTimer(null)}
                                     className="w-full px-3 py-2 text-left text-sm text-red-500 hover:bg-red-50 transition-colors border-t border-gray-100"
@@ -27,7 +27,7 @@ interface PlaylistPageProps {
 export function PlaylistPage({ playlist }: PlaylistPageProps) {
     const [tracks, setTracks] = useState<PlaylistTrack[]>([]);
     const [loading, setLoading] = useState(true);
-    const { setTrack, setQueue, currentTrack, isPlaying } = usePlayerStore();
+    const { playTracks, currentTrack, isPlaying } = usePlayerStore();
 
     useEffect(() => {
         loadTracks();
@@ -57,34 +57,23 @@ export function PlaylistPage({ playlist }: PlaylistPageProps) {
         }
     };
 
+    const queueTracks = () => tracks.map(t => ({
+        id: t.id,
+        work_id: t.work_id,
+        title: t.title,
+        path: t.path,
+        duration: t.duration_sec || 0,
+        work_title: t.work_title,
+        cover_path: t.cover_path || undefined
+    }));
+
     const handlePlayAll = () => {
         if (tracks.length === 0) return;
-
-        const mappedTracks = tracks.map(t => ({
-            id: t.id,
-            title: t.title,
-            path: t.path,
-            duration: t.duration_sec || 0,
-            work_title: t.work_title,
-            cover_path: t.cover_path || undefined
-        }));
-
-        setQueue(mappedTracks);
-        setTrack(mappedTracks[0]);
+        playTracks(queueTracks(), 0).catch(console.error);
     };
 
     const handlePlayTrack = (track: PlaylistTrack) => {
-        const mappedTracks = tracks.map(t => ({
-            id: t.id,
-            title: t.title,
-            path: t.path,
-            duration: t.duration_sec || 0,
-            work_title: t.work_title,
-            cover_path: t.cover_path || undefined
-        }));
-
-        setQueue(mappedTracks);
-        setTrack(mappedTracks.find(t => t.id === track.id)!);
+        playTracks(queueTracks(), tracks.findIndex(t => t.id === track.id)).catch(console.error);
     };
 
     const formatTime = (sec: number | null) => {
@@ -37,7 +37,7 @@ export function Sidebar({ currentPage, onPageChange, onPlaylistSelect, selectedP
 
     useEffect(() => {
         const unlisten = listen('scan-progress', (event) => {
-            setScanCount(event.payload as number);
+            setScanCount((event.payload as { works_done: number }).works_done);
             setScanning(true);
         });
 
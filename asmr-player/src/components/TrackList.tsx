@@ -2,19 +2,11 @@ import { useState } from 'react';
 import { usePlayerStore } from '../hooks/usePlayerStore';
 import { Play, ListPlus } from 'lucide-react';
 import { TrackPlaylistModal } from './TrackPlaylistModal';
-
-interface Track {
-    id: number;
-    title: string;
-    path: string;
-    duration?: number;
-    work_title?: string;
-    cover_path?: string;
-}
+import type { QueueEntry } from '../hooks/usePlayerStore';
 
 export function TrackList() {
-    const { queue, currentTrack, setTrack, isPlaying } = usePlayerStore();
-    const [playlistTrack, setPlaylistTrack] = useState<Track | null>(null);
+    const { queue, currentIndex, jumpTo, isPlaying } = usePlayerStore();
+    const [playlistTrack, setPlaylistTrack] = useState<QueueEntry | null>(null);
 
     if (queue.length === 0) {
         return (
@@ -39,11 +31,11 @@ export function TrackList() {
 
             <div className="flex-1 overflow-y-auto overflow-x-hidden p-2">
                 {queue.map((track, index) => {
-                    const isActive = currentTrack?.id === track.id;
+                    const isActive = currentIndex === index;
 
                     return (
                         <div
-                            key={track.id}
+                            key={track.entry_id}
                             className={`
                                 group flex items-center gap-3 px-3 py-2.5 rounded-lg cursor-pointer transition-all mb-1
                                 ${isActive ? 'bg-accent/10' : 'hover:bg-bg-hover'}
@@ -51,7 +43,7 @@ export function TrackList() {
                         >
                             <div
                                 className="w-6 flex justify-center text-xs text-text-muted font-mono"
-                                onClick={() => setTrack(track)}
+                                onClick={() => jumpTo(index).catch(console.error)}
                             >
                                 {isActive && isPlaying ? (
                                     <div className="w-3 h-3 bg-accent rounded-full animate-pulse"></div>
@@ -63,7 +55,7 @@ export function TrackList() {
 
                             <div
                                 className="flex-1 min-w-0 flex flex-col"
-                                onClick={() => setTrack(track)}
+                                onClick={() => jumpTo(index).catch(console.error)}
                             >
                                 <span className={`text-sm truncate font-medium ${isActive ? 'text-accent' : 'text-text-primary group-hover:text-accent'}`}>
                                     {track.title}
@@ -27,7 +27,7 @@ function formatDuration(seconds: number): string {
 export function WorkDetailModal({ work, isOpen, onClose }: WorkDetailModalProps) {
     const [tracks, setTracks] = useState<Track[]>([]);
     const [loading, setLoading] = useState(true);
-    const { setTrack, setQueue, currentTrack, isPlaying } = usePlayerStore();
+    const { playTracks, currentTrack, isPlaying } = usePlayerStore();
 
     useEffect(() => {
         if (isOpen && work) {
@@ -54,6 +54,7 @@ export function WorkDetailModal({ work, isOpen, onClose }: WorkDetailModalProps)
         // Build the queue with all tracks
         const queueTracks = tracks.map(t => ({
             id: t.id,
+            work_id: work.id,
             title: t.title,
             path: t.path,
             duration: t.duration,
@@ -61,16 +62,7 @@ export function WorkDetailModal({ work, isOpen, onClose }: WorkDetailModalProps)
             cover_path: work.cover_path || undefined,
         }));
 
-        // Set queue first, then set the specific track
-        setQueue(queueTracks);
-        setTrack({
-            id: track.id,
-            title: track.title,
-            path: track.path,
-            duration: track.duration,
-            work_title: work.title,
-            cover_path: work.cover_path || undefined,
-        });
+        await playTracks(queueTracks, tracks.findIndex(t => t.id === track.id)).catch(console.error);
     };
 
     const handlePlayAll = () => {
@@ -30,7 +30,7 @@ export function WorkGrid({
     onClearFilters
 }: WorkGridProps) {
     const { works, loading, refetch } = useLibrary();
-    const { setTrack, setQueue } = usePlayerStore();
+    const { playTracks } = usePlayerStore();
     const [editingWork, setEditingWork] = useState<Work | null>(null);
     const [selectedWork, setSelectedWork] = useState<Work | null>(null);
     const [favorites, setFavorites] = useState<Set<number>>(new Set());
@@ -133,6 +133,7 @@ export function WorkGrid({
             if (tracks && tracks.length > 0) {
                 const mappedTracks = tracks.map(t => ({
                     id: t.id,
+                    work_id: work.id,
                     title: t.title,
                     path: t.path,
                     duration: t.duration || 0,
@@ -140,8 +141,7 @@ export function WorkGrid({
                     cover_path: work.cover_path || undefined
                 }));
 
-                setQueue(mappedTracks);
-                setTrack(mappedTracks[0]);
+                await playTracks(mappedTracks, 0);
 
                 // Add to history
                 await invoke('add_to_history', { workId: work.id, trackId: tracks[0].id }).catch(console.error);
@@ -1,11 +1,10 @@
 import { useEffect } from 'react';
 import { usePlayerStore } from './usePlayerStore';
-import { invoke } from '@tauri-apps/api/core';
 
 export function useKeyboardShortcuts() {
     const {
-        isPlaying, setIsPlaying, currentTrack, playNext, playPrev,
-        toggleShuffle, cycleRepeatMode
+        currentTrack, volume, togglePlay, seek, setVolume,
+        playNext, playPrev, toggleShuffle, cycleRepeatMode
     } = usePlayerStore();
 
     useEffect(() => {
@@ -21,86 +20,66 @@ export function useKeyboardShortcuts() {
             switch (e.code) {
                 case 'Space':
                     e.preventDefault();
-                    if (currentTrack) {
-                        if (isPlaying) {
-                            try { await invoke('pause_track'); } catch { }
-                        } else {
-                            try { await invoke('resume_track'); } catch { }
-                        }
-                        setIsPlaying(!isPlaying);
-                    }
+                    try { await togglePlay(); } catch { }
                     break;
 
                 case 'ArrowRight':
                     if (e.ctrlKey || e.metaKey) {
                         e.preventDefault();
-                        playNext();
+                        try { await playNext(); } catch { }
                     } else if (currentTrack) {
                         e.preventDefault();
                         // Seek forward 10 seconds
-                        try { await invoke('seek_track', { seconds: 10 }); } catch { }
+                        try { await seek(usePlayerStore.getState().currentTime + 10); } catch { }
                     }
                     break;
 
                 case 'ArrowLeft':
                     if (e.ctrlKey || e.metaKey) {
                         e.preventDefault();
-                        playPrev();
+                        try { await playPrev(); } catch { }
                     } else if (currentTrack) {
                         e.preventDefault();
                         // Seek backward 10 seconds
-                        try { await invoke('seek_track', { seconds: -10 }); } catch { }
+                        try { await seek(usePlayerStore.getState().currentTime - 10); } catch { }
                     }
                     break;
 
                 case 'ArrowUp':
                     e.preventDefault();
                     // Volume up
-                    try {
-                        const current = await invoke<number>('get_volume') || 1.0;
-                        await invoke('set_volume', { volume: Math.min(1.0, current + 0.1) });
-                    } catch { }
+                    try { await setVolume(Math.min(1.0, volume + 0.1)); } catch { }
                     break;
 
                 case 'ArrowDown':
                     e.preventDefault();
                     // Volume down
-                    try {
-                        const current = await invoke<number>('get_volume') || 1.0;
-                        await invoke('set_volume', { volume: Math.max(0, current - 0.1) });
-                    } catch { }
+                    try { await setVolume(Math.max(0, volume - 0.1)); } catch { }
                     break;
 
                 case 'KeyS':
                     if (!e.ctrlKey && !e.metaKey) {
                         e.preventDefault();
-                        toggleShuffle();
+                        try { await toggleShuffle(); } catch { }
                     }
                     break;
 
                 case 'KeyR':
                     if (!e.ctrlKey && !e.metaKey) {
                         e.preventDefault();
-                        cycleRepeatMode();
+                        try { await cycleRepeatMode(); } catch { }
                     }
                     break;
 
                 case 'KeyM':
                     e.preventDefault();
                     // Mute toggle (set volume to 0 or restore)
-                    try {
-                        const current = await invoke<number>('get_volume') || 1.0;
-                        if (current > 0) {
-                            await invoke('set_volume', { volume: 0 });
-                        } else {
-                            await invoke('set_volume', { volume: 1.0 });
-                        }
-                    } catch { }
+                    try { await setVolume(volume > 0 ? 0 : 1.0); } catch { }
                     break;
             }
         };
 
         window.addEventListener('keydown', handleKeyDown);
         return () => window.removeEventListener('keydown', handleKeyDown);
-    }, [isPlaying, currentTrack, setIsPlaying, playNext, playPrev, toggleShuffle, cycleRepeatMode]);
+    }, [currentTrack, volume, togglePlay, seek, setVolume, playNext, playPrev, toggleShuffle, cycleRepeatMode]);
 }
@@ -1,110 +1,215 @@
 import { create } from 'zustand';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
-interface Track {
+export interface Track {
     id: number;
     title: string;
     path: string;
     duration: number;
-    work_title?: string;
-    cover_path?: string;
-    work_id?: number;
+    work_title?: string | null;
+    cover_path?: string | null;
+    work_id?: number | null;
 }
 
-export type RepeatMode = 'off' | 'all' | 'one';
+// A slot in the backend's queue; the same track may be queued twice
+export interface QueueEntry extends Track {
+    entry_id: number;
+}
+
+export type RepeatMode = 'off' | 'one' | 'work' | 'playlist';
+export type ShuffleMode = 'off' | 'tracks' | 'works';
+type PlaybackState = 'playing' | 'paused' | 'stopped' | 'buffering';
+
+interface QueueSnapshot {
+    entries: QueueEntry[];
+    current_index: number | null;
+}
+
+interface PlaybackPosition {
+    entry_id: number | null;
+    position: number;
+    duration: number | null;
+}
+
+interface PlaybackStatus {
+    state: PlaybackState;
+    current_index: number | null;
+    entry: QueueEntry | null;
+    position: PlaybackPosition;
+    volume: number;
+}
+
+interface PlaybackMode {
+    repeat: RepeatMode;
+    shuffle: ShuffleMode;
+}
 
 interface PlayerState {
     isPlaying: boolean;
-    currentTrack: Track | null;
-    queue: Track[];
+    currentTrack: QueueEntry | null;
+    currentIndex: number | null;
+    queue: QueueEntry[];
     volume: number;
     currentTime: number;
     duration: number;
-    shuffle: boolean;
+    shuffle: ShuffleMode;
     repeatMode: RepeatMode;
 
-    setIsPlaying: (isPlaying: boolean) => void;
-    setTrack: (track: Track) => void;
-    setQueue: (tracks: Track[]) => void;
-    setCurrentTime: (time: number) => void;
-    setDuration: (duration: number) => void;
-    toggleShuffle: () => void;
-    cycleRepeatMode: () => void;
-    playNext: () => void;
-    playPrev: () => void;
+    // Everything below asks the backend; the state above follows its events
+    playTracks: (tracks: Track[], startIndex: number) => Promise<void>;
+    enqueue: (tracks: Track[]) => Promise<void>;
+    jumpTo: (index: number) => Promise<void>;
+    togglePlay: () => Promise<void>;
+    seek: (seconds: number) => Promise<void>;
+    setVolume: (volume: number) => Promise<void>;
+    toggleShuffle: () => Promise<void>;
+    cycleRepeatMode: () => Promise<void>;
+    playNext: () => Promise<void>;
+    playPrev: () => Promise<void>;
 }
 
+const isActive = (state: PlaybackState) => state === 'playing' || state === 'buffering';
+
+// The shape the queue commands take, without the frontend-only fields
+const toQueueTrack = (t: Track) => ({
+    id: t.id,
+    work_id: t.work_id ?? null,
+    title: t.title,
+    path: t.path,
+    duration: t.duration || 0,
+    work_title: t.work_title ?? null,
+    cover_path: t.cover_path ?? null,
+});
+
 export const usePlayerStore = create<PlayerState>((set, get) => ({
     isPlaying: false,
     currentTrack: null,
+    currentIndex: null,
     queue: [],
     volume: 1.0,
     currentTime: 0,
     duration: 0,
-    shuffle: false,
+    shuffle: 'off',
     repeatMode: 'off',
 
-    setIsPlaying: (isPlaying) => set({ isPlaying }),
-    setTrack: (track) => set({ currentTrack: track, isPlaying: true }),
-    setQueue: (queue) => set({ queue }),
-    setCurrentTime: (currentTime) => set({ currentTime }),
-    setDuration: (duration) => set({ duration }),
-
-    toggleShuffle: () => set(state => ({ shuffle: !state.shuffle })),
+    playTracks: async (tracks, startIndex) => {
+        await invoke('queue_replace', { tracks: tracks.map(toQueueTrack), startIndex });
+    },
 
-    cycleRepeatMode: () => set(state => {
-        const modes: RepeatMode[] = ['off', 'all', 'one'];
-        const currentIndex = modes.indexOf(state.repeatMode);
-        const nextIndex = (currentIndex + 1) % modes.length;
-        return { repeatMode: modes[nextIndex] };
-    }),
+    enqueue: async (tracks) => {
+        await invoke('queue_enqueue', { tracks: tracks.map(toQueueTrack) });
+    },
 
-    playNext: () => {
-        const { queue, currentTrack, shuffle, repeatMode } = get();
-        if (!currentTrack || queue.length === 0) return;
+    jumpTo: async (index) => {
+        await invoke('queue_jump', { index });
+    },
 
-        const currentIndex = queue.findIndex(t => t.id === currentTrack.id);
+    togglePlay: async () => {
+        const { isPlaying, currentTrack } = get();
+        if (!currentTrack) return;
+        await invoke(isPlaying ? 'pause_track' : 'resume_track');
+    },
 
-        if (repeatMode === 'one') {
-            // Repeat current track
-            set({ currentTrack: { ...currentTrack }, isPlaying: true });
-            return;
-        }
+    seek: async (seconds) => {
+        const { duration } = get();
+        const target = Math.max(0, duration > 0 ? Math.min(seconds, duration) : seconds);
+        await invoke('seek_track', { seconds: target });
+        set({ currentTime: target });
+    },
 
-        let nextIndex: number;
-        if (shuffle) {
-            // Random track (excluding current)
-            const otherIndices = queue.map((_, i) => i).filter(i => i !== currentIndex);
-            if (otherIndices.length === 0) return;
-            nextIndex = otherIndices[Math.floor(Math.random() * otherIndices.length)];
-        } else {
-            nextIndex = currentIndex + 1;
-        }
+    setVolume: async (volume) => {
+        set({ volume });
+        await invoke('set_volume', { volume });
+    },
 
-        if (nextIndex >= queue.length) {
-            if (repeatMode === 'all') {
-                nextIndex = 0;
-            } else {
-                return; // End of queue
-            }
-        }
+    toggleShuffle: async () => {
+        const modes: ShuffleMode[] = ['off', 'tracks', 'works'];
+        const next = modes[(modes.indexOf(get().shuffle) + 1) % modes.length];
+        await invoke('set_shuffle_mode', { mode: next });
+    },
 
-        set({ currentTrack: queue[nextIndex], isPlaying: true });
+    cycleRepeatMode: async () => {
+        const modes: RepeatMode[] = ['off', 'playlist', 'work', 'one'];
+        const next = modes[(modes.indexOf(get().repeatMode) + 1) % modes.length];
+        await invoke('set_repeat_mode', { mode: next });
     },
 
-    playPrev: () => {
-        const { queue, currentTrack, currentTime } = get();
-        if (!currentTrack) return;
+    playNext: async () => {
+        await invoke('queue_next');
+    },
 
+    playPrev: async () => {
         // If more than 3 seconds in, restart current track
-        if (currentTime > 3) {
-            set({ currentTrack: { ...currentTrack }, isPlaying: true });
+        if (get().currentTime > 3) {
+            await get().seek(0);
             return;
         }
-
-        const index = queue.findIndex(t => t.id === currentTrack.id);
-        if (index > 0) {
-            set({ currentTrack: queue[index - 1], isPlaying: true });
-        }
-    }
+        await invoke('queue_previous');
+    },
 }));
 
+// Mirror the backend's queue and playback state into the store. Returns the unsubscriber.
+export function syncPlayerStore(): () => void {
+    const { setState } = usePlayerStore;
+
+    const applyQueue = (snapshot: QueueSnapshot) => {
+        const current = snapshot.current_index !== null ? snapshot.entries[snapshot.current_index] : null;
+        setState(state => ({
+            queue: snapshot.entries,
+            currentIndex: snapshot.current_index,
+            currentTrack: current ?? null,
+            // Keep the reported duration while the same entry stays current
+            duration: current && current.entry_id === state.currentTrack?.entry_id ? state.duration : current?.duration ?? 0,
+        }));
+    };
+
+    const unlisteners = [
+        listen<QueueSnapshot>('queue-changed', (event) => applyQueue(event.payload)),
+        listen<{ index: number; entry: QueueEntry }>('track-changed', (event) => {
+            setState({
+                currentIndex: event.payload.index,
+                currentTrack: event.payload.entry,
+                currentTime: 0,
+                duration: event.payload.entry.duration || 0,
+            });
+        }),
+        listen<PlaybackState>('playback-state-changed', (event) => {
+            setState({ isPlaying: isActive(event.payload) });
+            if (event.payload === 'stopped') {
+                setState({ currentTime: 0 });
+            }
+        }),
+        listen<PlaybackPosition>('playback-position', (event) => {
+            const { entry_id, position, duration } = event.payload;
+            if (entry_id !== usePlayerStore.getState().currentTrack?.entry_id) return;
+            setState(state => ({ currentTime: position, duration: duration ?? state.duration }));
+        }),
+        listen<PlaybackMode>('playback-mode-changed', (event) => {
+            setState({ repeatMode: event.payload.repeat, shuffle: event.payload.shuffle });
+        }),
+        listen<{ path: string; error: string }>('track-error', (event) => {
+            console.error('[Audio] Failed to play:', event.payload.path, event.payload.error);
+        }),
+    ];
+
+    // Pick up whatever the backend was doing before this window loaded
+    invoke<QueueSnapshot>('get_queue').then(applyQueue).catch(console.error);
+    invoke<PlaybackStatus>('get_playback_state')
+        .then(status => {
+            setState(state => ({
+                isPlaying: isActive(status.state),
+                volume: status.volume,
+                currentTime: status.position.position,
+                duration: status.position.duration ?? state.duration,
+            }));
+        })
+        .catch(console.error);
+    invoke<PlaybackMode>('get_playback_mode')
+        .then(mode => setState({ repeatMode: mode.repeat, shuffle: mode.shuffle }))
+        .catch(console.error);
+
+    return () => {
+        unlisteners.forEach(unlisten => unlisten.then(f => f()));
+    };
+}